@@ -0,0 +1,90 @@
+#![cfg(feature = "deferred_coalescing")]
+#![feature(test)]
+
+extern crate ralloc;
+extern crate test;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+use ralloc::CoalescePolicy;
+
+/// Allocate and free a bunch of buffers of churning sizes.
+fn churn() {
+    let mut bufs = [0 as *mut u8; 16];
+
+    for (i, buf) in bufs.iter_mut().enumerate() {
+        *buf = ralloc::alloc(16 + 8 * i, 8);
+    }
+    for (i, &buf) in bufs.iter().enumerate() {
+        unsafe {
+            ralloc::free(buf, 16 + 8 * i);
+        }
+    }
+}
+
+#[bench]
+fn bench_coalesce_eager(b: &mut test::Bencher) {
+    ralloc::set_coalesce_policy(CoalescePolicy::Eager);
+    b.iter(churn);
+}
+
+#[bench]
+fn bench_coalesce_deferred(b: &mut test::Bencher) {
+    ralloc::set_coalesce_policy(CoalescePolicy::Deferred);
+    b.iter(churn);
+    ralloc::set_coalesce_policy(CoalescePolicy::Eager);
+}
+
+/// Fragment the pool, by freeing every other of a bunch of buffers.
+///
+/// The buffers kept are returned, to be freed after the benchmark.
+fn fragment() -> Vec<*mut u8> {
+    let bufs: Vec<_> = (0..1024).map(|_| ralloc::alloc(32, 8)).collect();
+    let mut kept = Vec::with_capacity(bufs.len() / 2);
+
+    for (i, &buf) in bufs.iter().enumerate() {
+        if i % 2 == 0 {
+            unsafe {
+                ralloc::free(buf, 32);
+            }
+        } else {
+            kept.push(buf);
+        }
+    }
+
+    kept
+}
+
+/// Allocate and free a buffer from a fragmented pool, measuring the latency of freeing.
+///
+/// Freeing eagerly searches the pool and merges the block with its neighbors, while deferring it
+/// keeps the block aside.
+fn bench_free_latency(b: &mut test::Bencher, policy: CoalescePolicy) {
+    let kept = fragment();
+
+    ralloc::set_coalesce_policy(policy);
+    b.iter(|| {
+        let buf = ralloc::alloc(24, 8);
+        unsafe {
+            ralloc::free(test::black_box(buf), 24);
+        }
+    });
+    ralloc::set_coalesce_policy(CoalescePolicy::Eager);
+
+    for buf in kept {
+        unsafe {
+            ralloc::free(buf, 32);
+        }
+    }
+}
+
+#[bench]
+fn bench_free_latency_eager(b: &mut test::Bencher) {
+    bench_free_latency(b, CoalescePolicy::Eager);
+}
+
+#[bench]
+fn bench_free_latency_deferred(b: &mut test::Bencher) {
+    bench_free_latency(b, CoalescePolicy::Deferred);
+}
@@ -11,6 +11,60 @@ use core::{intrinsics, cmp};
 pub const OS_MEMTRIM_LIMIT: usize = 200000000;
 /// Minimum size before a block is worthy to memtrim.
 pub const OS_MEMTRIM_WORTHY: usize = 4000;
+/// The number of free bytes a global allocator aims to keep at most.
+///
+/// Whenever this is exceeded, the allocator will release as much memory to the system as it can,
+/// regardless of the size of the individual blocks. This is best-effort: Only the top block of
+/// the pool can be released, if it lies next to the program break. Free memory in the interior of
+/// the pool is only given back with the `madvise` feature (the blocks stay in the pool), and kept
+/// otherwise. The limit applies to every global shard on its own.
+pub const MAX_POOL_FREE_BYTES: usize = 1 << 26;
+/// The ceiling of unused entries in the block pool.
+///
+/// The block pool grows with the number of free blocks, but never shrinks by itself. Whenever its
+/// unused capacity exceeds this (on top of the headroom doubling the pool leaves), the excess is
+/// reclaimed and freed. This bounds the metadata memory left behind by a spike in the number of
+/// free blocks.
+pub const MAX_POOL_SLACK: usize = 4096;
+
+/// The minimum alignment of allocations.
+///
+/// Every requested alignment is raised to (a multiple of) this, since C code expects `malloc` to
+/// return memory aligned for any type (`max_align_t`). Memory-tight programs, which only request
+/// the alignment they need, can set this to 1.
+pub const MIN_ALIGN: usize = 16;
+
+/// The page size.
+///
+/// Only page-aligned memory can be given back to the OS through `madvise`.
+pub const PAGE_SIZE: usize = 4096;
+
+/// The number of idle ticks before idle trimming.
+///
+/// When idle trimming is enabled, the allocator is trimmed after this many consecutive calls to
+/// `tick` without any allocation in between.
+pub const IDLE_TRIM_TICKS: usize = 4;
+
+/// The number of global allocator shards.
+///
+/// Threads are spread over these to reduce contention of the global allocator lock.
+pub const SHARDS: usize = 4;
+
+/// A strategy for picking the free block to allocate from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FitStrategy {
+    /// Take the first (lowest addressed) block which fits.
+    ///
+    /// This is fast, but can cause fragmentation under mixed-size workloads.
+    FirstFit,
+    /// Take the smallest block which fits.
+    ///
+    /// This scans the whole block pool, but leaves bigger blocks intact for bigger allocations.
+    BestFit,
+}
+
+/// The strategy used for picking the free block to allocate from.
+pub const FIT_STRATEGY: FitStrategy = FitStrategy::FirstFit;
 
 /// The fragmentation scale constant.
 ///
@@ -26,6 +80,100 @@ pub const LOCAL_MEMTRIM_LIMIT: usize = 16384;
 /// than this value.
 pub const LOCAL_MEMTRIM_STOP: usize = 1024;
 
+/// The worst-case number of blocks pushed to the block pool while reserving.
+///
+/// This is the number of elements needed to conduct a `reserve` without the stack overflowing:
+///
+/// 1. Aligner.
+/// 2. Excessive space.
+/// 3. The old buffer.
+///
+/// Any change to the push logic of the bookkeeper must update this.
+pub const RESERVE_PUSHES: usize = 3;
+/// Elements required _more_ than the length as capacity.
+///
+/// This must be at least `RESERVE_PUSHES` plus one (representing the pushed or inserted block),
+/// which is asserted at compile time.
+pub const EXTRA_ELEMENTS: usize = 4;
+
+/// The number of allocations the live set can track.
+///
+/// This is only used with the `track_live` feature. Allocations exceeding this will not be
+/// tracked.
+pub const LIVE_SET_CAPACITY: usize = 4096;
+
+/// The number of return addresses to capture for every live allocation.
+///
+/// This is only used with the `fp_backtrace` feature.
+pub const BACKTRACE_DEPTH: usize = 8;
+
+/// The number of size classes to keep statistics of.
+///
+/// This is only used with the `introspection` feature. Size class `n` covers the sizes up to
+/// `2^n`, and the last class additionally covers all larger sizes.
+pub const SIZE_CLASSES: usize = 16;
+
+/// The base 2 logarithm of the number of size classes per power of two in the size class cache.
+///
+/// This is only used with the `size_class_cache` feature. With `k` being this value, the sizes
+/// `2^n` through `2^(n + 1) - 1` are split into `2^k` classes of equal width (and the sizes below
+/// `2^k` get a class each), so the blocks of a class exceed the lower bound of the class by less
+/// than a `2^k`th. `0` gives power-of-two classes, whereas `2` gives classes like jemalloc's (..,
+/// 64, 80, 96, 112, 128, 160, ..).
+pub const SIZE_CLASS_CACHE_STEPS_LOG2: usize = 0;
+/// The number of size classes of the size class cache.
+///
+/// This is only used with the `size_class_cache` feature. Class `n` serves allocations of size up
+/// to its lower bound (see `SIZE_CLASS_CACHE_STEPS_LOG2`), so this caches allocations of up to 4
+/// KiB.
+pub const SIZE_CLASS_CACHE_CLASSES: usize =
+    (13 - SIZE_CLASS_CACHE_STEPS_LOG2) << SIZE_CLASS_CACHE_STEPS_LOG2;
+/// The maximum number of blocks cached per size class.
+///
+/// This is only used with the `size_class_cache` feature. Further blocks go to the block pool.
+pub const SIZE_CLASS_CACHE_LEN: usize = 32;
+
+/// The minimum size of the remainder worth splitting off an allocation.
+///
+/// When allocating through `alloc_excess`, a block whose remainder would be smaller than this is
+/// handed out whole, rather than leaving a tiny stub in the pool. Other allocations are always
+/// split exactly, since their callers free them by the requested size.
+pub const MIN_SPLIT: usize = 32;
+
+/// The maximum number of freed blocks kept aside per allocator with deferred coalescing.
+///
+/// Further frees are coalesced right away. See `CoalescePolicy::Deferred`.
+pub const DEFERRED_FREES: usize = 16;
+
+/// The minimum size of freed blocks to protect.
+///
+/// This is only used with the `protect_freed` feature. Protecting costs two system calls, so only
+/// big blocks are worth it.
+pub const PROTECT_FREED_MIN: usize = 4 * PAGE_SIZE;
+/// The number of protected freed blocks kept in quarantine.
+///
+/// This is only used with the `protect_freed` feature. When the quarantine is full, the oldest
+/// block is made accessible again and freed for real.
+pub const PROTECT_FREED_QUARANTINE: usize = 16;
+
+/// The size of the address space reserved for the emulated program break on Windows.
+///
+/// The program break can never grow beyond this.
+#[cfg(all(windows, target_pointer_width = "64"))]
+pub const WINDOWS_BRK_RESERVE: usize = 1 << 36;
+/// The size of the address space reserved for the emulated program break on Windows.
+///
+/// The program break can never grow beyond this.
+#[cfg(all(windows, not(target_pointer_width = "64")))]
+pub const WINDOWS_BRK_RESERVE: usize = 1 << 29;
+
+/// The size of the arena the program break is simulated in.
+///
+/// This is only used with the `mock_syscalls` feature. The program break can never grow beyond
+/// this.
+#[cfg(feature = "mock_syscalls")]
+pub const MOCK_ARENA_SIZE: usize = 1 << 28;
+
 /// The minimum log level.
 pub const MIN_LOG_LEVEL: u8 = 0;
 
@@ -43,7 +191,7 @@ pub fn default_oom_handler() -> ! {
 /// Write to the log.
 ///
 /// This points to stderr, but could be changed arbitrarily.
-#[cfg(not(target_os = "redox"))]
+#[cfg(not(any(target_os = "redox", windows)))]
 pub fn log(s: &str) -> usize {
     unsafe { syscall!(WRITE, 2, s.as_ptr(), s.len()) }
 }
@@ -56,6 +204,14 @@ pub fn log(s: &str) -> usize {
     ::syscall::write(2, s.as_bytes()).unwrap_or(!0)
 }
 
+/// Write to the log.
+///
+/// This points to stderr, but could be changed arbitrarily.
+#[cfg(windows)]
+pub fn log(s: &str) -> usize {
+    ::syscalls::write_stderr(s.as_bytes())
+}
+
 /// Canonicalize a fresh allocation.
 ///
 /// The return value specifies how much _more_ space is requested to the fresh allocator.
@@ -72,7 +228,7 @@ pub fn extra_fresh(size: usize) -> usize {
     /// The maximal amount of _extra_ bytes.
     const MAX_EXTRA: usize = 1024;
 
-    cmp::max(MIN_EXTRA, cmp::min(MULTIPLIER * size, MAX_EXTRA))
+    cmp::max(MIN_EXTRA, cmp::min(MULTIPLIER.saturating_mul(size), MAX_EXTRA))
 }
 
 /// Canonicalize a BRK request.
@@ -99,5 +255,5 @@ pub fn extra_brk(size: usize) -> usize {
     /// The maximal amount of _extra_ bytes.
     const MAX_EXTRA: usize = 65536;
 
-    cmp::max(MIN_EXTRA, cmp::min(MULTIPLIER * size, MAX_EXTRA))
+    cmp::max(MIN_EXTRA, cmp::min(MULTIPLIER.saturating_mul(size), MAX_EXTRA))
 }
@@ -1,6 +1,6 @@
 //! Symbols and externs that `ralloc` depends on.
 //!
-//! This crate provides implementation/import of these in Linux, BSD, and Mac OS.
+//! This crate provides implementation/import of these in Linux, BSD, Mac OS, Redox, and Windows.
 //!
 //! # Important
 //!
@@ -11,7 +11,7 @@
 #![no_std]
 #![warn(missing_docs)]
 
-#[cfg(not(target_os = "redox"))]
+#[cfg(not(any(target_os = "redox", windows)))]
 #[macro_use]
 extern crate sc;
 
@@ -1,5 +1,10 @@
 //! System calls.
 
+#[cfg(feature = "mock_syscalls")]
+pub use self::mock::*;
+#[cfg(windows)]
+pub use self::windows::*;
+
 /// Change the data segment. See `man brk`.
 ///
 /// On success, the new program break is returned. On failure, the old program break is returned.
@@ -7,17 +12,138 @@
 /// # Note
 ///
 /// This is the `brk` **syscall**, not the library function.
-#[cfg(not(target_os = "redox"))]
+#[cfg(not(any(target_os = "redox", windows, feature = "mock_syscalls")))]
 pub unsafe fn brk(ptr: *const u8) -> *const u8 {
     syscall!(BRK, ptr) as *const u8
 }
 
 /// Voluntarily give a time slice to the scheduler.
-#[cfg(not(target_os = "redox"))]
+#[cfg(not(any(target_os = "redox", windows)))]
 pub fn sched_yield() -> usize {
     unsafe { syscall!(SCHED_YIELD) }
 }
 
+/// Fill a buffer with random bytes from the OS. See `man getrandom`.
+///
+/// This never blocks: If the entropy pool is not initialized yet, it fails. The number of bytes
+/// written is returned, or a negative error code (as `usize`) on failure.
+#[cfg(not(any(target_os = "redox", windows)))]
+pub fn getrandom(buf: &mut [u8]) -> usize {
+    /// `GRND_NONBLOCK` of `getrandom`.
+    const GRND_NONBLOCK: usize = 1;
+
+    unsafe { syscall!(GETRANDOM, buf.as_mut_ptr(), buf.len(), GRND_NONBLOCK) }
+}
+
+/// Get the ID of the current thread. See `man gettid`.
+#[cfg(not(any(target_os = "redox", windows)))]
+pub fn thread_id() -> usize {
+    unsafe { syscall!(GETTID) }
+}
+
+/// Get the address of the top of the stack of the current thread, if known.
+///
+/// No frame of the current thread lies above it. The top of the stack of the main thread is
+/// `__libc_stack_end`, and the thread descriptor (i.e. `pthread_self()`) lies right above the stack
+/// of every other thread. `None` is returned if the respective symbol is not linked in.
+#[cfg(target_os = "linux")]
+pub fn stack_top() -> Option<usize> {
+    extern {
+        #[linkage = "extern_weak"]
+        static __libc_stack_end: *const usize;
+        #[linkage = "extern_weak"]
+        static pthread_self: *const u8;
+    }
+
+    unsafe {
+        if syscall!(GETPID) == syscall!(GETTID) {
+            if __libc_stack_end.is_null() {
+                None
+            } else {
+                Some(*__libc_stack_end)
+            }
+        } else if pthread_self.is_null() {
+            None
+        } else {
+            Some(::core::mem::transmute::<*const u8, extern fn() -> usize>(pthread_self)())
+        }
+    }
+}
+
+/// Get the address of the top of the stack of the current thread, if known.
+///
+/// This is not supported on this platform, so `None` is returned.
+#[cfg(not(target_os = "linux"))]
+pub fn stack_top() -> Option<usize> {
+    None
+}
+
+/// Advise the OS that some memory is not needed. See `man madvise`.
+///
+/// The physical pages are reclaimed, while the range stays mapped (reading zeros). `ptr` must be
+/// page aligned. Zero is returned on success.
+#[cfg(not(any(target_os = "redox", windows, feature = "mock_syscalls")))]
+pub unsafe fn madvise(ptr: *const u8, size: usize) -> usize {
+    /// `MADV_DONTNEED` of `madvise`.
+    const MADV_DONTNEED: usize = 4;
+
+    syscall!(MADVISE, ptr, size, MADV_DONTNEED)
+}
+
+/// Change the access protection of some memory. See `man mprotect`.
+///
+/// If `accessible` is false, any access to the range faults, otherwise it can be read and written.
+/// `ptr` must be page aligned. Zero is returned on success.
+#[cfg(not(any(target_os = "redox", windows, feature = "mock_syscalls")))]
+pub unsafe fn mprotect(ptr: *const u8, size: usize, accessible: bool) -> usize {
+    /// `PROT_NONE` of `mprotect`.
+    const PROT_NONE: usize = 0;
+    /// `PROT_READ | PROT_WRITE` of `mprotect`.
+    const PROT_READ_WRITE: usize = 3;
+
+    syscall!(MPROTECT, ptr, size, if accessible { PROT_READ_WRITE } else { PROT_NONE })
+}
+
+/// Write to a file descriptor. See `man write`.
+///
+/// The number of bytes written is returned, or a negative error code (as `usize`) on failure.
+#[cfg(not(any(target_os = "redox", windows)))]
+pub fn write(fd: usize, buf: &[u8]) -> usize {
+    unsafe { syscall!(WRITE, fd, buf.as_ptr(), buf.len()) }
+}
+
+/// Are writes to a file descriptor non-blocking? See `man fcntl`.
+///
+/// `false` is returned on failure (e.g. an invalid file descriptor).
+#[cfg(not(any(target_os = "redox", windows)))]
+pub fn is_nonblocking(fd: usize) -> bool {
+    /// `F_GETFL` of `fcntl`.
+    const F_GETFL: usize = 3;
+    /// `O_NONBLOCK` of `open`.
+    const O_NONBLOCK: usize = 0o4000;
+
+    unsafe {
+        let flags = syscall!(FCNTL, fd, F_GETFL);
+        // Error codes are in the range `-4095..0`.
+        flags <= !4095 && flags & O_NONBLOCK != 0
+    }
+}
+
+/// Get the monotonic time in nanoseconds. See `man clock_gettime`.
+#[cfg(not(any(target_os = "redox", windows)))]
+pub fn monotonic_time() -> u64 {
+    /// `CLOCK_MONOTONIC` of `clock_gettime`.
+    const CLOCK_MONOTONIC: usize = 1;
+
+    // The `timespec`, i.e. the seconds and the nanoseconds.
+    let mut time = [0usize; 2];
+    unsafe {
+        syscall!(CLOCK_GETTIME, CLOCK_MONOTONIC, time.as_mut_ptr());
+    }
+
+    time[0] as u64 * 1_000_000_000 + time[1] as u64
+}
+
 /// Change the data segment. See `man brk`.
 ///
 /// On success, the new program break is returned. On failure, the old program break is returned.
@@ -25,7 +151,7 @@ pub fn sched_yield() -> usize {
 /// # Note
 ///
 /// This is the `brk` **syscall**, not the library function.
-#[cfg(target_os = "redox")]
+#[cfg(all(target_os = "redox", not(feature = "mock_syscalls")))]
 pub unsafe fn brk(ptr: *const u8) -> *const u8 {
     let old = ::syscall::brk(0).unwrap_or(0);
     ::syscall::brk(ptr as usize).unwrap_or(old) as *const u8
@@ -36,3 +162,329 @@ pub unsafe fn brk(ptr: *const u8) -> *const u8 {
 pub fn sched_yield() -> usize {
     ::syscall::Error::mux(::syscall::sched_yield())
 }
+
+/// Fill a buffer with random bytes from the OS. See `man getrandom`.
+///
+/// The bytes are read from the `rand:` scheme. The number of bytes written is returned, or `!0` on
+/// failure.
+#[cfg(target_os = "redox")]
+pub fn getrandom(buf: &mut [u8]) -> usize {
+    let fd = match ::syscall::open("rand:", ::syscall::O_RDONLY) {
+        Ok(fd) => fd,
+        Err(_) => return !0,
+    };
+
+    let res = ::syscall::read(fd, buf).unwrap_or(!0);
+    let _ = ::syscall::close(fd);
+
+    res
+}
+
+/// Get the ID of the current thread. See `man gettid`.
+///
+/// Every thread is a context of its own on Redox, so this is the context ID.
+#[cfg(target_os = "redox")]
+pub fn thread_id() -> usize {
+    ::syscall::getpid().unwrap_or(0)
+}
+
+/// Write to a file descriptor. See `man write`.
+///
+/// The number of bytes written is returned, or `!0` on failure.
+#[cfg(target_os = "redox")]
+pub fn write(fd: usize, buf: &[u8]) -> usize {
+    ::syscall::write(fd, buf).unwrap_or(!0)
+}
+
+/// Are writes to a file descriptor non-blocking? See `man fcntl`.
+///
+/// `false` is returned on failure (e.g. an invalid file descriptor).
+#[cfg(target_os = "redox")]
+pub fn is_nonblocking(fd: usize) -> bool {
+    ::syscall::fcntl(fd, ::syscall::F_GETFL, 0)
+        .map(|flags| flags & ::syscall::O_NONBLOCK != 0)
+        .unwrap_or(false)
+}
+
+/// Get the monotonic time in nanoseconds. See `man clock_gettime`.
+#[cfg(target_os = "redox")]
+pub fn monotonic_time() -> u64 {
+    let mut time = ::syscall::TimeSpec::default();
+    let _ = ::syscall::clock_gettime(::syscall::CLOCK_MONOTONIC, &mut time);
+
+    time.tv_sec as u64 * 1_000_000_000 + time.tv_nsec as u64
+}
+
+/// Advise the OS that some memory is not needed. See `man madvise`.
+///
+/// Redox has no such system call, so this always fails (returning a non-zero value).
+#[cfg(all(target_os = "redox", not(feature = "mock_syscalls")))]
+pub unsafe fn madvise(_ptr: *const u8, _size: usize) -> usize {
+    !0
+}
+
+/// Change the access protection of some memory. See `man mprotect`.
+///
+/// This is not supported for the Redox heap, so this always fails (returning a non-zero value).
+#[cfg(all(target_os = "redox", not(feature = "mock_syscalls")))]
+pub unsafe fn mprotect(_ptr: *const u8, _size: usize, _accessible: bool) -> usize {
+    !0
+}
+
+/// Simulated system calls.
+///
+/// With the `mock_syscalls` feature, the program break is simulated inside a static arena (see
+/// `config::MOCK_ARENA_SIZE`) instead of touching the OS. This makes the allocator's interaction
+/// with the OS deterministic, e.g. OOM occurs exactly when the arena is exhausted.
+#[cfg(feature = "mock_syscalls")]
+mod mock {
+    use core::ptr;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use config;
+
+    /// The simulated address space.
+    #[repr(align(4096))]
+    struct Arena([u8; config::MOCK_ARENA_SIZE]);
+
+    /// The arena the program break is simulated in.
+    static mut ARENA: Arena = Arena([0; config::MOCK_ARENA_SIZE]);
+    /// The simulated program break, relative to the start of the arena.
+    static BRK: AtomicUsize = AtomicUsize::new(0);
+
+    /// Change the simulated data segment. See `man brk`.
+    ///
+    /// On success, the new program break is returned. On failure (i.e. if `ptr` is outside the
+    /// arena), the old program break is returned.
+    ///
+    /// # Safety
+    ///
+    /// Calls must not happen concurrently, since the simulated break is not synchronized
+    /// (`ralloc` only calls it while holding the BRK lock).
+    pub unsafe fn brk(ptr: *const u8) -> *const u8 {
+        let start = ARENA.0.as_ptr() as usize;
+        let new = ptr as usize;
+
+        if new >= start && new <= start + config::MOCK_ARENA_SIZE {
+            BRK.store(new - start, Ordering::SeqCst);
+        }
+
+        (start + BRK.load(Ordering::SeqCst)) as *const u8
+    }
+
+    /// Advise that some simulated memory is not needed. See `man madvise`.
+    ///
+    /// This simulates the pages being reclaimed by zeroing them. Zero is returned.
+    pub unsafe fn madvise(ptr: *const u8, size: usize) -> usize {
+        ptr::write_bytes(ptr as *mut u8, 0, size);
+
+        0
+    }
+
+    /// Change the access protection of some simulated memory. See `man mprotect`.
+    ///
+    /// The arena cannot be protected, so this does nothing. Zero is returned.
+    pub unsafe fn mprotect(_ptr: *const u8, _size: usize, _accessible: bool) -> usize {
+        0
+    }
+}
+
+/// System calls for Windows.
+///
+/// Windows has no program break, so it is emulated by reserving a large region of the address
+/// space (see `config::WINDOWS_BRK_RESERVE`), and committing (or decommitting) pages of it as the
+/// break is moved. Thus, the break can never grow beyond the reserved region.
+#[cfg(windows)]
+mod windows {
+    use core::ptr;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use config;
+
+    /// `MEM_COMMIT` of `VirtualAlloc`.
+    const MEM_COMMIT: u32 = 0x1000;
+    /// `MEM_RESERVE` of `VirtualAlloc`.
+    const MEM_RESERVE: u32 = 0x2000;
+    /// `MEM_DECOMMIT` of `VirtualFree`.
+    const MEM_DECOMMIT: u32 = 0x4000;
+    /// `MEM_RESET` of `VirtualAlloc`.
+    const MEM_RESET: u32 = 0x80000;
+    /// `PAGE_NOACCESS` of `VirtualAlloc`.
+    const PAGE_NOACCESS: u32 = 0x01;
+    /// `PAGE_READWRITE` of `VirtualAlloc`.
+    const PAGE_READWRITE: u32 = 0x04;
+    /// `STD_ERROR_HANDLE` of `GetStdHandle`.
+    const STD_ERROR_HANDLE: u32 = -12i32 as u32;
+
+    #[link(name = "advapi32")]
+    extern "system" {
+        #[link_name = "SystemFunction036"]
+        fn RtlGenRandom(buf: *mut u8, len: u32) -> u8;
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn VirtualAlloc(addr: *mut u8, size: usize, alloc_type: u32, protect: u32) -> *mut u8;
+        fn VirtualFree(addr: *mut u8, size: usize, free_type: u32) -> i32;
+        fn VirtualProtect(addr: *mut u8, size: usize, protect: u32, old: *mut u32) -> i32;
+        fn SwitchToThread() -> i32;
+        fn GetCurrentThreadId() -> u32;
+        fn GetStdHandle(std_handle: u32) -> *mut u8;
+        fn WriteFile(
+            file: *mut u8,
+            buf: *const u8,
+            len: u32,
+            written: *mut u32,
+            overlapped: *mut u8,
+        ) -> i32;
+    }
+
+    /// The start of the reserved region, or zero if not yet reserved.
+    static BASE: AtomicUsize = AtomicUsize::new(0);
+    /// The emulated program break.
+    static BRK: AtomicUsize = AtomicUsize::new(0);
+    /// The end of the committed part of the reserved region.
+    static COMMITTED: AtomicUsize = AtomicUsize::new(0);
+
+    /// Round up to the nearest page boundary.
+    fn page_round(addr: usize) -> usize {
+        (addr + config::PAGE_SIZE - 1) & !(config::PAGE_SIZE - 1)
+    }
+
+    /// Change the (emulated) data segment. See `man brk`.
+    ///
+    /// On success, the new program break is returned. On failure, the old program break is
+    /// returned.
+    ///
+    /// # Safety
+    ///
+    /// Calls must not happen concurrently, since the emulated break is not synchronized (`ralloc`
+    /// only calls it while holding the BRK lock).
+    #[cfg(not(feature = "mock_syscalls"))]
+    pub unsafe fn brk(ptr: *const u8) -> *const u8 {
+        // Reserve the region on first use.
+        if BASE.load(Ordering::SeqCst) == 0 {
+            let base = VirtualAlloc(
+                ptr::null_mut(),
+                config::WINDOWS_BRK_RESERVE,
+                MEM_RESERVE,
+                PAGE_NOACCESS,
+            ) as usize;
+
+            if base == 0 {
+                return ptr::null();
+            }
+
+            BASE.store(base, Ordering::SeqCst);
+            BRK.store(base, Ordering::SeqCst);
+            COMMITTED.store(base, Ordering::SeqCst);
+        }
+
+        let base = BASE.load(Ordering::SeqCst);
+        let old = BRK.load(Ordering::SeqCst);
+        let new = ptr as usize;
+
+        // Requesting the current break, or a break outside the reserved region.
+        if new < base || new > base + config::WINDOWS_BRK_RESERVE {
+            return old as *const u8;
+        }
+
+        let committed = COMMITTED.load(Ordering::SeqCst);
+        let end = page_round(new);
+        if end > committed {
+            // Commit the pages needed.
+            if VirtualAlloc(committed as *mut u8, end - committed, MEM_COMMIT, PAGE_READWRITE)
+                .is_null()
+            {
+                return old as *const u8;
+            }
+        } else if end < committed {
+            // Give the unneeded pages back to the OS.
+            VirtualFree(end as *mut u8, committed - end, MEM_DECOMMIT);
+        }
+
+        COMMITTED.store(end, Ordering::SeqCst);
+        BRK.store(new, Ordering::SeqCst);
+
+        new as *const u8
+    }
+
+    /// Advise the OS that some memory is not needed. See `man madvise`.
+    ///
+    /// The physical pages are reclaimed through `MEM_RESET`, while the range stays committed.
+    /// Zero is returned on success.
+    #[cfg(not(feature = "mock_syscalls"))]
+    pub unsafe fn madvise(ptr: *const u8, size: usize) -> usize {
+        if VirtualAlloc(ptr as *mut u8, size, MEM_RESET, PAGE_READWRITE).is_null() {
+            !0
+        } else {
+            0
+        }
+    }
+
+    /// Change the access protection of some memory. See `man mprotect`.
+    ///
+    /// If `accessible` is false, any access to the range faults, otherwise it can be read and
+    /// written. Zero is returned on success.
+    #[cfg(not(feature = "mock_syscalls"))]
+    pub unsafe fn mprotect(ptr: *const u8, size: usize, accessible: bool) -> usize {
+        let protect = if accessible { PAGE_READWRITE } else { PAGE_NOACCESS };
+        let mut old = 0;
+
+        if VirtualProtect(ptr as *mut u8, size, protect, &mut old) == 0 {
+            !0
+        } else {
+            0
+        }
+    }
+
+    /// Voluntarily give a time slice to the scheduler.
+    ///
+    /// This always succeeds, returning zero like `sched_yield` does elsewhere. `SwitchToThread`
+    /// returning zero merely means that no other thread was ready to run.
+    pub fn sched_yield() -> usize {
+        unsafe {
+            SwitchToThread();
+        }
+
+        0
+    }
+
+    /// Fill a buffer with random bytes from the OS.
+    ///
+    /// The number of bytes written is returned, or `!0` on failure.
+    pub fn getrandom(buf: &mut [u8]) -> usize {
+        if unsafe { RtlGenRandom(buf.as_mut_ptr(), buf.len() as u32) } == 0 {
+            !0
+        } else {
+            buf.len()
+        }
+    }
+
+    /// Get the ID of the current thread.
+    pub fn thread_id() -> usize {
+        unsafe { GetCurrentThreadId() as usize }
+    }
+
+    /// Write to the standard error.
+    ///
+    /// The number of bytes written is returned, or `!0` on failure.
+    pub fn write_stderr(s: &[u8]) -> usize {
+        let mut written = 0;
+
+        unsafe {
+            if WriteFile(
+                GetStdHandle(STD_ERROR_HANDLE),
+                s.as_ptr(),
+                s.len() as u32,
+                &mut written,
+                ptr::null_mut(),
+            ) == 0
+            {
+                !0
+            } else {
+                written as usize
+            }
+        }
+    }
+}
@@ -5,7 +5,7 @@
 pub use self::arch::*;
 
 /// Thread destructors for Linux/BSD.
-#[cfg(not(target_os = "macos"))]
+#[cfg(not(any(target_os = "macos", windows)))]
 pub mod arch {
     extern {
         #[linkage = "extern_weak"]
@@ -14,6 +14,14 @@ pub mod arch {
         static __cxa_thread_atexit_impl: *const u8;
     }
 
+    /// Are thread destructors supported?
+    ///
+    /// They are not, if `__cxa_thread_atexit_impl` is not linked in (e.g. in some static
+    /// binaries).
+    pub fn is_supported() -> bool {
+        unsafe { !__cxa_thread_atexit_impl.is_null() }
+    }
+
     /// Register a thread destructor.
     // TODO: Due to rust-lang/rust#18804, make sure this is not generic!
     pub fn register(t: *mut u8, dtor: unsafe extern fn(*mut u8)) {
@@ -39,8 +47,30 @@ pub mod arch {
         fn _tlv_atexit(dtor: unsafe extern fn(*mut u8), arg: *mut u8);
     }
 
+    /// Are thread destructors supported?
+    pub fn is_supported() -> bool {
+        true
+    }
+
     /// Register a thread destructor.
     pub fn register(t: *mut u8, dtor: unsafe extern fn(*mut u8)) {
         _tlv_atexit(dtor, t);
     }
 }
+
+/// Thread destructors for Windows.
+///
+/// These are not supported yet, so the destructors are never run. Consequently, the memory of the
+/// thread-local allocators is never given back upon thread exit.
+#[cfg(windows)]
+pub mod arch {
+    /// Are thread destructors supported?
+    pub fn is_supported() -> bool {
+        false
+    }
+
+    /// Register a thread destructor.
+    ///
+    /// This is a no-op.
+    pub fn register(_t: *mut u8, _dtor: unsafe extern fn(*mut u8)) {}
+}
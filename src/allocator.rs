@@ -4,31 +4,103 @@
 
 use prelude::*;
 
-use core::{mem, ops};
+use core::alloc::Layout;
+use core::sync::atomic::{self, AtomicBool, AtomicPtr, AtomicUsize};
+use core::{cmp, mem, ops, ptr};
 
 use bookkeeper::{self, Allocator, Bookkeeper};
-use {brk, sync};
+use hook::{self, AllocEvent};
+use {brk, fail, sync};
+#[cfg(feature = "libc_fallback")]
+use system;
+
+#[cfg(feature = "canary")]
+use canary;
+#[cfg(feature = "track_live")]
+use live;
+#[cfg(feature = "protect_freed")]
+use quarantine;
+#[cfg(feature = "introspection")]
+use stats;
 
 use shim::config;
+#[cfg(feature = "tls")]
+use shim::thread_destructor;
 
 #[cfg(feature = "tls")]
 use tls;
 
+/// The number of bytes reserved after every allocation for the canary.
+#[cfg(feature = "canary")]
+const CANARY_SIZE: usize = canary::SIZE;
+/// The number of bytes reserved after every allocation for the canary.
+#[cfg(not(feature = "canary"))]
+const CANARY_SIZE: usize = 0;
+
 /// Alias for the wrapper type of the thread-local variable holding the local
 /// allocator.
 #[cfg(feature = "tls")]
 type ThreadLocalAllocator =
     MoveCell<Option<LazyInit<fn() -> LocalAllocator, LocalAllocator>>>;
 
-/// The global default allocator.
+/// Alias for the type of a global allocator shard.
 // TODO: Remove these filthy function pointers.
-static GLOBAL_ALLOCATOR: sync::Mutex<
-    LazyInit<fn() -> GlobalAllocator, GlobalAllocator>,
-> = sync::Mutex::new(LazyInit::new(GlobalAllocator::init));
+type GlobalShard = sync::Mutex<LazyInit<fn() -> GlobalAllocator, GlobalAllocator>>;
+
+/// The initial state of a global allocator shard.
+const GLOBAL_SHARD_INIT: GlobalShard = sync::Mutex::new(LazyInit::new(GlobalAllocator::init));
+/// The global default allocators.
+///
+/// The global allocator is split into `config::SHARDS` independent shards, each with its own lock,
+/// to reduce lock contention. Any block can be freed to any shard.
+static GLOBAL_ALLOCATORS: [GlobalShard; config::SHARDS] = [GLOBAL_SHARD_INIT; config::SHARDS];
+/// Is idle trimming enabled?
+static IDLE_TRIM: AtomicBool = AtomicBool::new(false);
+/// The number of free bytes the global allocator is trimmed down to when idle.
+static IDLE_TRIM_THRESHOLD: AtomicUsize = AtomicUsize::new(0);
+/// Has there been any allocations since the last tick?
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+/// The number of consecutive ticks without allocations.
+static IDLE_TICKS: AtomicUsize = AtomicUsize::new(0);
+/// The fallback allocator, or null if none is set.
+static FALLBACK: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+/// The memory ordering of the statistics counters.
+///
+/// No increment is ever lost, and all of them are visible once synchronized with the incrementing
+/// threads (e.g. by joining them), whatever the ordering. `Relaxed` only leaves the order in which
+/// other threads observe increments of _different_ counters unspecified, so it is the default,
+/// keeping the hot path fast on weakly-ordered targets. The `precise_stats` feature makes them
+/// `SeqCst`, giving the increments of all counters a single total order.
+#[cfg(not(feature = "precise_stats"))]
+const STATS_ORDERING: atomic::Ordering = atomic::Ordering::Relaxed;
+/// The memory ordering of the statistics counters.
+#[cfg(feature = "precise_stats")]
+const STATS_ORDERING: atomic::Ordering = atomic::Ordering::SeqCst;
+/// The number of global allocator shards currently growing (BRK'ing).
+static GROWING: AtomicUsize = AtomicUsize::new(0);
+/// The number of times a global allocator shard grew.
+static GROWS: AtomicUsize = AtomicUsize::new(0);
+/// The number of grows avoided by taking memory from another shard.
+static AVOIDED_GROWS: AtomicUsize = AtomicUsize::new(0);
+/// The number of allocations through `GlobalAlloc` left before they fail, or `!0` if they never
+/// fail.
+#[cfg(feature = "testing")]
+static ALLOCS_LEFT: AtomicUsize = AtomicUsize::new(!0);
+/// The number of buckets of the size histogram.
+#[cfg(feature = "histogram")]
+const HISTOGRAM_BUCKETS: usize = 64;
+/// The initial state of a bucket of the size histogram.
+#[cfg(feature = "histogram")]
+const HISTOGRAM_BUCKET_INIT: AtomicUsize = AtomicUsize::new(0);
+/// The number of allocations of every power-of-two size bucket (see `size_histogram`).
+#[cfg(feature = "histogram")]
+static SIZE_HISTOGRAM: [AtomicUsize; HISTOGRAM_BUCKETS] =
+    [HISTOGRAM_BUCKET_INIT; HISTOGRAM_BUCKETS];
 #[cfg(feature = "tls")]
 tls! {
     /// The thread-local allocator.
-    static THREAD_ALLOCATOR: ThreadLocalAllocator = MoveCell::new(Some(LazyInit::new(LocalAllocator::init)));
+    static THREAD_ALLOCATOR: ThreadLocalAllocator =
+        MoveCell::new(Some(LazyInit::new(LocalAllocator::init)));
 }
 
 /// Temporarily get the allocator.
@@ -50,7 +122,26 @@ macro_rules! get_allocator {
         #[cfg(feature = "tls")]
         {
             THREAD_ALLOCATOR.with(|thread_alloc| {
-                if let Some(mut thread_alloc_original) = thread_alloc.replace(None) {
+                // Without thread destructors, the local allocator could never give its memory back
+                // upon thread exit, so it is never initialized, and the global allocator is used
+                // directly.
+                let thread_alloc_original = if thread_destructor::is_supported() {
+                    let res = thread_alloc.replace(None);
+                    if res.is_none() {
+                        // The local allocator seems to have been deinitialized, for this reason we
+                        // fallback to the global allocator.
+                        log!(
+                            WARNING,
+                            "Accessing the allocator after deinitialization of the local allocator."
+                        );
+                    }
+
+                    res
+                } else {
+                    None
+                };
+
+                if let Some(mut thread_alloc_original) = thread_alloc_original {
                     let res = {
                         // Call the closure involved.
                         let $v = thread_alloc_original.get();
@@ -62,15 +153,8 @@ macro_rules! get_allocator {
 
                     res
                 } else {
-                    // The local allocator seems to have been deinitialized, for this reason we
-                    // fallback to the global allocator.
-                    log!(
-                        WARNING,
-                        "Accessing the allocator after deinitialization of the local allocator."
-                    );
-
                     // Lock the global allocator.
-                    let mut guard = GLOBAL_ALLOCATOR.lock();
+                    let mut guard = global_allocator().lock();
 
                     // Call the block in question.
                     let $v = guard.get();
@@ -83,7 +167,7 @@ macro_rules! get_allocator {
         #[cfg(not(feature = "tls"))]
         {
             // Lock the global allocator.
-            let mut guard = GLOBAL_ALLOCATOR.lock();
+            let mut guard = global_allocator().lock();
 
             // Call the block in question.
             let $v = guard.get();
@@ -92,6 +176,30 @@ macro_rules! get_allocator {
     }};
 }
 
+/// Get the index of the global allocator shard of the current thread.
+///
+/// The shard is picked by hashing a key unique to the current thread (that is, the address of the
+/// thread-local allocator, or the thread ID when TLS is disabled), so a thread always gets the
+/// same shard.
+#[inline]
+fn shard_index() -> usize {
+    #[cfg(feature = "tls")]
+    let key = THREAD_ALLOCATOR.with(|x| x as *const ThreadLocalAllocator as usize) >> 4;
+    #[cfg(not(feature = "tls"))]
+    let key = ::shim::syscalls::thread_id();
+
+    // Fibonacci hashing. The upper bits are the best mixed ones.
+    let hash = key.wrapping_mul(0x9E37_79B9_7F4A_7C15u64 as usize);
+
+    (hash >> (mem::size_of::<usize>() * 8 - 8)) % config::SHARDS
+}
+
+/// Get the global allocator shard of the current thread.
+#[inline]
+fn global_allocator() -> &'static GlobalShard {
+    &GLOBAL_ALLOCATORS[shard_index()]
+}
+
 /// Derives `Deref` and `DerefMut` to the `inner` field.
 ///
 /// This requires importing `core::ops`.
@@ -121,6 +229,18 @@ macro_rules! derive_deref {
 struct GlobalAllocator {
     // The inner bookkeeper.
     inner: Bookkeeper,
+    // Is the pool being forcibly trimmed or memtrimmed right now?
+    //
+    // Trimming pushes the top block back if it cannot be released, which would trim again.
+    trimming: bool,
+    // Is the block returned by the last fresh allocation known to be zeroed?
+    fresh_zeroed: bool,
+    // The number of free bytes in the pool right after the last advise pass (see `advise`).
+    //
+    // Advised blocks stay in the pool, so they are discounted when checking the limits, making
+    // sure that another pass is only made once as much new free memory came in.
+    #[cfg(feature = "madvise")]
+    advised: usize,
 }
 
 impl GlobalAllocator {
@@ -130,18 +250,31 @@ impl GlobalAllocator {
         log!(NOTE, "Initializing the global allocator.");
 
         // The initial acquired segment.
+        #[cfg(not(feature = "libc_fallback"))]
         let (aligner, initial_segment, excessive) = brk::lock().canonical_brk(
             8 * bookkeeper::EXTRA_ELEMENTS * mem::size_of::<Block>(),
             mem::align_of::<Block>(),
         );
+        #[cfg(feature = "libc_fallback")]
+        let (aligner, initial_segment, excessive) = init_segment();
 
         // Initialize the new allocator.
         let mut res = GlobalAllocator {
-            inner: Bookkeeper::new(unsafe {
-                // LAST AUDIT: 2016-08-21 (Ticki).
+            inner: Bookkeeper::new(
+                unsafe {
+                    // LAST AUDIT: 2016-08-21 (Ticki).
 
-                Vec::from_raw_parts(initial_segment, 0)
-            }),
+                    Vec::from_raw_parts(initial_segment, 0)
+                },
+                "global",
+                // Shards are only ever initialized through `global_allocator`, so this is the
+                // shard being initialized.
+                shard_index(),
+            ),
+            trimming: false,
+            fresh_zeroed: false,
+            #[cfg(feature = "madvise")]
+            advised: 0,
         };
 
         // Free the secondary space.
@@ -150,16 +283,154 @@ impl GlobalAllocator {
 
         res
     }
+
+    /// Release free memory to the OS, aiming for at most `threshold` free bytes left.
+    ///
+    /// Only the top of the pool (the memory next to the program break) can be released, so this
+    /// is best-effort, and more than `threshold` bytes might be left.
+    fn trim(&mut self, threshold: usize) {
+        if self.total_bytes() <= threshold {
+            return;
+        }
+
+        // Only a top block ending at the program break can be released. Check that up front, as
+        // taking the block out of the pool is not worth it otherwise.
+        let mut brk = brk::lock();
+        if !self.top().map_or(false, |x| brk.can_release(x)) {
+            return;
+        }
+
+        /// Logging...
+        log!(NOTE, "Trimming the global allocator.");
+
+        // Only release the excess, in case the top block holds more than that.
+        let excess = self.total_bytes() - threshold;
+        let block = unborrow!(self.remove_at(self.len() - 1));
+        let (mut keep, release) = unborrow!(block.split(block.size().saturating_sub(excess)));
+
+        if let Err(mut release) = brk.release(release) {
+            // Logging...
+            log!(WARNING, "Trimming the global allocator failed.");
+
+            // Put the block back together.
+            keep.merge_right(&mut release)
+                .expect("Unable to merge the split block back together.");
+        }
+
+        // Note that the BRK lock must be released before pushing, since pushing might BRK.
+        drop(brk);
+        self.push(keep);
+    }
+
+    /// Give the physical memory of the free blocks back to the OS, as the pool exceeds `limit`.
+    ///
+    /// Every block worthy of it (see `config::OS_MEMTRIM_WORTHY`) is advised free, keeping it in
+    /// the pool. The memory not advised before is counted against `limit`, so passes are rare.
+    #[cfg(feature = "madvise")]
+    fn advise(&mut self, limit: usize) {
+        if self.total_bytes().saturating_sub(self.advised) <= limit {
+            return;
+        }
+
+        /// Logging...
+        log!(NOTE, "Advising the free memory of the global allocator to the OS.");
+
+        // Note that the BRK lock is held throughout, as nothing is pushed.
+        let mut brk = brk::lock();
+        for block in self.iter_blocks() {
+            if block.size() >= config::OS_MEMTRIM_WORTHY {
+                let _ = brk.advise_free(block);
+            }
+        }
+
+        self.advised = self.total_bytes();
+    }
+
+    /// Give memory back to the OS, as the pool exceeds `config::OS_MEMTRIM_LIMIT`.
+    ///
+    /// The top block is released, if it is worthy. With the `madvise` feature, the pool is advised
+    /// free as well (see `advise`).
+    fn memtrim(&mut self) {
+        // Pop the last block.
+        let block = self
+            .pop()
+            .expect("The byte count on the global allocator is invalid.");
+
+        // Check if the memtrim is worth it.
+        if block.size() >= config::OS_MEMTRIM_WORTHY {
+            /// Logging...
+            log!(NOTE, "Memtrimming the global allocator.");
+
+            // Release the block to the OS.
+            if let Err(block) = brk::lock().release(block) {
+                // It failed, put the block back.
+                // TODO: This can be done faster.
+                self.push(block);
+            }
+
+        // Note that this block is the only block next to the program
+        // break, due to the segments being as long as
+        // possible. For that reason, repeating to push and
+        // release would fail.
+        } else {
+            /// Logging...
+            log!(WARNING, "Memtrimming for the global allocator failed.");
+
+            // Push the block back.
+            // TODO: This can be done faster.
+            self.push(block);
+        }
+
+        #[cfg(feature = "madvise")]
+        self.advise(config::OS_MEMTRIM_LIMIT);
+    }
 }
 
 derive_deref!(GlobalAllocator, Bookkeeper);
 
 impl Allocator for GlobalAllocator {
     #[inline]
-    fn alloc_fresh(&mut self, size: usize, align: usize) -> Block {
-        // Obtain what you need.
-        let (alignment_block, res, excessive) =
-            brk::lock().canonical_brk(size, align);
+    fn try_alloc_fresh(&mut self, size: usize, align: usize) -> Option<Block> {
+        // Growing acquires more memory than needed, so when many threads run dry at once (e.g.
+        // starting from cold), one of them growing usually suffices. Hence, if another shard is
+        // growing right now, we wait for it to finish (it holds the BRK lock), and before growing
+        // ourselves, we try to take the memory from the other shards.
+        if GROWING.load(atomic::Ordering::SeqCst) > 0 {
+            drop(brk::lock());
+        }
+        // Stolen and fallback memory might be dirty.
+        self.fresh_zeroed = false;
+        if let Some(res) = steal(size, align) {
+            AVOIDED_GROWS.fetch_add(1, STATS_ORDERING);
+
+            return Some(res);
+        }
+
+        // Obtain what you need. Note that the BRK lock is released before falling back.
+        //
+        // Extra space is acquired along with the block, to limit the number of BRKs. Near OOM, the
+        // extra space itself might not fit, so before giving up, we retry without it, acquiring
+        // only the block and its alignment.
+        //
+        // Whether the block is zeroed must be checked under the same lock, before the break moves.
+        GROWING.fetch_add(1, atomic::Ordering::SeqCst);
+        let brk = {
+            let mut brk = brk::lock();
+            brk.try_canonical_brk(size, align)
+                .or_else(|| brk.try_exact_brk(size, align))
+                .map(|(alignment_block, res, excessive)| {
+                    let zeroed = brk.is_zeroed(&res);
+                    (alignment_block, res, excessive, zeroed)
+                })
+        };
+        GROWING.fetch_sub(1, atomic::Ordering::SeqCst);
+
+        let (alignment_block, res, excessive, zeroed) = match brk {
+            Some(x) => x,
+            // BRK failed, so try the fallback allocator (if any) before giving up.
+            None => return fallback(size, align),
+        };
+        GROWS.fetch_add(1, STATS_ORDERING);
 
         // Add it to the list. This will not change the order, since the
         // pointer is higher than all the previous blocks (BRK extends
@@ -168,44 +439,63 @@ impl Allocator for GlobalAllocator {
         self.push(alignment_block);
         self.push(excessive);
 
-        res
+        // Pushing might allocate fresh memory too, so this is set last.
+        self.fresh_zeroed = zeroed;
+
+        Some(res)
     }
 
-    fn on_new_memory(&mut self) {
-        if self.total_bytes() > config::OS_MEMTRIM_LIMIT {
-            // memtrim the fack outta 'em.
+    #[inline]
+    fn fresh_is_zeroed(&self) -> bool {
+        self.fresh_zeroed
+    }
 
-            // Pop the last block.
-            let block = self
-                .pop()
-                .expect("The byte count on the global allocator is invalid.");
+    fn on_new_memory(&mut self) {
+        if !self.trimming && self.total_bytes() > config::MAX_POOL_FREE_BYTES {
+            // No two free blocks are adjacent, so the pool is already as defragmented as it gets,
+            // and the top block is the only one which can be released. The interior blocks can
+            // only be advised free.
+            self.trimming = true;
+            self.trim(0);
+            #[cfg(feature = "madvise")]
+            self.advise(config::MAX_POOL_FREE_BYTES);
+            self.trimming = false;
+        }
 
-            // Check if the memtrim is worth it.
-            if block.size() >= config::OS_MEMTRIM_WORTHY {
-                /// Logging...
-                log!(NOTE, "Memtrimming the global allocator.");
-
-                // Release the block to the OS.
-                if let Err(block) = brk::lock().release(block) {
-                    // It failed, put the block back.
-                    // TODO: This can be done faster.
-                    self.push(block);
-                }
+        if !self.trimming && self.total_bytes() > config::OS_MEMTRIM_LIMIT {
+            // memtrim the fack outta 'em. Releasing pushes blocks back, which would memtrim again.
+            self.trimming = true;
+            self.memtrim();
+            self.trimming = false;
+        }
+    }
+}
 
-            // Note that this block is the only block next to the program
-            // break, due to the segments being as long as
-            // possible. For that reason, repeating to push and
-            // release would fail.
-            } else {
-                /// Logging...
-                log!(WARNING, "Memtrimming for the global allocator failed.");
+/// Free a block to the global allocator of the current thread.
+///
+/// This is used for memory, which did not come from the global allocator, but should end up in it
+/// (e.g. the segments of a scratch allocator, which cannot be released to the OS).
+pub fn free_to_global(block: Block) {
+    global_allocator().lock().get().free(block);
+}
 
-                // Push the block back.
-                // TODO: This can be done faster.
-                self.push(block);
+/// Take a block from the pool of any other global allocator shard.
+///
+/// Shards locked by someone else (including the current one, which the caller holds) are skipped,
+/// so this never blocks. `None` is returned if no shard could serve the allocation.
+fn steal(size: usize, align: usize) -> Option<Block> {
+    for shard in GLOBAL_ALLOCATORS.iter() {
+        if let Some(mut guard) = shard.try_lock() {
+            if let Some(res) = guard
+                .get_if_initialized()
+                .and_then(|alloc| alloc.try_alloc_pooled(size, align))
+            {
+                return Some(res);
             }
         }
     }
+
+    None
 }
 
 /// A local allocator.
@@ -216,6 +506,8 @@ impl Allocator for GlobalAllocator {
 pub struct LocalAllocator {
     // The inner bookkeeper.
     inner: Bookkeeper,
+    // The global allocator shard, memory is acquired from and memtrimmed to.
+    global: &'static GlobalShard,
 }
 
 #[cfg(feature = "tls")]
@@ -230,6 +522,16 @@ impl LocalAllocator {
             /// Logging...
             log!(NOTE, "Deinitializing and freeing the local allocator.");
 
+            // Warn about the memory the thread leaves behind. Leaking at exit is often intended, so
+            // this is no error.
+            #[cfg(feature = "leak_audit")]
+            {
+                let bytes = live::outstanding();
+                if bytes > 0 {
+                    log!(WARNING, "The thread exits with {} bytes outstanding.", bytes);
+                }
+            }
+
             // This is important! The thread destructors guarantee no other,
             // and thus one could use the allocator _after_ this
             // destructor have been finished. In fact, this is a
@@ -240,41 +542,79 @@ impl LocalAllocator {
             // allocation calls will be redirected to the global
             // allocator, which is of course still usable at this
             // moment.
-            let alloc = alloc
-                .replace(None)
-                .expect("Thread-local allocator is already freed.");
-
-            // Lock the global allocator.
-            let mut global_alloc = GLOBAL_ALLOCATOR.lock();
-            let global_alloc = global_alloc.get();
-
-            // TODO: we know this is sorted, so we could abuse that fact to
-            // faster insertion in the global allocator.
-
             alloc
+                .replace(None)
+                .expect("Thread-local allocator is already freed.")
                 .into_inner()
-                .inner
-                .for_each(move |block| global_alloc.free(block));
+                .free_all();
         }
 
         /// Logging...
         log!(NOTE, "Initializing the local allocator.");
 
-        // The initial acquired segment.
-        let initial_segment = GLOBAL_ALLOCATOR.lock().get().alloc(
-            8 * bookkeeper::EXTRA_ELEMENTS * mem::size_of::<Block>(),
-            mem::align_of::<Block>(),
-        );
+        let res = LocalAllocator::new();
 
         unsafe {
             // LAST AUDIT: 2016-08-21 (Ticki).
 
             // Register the thread destructor on the current thread.
             THREAD_ALLOCATOR.register_thread_destructor(dtor);
+        }
 
-            LocalAllocator {
-                inner: Bookkeeper::new(Vec::from_raw_parts(initial_segment, 0)),
-            }
+        res
+    }
+
+    /// Create a new local allocator.
+    ///
+    /// Unlike `init`, this does not register the thread destructor.
+    fn new() -> LocalAllocator {
+        // Pick the global allocator shard of this thread.
+        let global = global_allocator();
+
+        // The initial acquired segment.
+        let initial_segment = global.lock().get().alloc(
+            8 * bookkeeper::EXTRA_ELEMENTS * mem::size_of::<Block>(),
+            mem::align_of::<Block>(),
+        );
+
+        LocalAllocator {
+            inner: Bookkeeper::new(
+                unsafe { Vec::from_raw_parts(initial_segment, 0) },
+                "local",
+                ::shim::syscalls::thread_id(),
+            ),
+            global: global,
+        }
+    }
+
+    /// Free everything, including the block pool itself, to the global allocator.
+    fn free_all(self) {
+        // Lock the global allocator.
+        let mut global_alloc = self.global.lock();
+        let global_alloc = global_alloc.get();
+
+        // TODO: we know this is sorted, so we could abuse that fact to
+        // faster insertion in the global allocator.
+
+        self.inner.for_each(move |block| global_alloc.free(block));
+    }
+
+    /// Free all the blocks of the local allocator to the global allocator.
+    fn flush(&mut self) {
+        // Lock the global allocator.
+        let mut global_alloc = self.global.lock();
+        let global_alloc = global_alloc.get();
+
+        while self.len() > 0 {
+            global_alloc.free(unborrow!(self.remove_at(self.len() - 1)));
+        }
+        #[cfg(feature = "size_class_cache")]
+        while let Some(block) = self.pop_cached() {
+            global_alloc.free(block);
+        }
+        #[cfg(feature = "deferred_coalescing")]
+        while let Some(block) = self.pop_deferred_any() {
+            global_alloc.free(block);
         }
     }
 }
@@ -285,11 +625,23 @@ derive_deref!(LocalAllocator, Bookkeeper);
 #[cfg(feature = "tls")]
 impl Allocator for LocalAllocator {
     #[inline]
-    fn alloc_fresh(&mut self, size: usize, align: usize) -> Block {
+    fn try_alloc_fresh(&mut self, size: usize, align: usize) -> Option<Block> {
         // Get the block from the global allocator. Please note that we cannot
         // canonicalize `size`, due to freeing excessive blocks would change
         // the order.
-        GLOBAL_ALLOCATOR.lock().get().alloc(size, align)
+        self.global.lock().get().try_alloc(size, align)
+    }
+
+    #[inline]
+    fn try_extend_fresh(&mut self, block: Block, new_size: usize) -> Result<Block, Block> {
+        // The memory to the right of the block might have been freed to the global allocator
+        // (e.g. by memtrimming), in which case we take it back from there, rather than copying.
+        //
+        // Every free block is owned by exactly one pool (of either allocator), while allocated
+        // blocks are in neither. The block is allocated, so the global allocator can only merge
+        // it with a block it owns, and it does so under its lock, after which the whole block is
+        // allocated and thus owned by the caller alone.
+        self.global.lock().get().realloc_inplace(block, new_size)
     }
 
     #[inline]
@@ -303,7 +655,7 @@ impl Allocator for LocalAllocator {
             log!(NOTE, "Memtrimming the local allocator.");
 
             // Lock the global allocator.
-            let mut global_alloc = GLOBAL_ALLOCATOR.lock();
+            let mut global_alloc = self.global.lock();
             let global_alloc = global_alloc.get();
 
             while let Some(block) = self.pop() {
@@ -319,8 +671,43 @@ impl Allocator for LocalAllocator {
     }
 }
 
+/// Raise an alignment to at least `config::MIN_ALIGN`.
+///
+/// Alignments need not be powers of two, so the least common multiple is taken, such that the
+/// result is a multiple of both.
+#[inline]
+fn min_align(align: usize) -> usize {
+    if align % config::MIN_ALIGN == 0 {
+        align
+    } else if align.is_power_of_two() && config::MIN_ALIGN.is_power_of_two() {
+        cmp::max(align, config::MIN_ALIGN)
+    } else {
+        // Euclid's algorithm.
+        let (mut a, mut b) = (align, config::MIN_ALIGN);
+        while b != 0 {
+            let t = a % b;
+            a = b;
+            b = t;
+        }
+
+        (align / a).saturating_mul(config::MIN_ALIGN)
+    }
+}
+
+/// Get the pointer of a zero-sized allocation.
+///
+/// Zero-sized allocations are not backed by any memory. The pointer is non-null and aligned to
+/// `align` (an alignment of zero is treated like one), but dangling, and freeing it does nothing.
+#[inline]
+pub fn zero_sized(align: usize) -> *mut u8 {
+    cmp::max(align, 1) as *mut u8
+}
+
 /// Allocate a block of memory.
 ///
+/// If `size` is zero, a dangling (but non-null and aligned) pointer is returned, without
+/// allocating.
+///
 /// # Errors
 ///
 /// The OOM handler handles out-of-memory conditions.
@@ -333,112 +720,1225 @@ pub fn alloc(size: usize, align: usize) -> *mut u8 {
         align
     );
 
-    get_allocator!(|alloc| Pointer::from(alloc.alloc(size, align)).get())
+    alloc_inner(size, align, false)
 }
 
-/// Free a buffer.
-///
-/// Note that this do not have to be a buffer allocated through ralloc. The
-/// only requirement is that it is not used after the free.
+/// Allocate a block of memory, which is zeroed if `zeroed` is set.
 ///
-/// # Important!
+/// This is shared by `alloc` and `alloc_secure`.
+#[inline]
+fn alloc_inner(size: usize, align: usize, zeroed: bool) -> *mut u8 {
+    if size == 0 {
+        return zero_sized(align);
+    }
+
+    let ptr = match alloc_system(size.saturating_add(CANARY_SIZE), align, zeroed) {
+        // BRK failed during initialization, so libc serves everything.
+        Some(ptr) => if ptr.is_null() { fail::oom() } else { ptr },
+        None => {
+            // Postpone idle trimming.
+            mark_active();
+
+            get_allocator!(|alloc| {
+                let size = size.saturating_add(CANARY_SIZE);
+                let block = if zeroed {
+                    alloc.alloc_zeroed(size, min_align(align))
+                } else {
+                    alloc.alloc(size, min_align(align))
+                };
+
+                Pointer::from(block).get()
+            })
+        }
+    };
+
+    #[cfg(feature = "canary")]
+    unsafe {
+        canary::write(ptr, size);
+    }
+
+    // Track the new allocation.
+    #[cfg(feature = "track_live")]
+    live::insert(ptr, size);
+    #[cfg(feature = "introspection")]
+    stats::record_alloc(size);
+    #[cfg(feature = "histogram")]
+    record_size(size);
+
+    hook::fire(AllocEvent::Alloc {
+        ptr: ptr,
+        size: size,
+        align: align,
+    });
+
+    ptr
+}
+
+/// Allocate a block of memory described by a `Layout`.
 ///
-/// You should only allocate buffers allocated through `ralloc`. Anything else
-/// is considered invalid.
+/// This is equivalent to `alloc(layout.size(), layout.align())`.
 ///
 /// # Errors
 ///
 /// The OOM handler handles out-of-memory conditions.
+#[inline]
+pub fn alloc_layout(layout: Layout) -> *mut u8 {
+    alloc(layout.size(), layout.align())
+}
+
+/// Allocate a zeroed block of memory.
 ///
-/// # Safety
+/// Unlike `alloc`, the buffer is always zeroed, even if it is reused memory and the `security`
+/// feature is disabled. This allows e.g. cryptographic code to get clean memory without paying
+/// for zeroing globally. Memory fresh from the OS is zeroed already, so it is not zeroed again.
 ///
-/// Rust assume that the allocation symbols returns correct values. For this
-/// reason, freeing invalid pointers might introduce memory unsafety.
+/// # Errors
 ///
-/// Secondly, freeing an used buffer can introduce use-after-free.
+/// The OOM handler handles out-of-memory conditions.
 #[inline]
-pub unsafe fn free(ptr: *mut u8, size: usize) {
-    log!(CALL, "Freeing buffer of size {}.", size);
+pub fn alloc_secure(size: usize, align: usize) -> *mut u8 {
+    log!(
+        CALL,
+        "Allocating zeroed buffer of size {} (align {}).",
+        size,
+        align
+    );
 
-    get_allocator!(
-        |alloc| alloc.free(Block::from_raw_parts(Pointer::new(ptr), size))
-    )
+    alloc_inner(size, align, true)
 }
 
-/// Reallocate memory.
-///
-/// Reallocate the buffer starting at `ptr` with size `old_size`, to a buffer
-/// starting at the returned pointer with size `size`.
+/// Allocate a block of memory, which might be bigger than requested.
 ///
-/// # Important!
+/// The pointer and the true size of the buffer are returned. If splitting the fitting block would
+/// leave a remainder smaller than `config::MIN_SPLIT`, the whole block is handed out instead of
+/// leaving a stub in the pool. The buffer must be freed (or reallocated) by its true size.
 ///
-/// You should only reallocate buffers allocated through `ralloc`. Anything
-/// else is considered invalid.
+/// With the `canary` feature, or when fallen back to libc, the buffer is always exact.
 ///
 /// # Errors
 ///
 /// The OOM handler handles out-of-memory conditions.
-///
-/// # Safety
-///
-/// Due to being able to potentially memcpy an arbitrary buffer, as well as
-/// shrinking a buffer, this is marked unsafe.
 #[inline]
-pub unsafe fn realloc(
-    ptr: *mut u8,
-    old_size: usize,
-    size: usize,
-    align: usize,
-) -> *mut u8 {
+pub fn alloc_excess(size: usize, align: usize) -> (*mut u8, usize) {
     log!(
         CALL,
-        "Reallocating buffer of size {} to new size {}.",
-        old_size,
-        size
-    );
-
-    get_allocator!(|alloc| Pointer::from(alloc.realloc(
-        Block::from_raw_parts(Pointer::new(ptr), old_size),
+        "Allocating buffer of size {} or more (align {}).",
         size,
         align
-    )).get())
+    );
+
+    // The canary is placed right after the requested size, so it would be lost in the excess.
+    if size == 0 || cfg!(feature = "canary") {
+        return (alloc_inner(size, align, false), size);
+    }
+
+    // BRK failed during initialization, so libc serves everything.
+    #[cfg(feature = "libc_fallback")]
+    {
+        if use_system() {
+            return (alloc_inner(size, align, false), size);
+        }
+    }
+
+    // Postpone idle trimming.
+    mark_active();
+
+    let block = get_allocator!(|alloc| alloc.alloc_excess(size, min_align(align)));
+    let size = block.size();
+    let ptr = Pointer::from(block).get();
+
+    // Track the new allocation.
+    #[cfg(feature = "track_live")]
+    live::insert(ptr, size);
+    #[cfg(feature = "introspection")]
+    stats::record_alloc(size);
+    #[cfg(feature = "histogram")]
+    record_size(size);
+
+    hook::fire(AllocEvent::Alloc {
+        ptr: ptr,
+        size: size,
+        align: align,
+    });
+
+    (ptr, size)
 }
 
-/// Try to reallocate the buffer _inplace_.
+/// Try to allocate a block of memory.
 ///
-/// In case of success, return the new buffer's size. On failure, return the
-/// old size.
-///
-/// This can be used to shrink (truncate) a buffer as well.
+/// Unlike `alloc`, this returns `None` instead of calling the OOM handler, when the system is
+/// unable to gather memory for the allocation.
 ///
-/// # Safety
+/// # Errors
 ///
-/// Due to being able to shrink (and thus free) the buffer, this is marked
-/// unsafe.
+/// Note that the allocator might still call the OOM handler, if it is unable to gather memory for
+/// its own bookkeeping.
 #[inline]
-pub unsafe fn realloc_inplace(
-    ptr: *mut u8,
-    old_size: usize,
-    size: usize,
-) -> Result<(), ()> {
+pub fn try_alloc(size: usize, align: usize) -> Option<*mut u8> {
     log!(
         CALL,
-        "Inplace reallocating buffer of size {} to new size {}.",
-        old_size,
-        size
+        "Trying to allocate buffer of size {} (align {}).",
+        size,
+        align
     );
 
-    get_allocator!(|alloc| {
-        if alloc
-            .realloc_inplace(
-                Block::from_raw_parts(Pointer::new(ptr), old_size),
-                size,
-            )
-            .is_ok()
-        {
-            Ok(())
-        } else {
-            Err(())
+    if size == 0 {
+        return Some(zero_sized(align));
+    }
+
+    let ptr = match alloc_system(size.saturating_add(CANARY_SIZE), align, false) {
+        // BRK failed during initialization, so libc serves everything.
+        Some(ptr) => if ptr.is_null() { None } else { Some(ptr) },
+        None => {
+            // Postpone idle trimming.
+            mark_active();
+
+            get_allocator!(|alloc| alloc
+                .try_alloc(size.saturating_add(CANARY_SIZE), min_align(align))
+                .map(|block| Pointer::from(block).get()))
         }
-    })
+    };
+
+    #[cfg(feature = "canary")]
+    {
+        if let Some(ptr) = ptr {
+            unsafe {
+                canary::write(ptr, size);
+            }
+        }
+    }
+
+    // Track the new allocation.
+    #[cfg(feature = "track_live")]
+    {
+        if let Some(ptr) = ptr {
+            live::insert(ptr, size);
+        }
+    }
+    #[cfg(feature = "introspection")]
+    {
+        if ptr.is_some() {
+            stats::record_alloc(size);
+        }
+    }
+    #[cfg(feature = "histogram")]
+    {
+        if ptr.is_some() {
+            record_size(size);
+        }
+    }
+
+    if let Some(ptr) = ptr {
+        hook::fire(AllocEvent::Alloc {
+            ptr: ptr,
+            size: size,
+            align: align,
+        });
+    }
+
+    ptr
+}
+
+/// Allocate a block of memory in bounded time.
+///
+/// This is meant for real-time code, which cannot afford unbounded allocation latency. Only
+/// sources with constant time access are used, namely the size class cache (when enabled) and the
+/// last block of the pool, of the thread-local allocator, and of the global allocator if its lock
+/// is free. This never blocks, scans the pool, nor BRKs, and `None` is returned if none of these
+/// sources suffice.
+///
+/// Note that the allocation hook, the live set, and the statistics (if enabled) are not bounded.
+#[inline]
+pub fn alloc_rt(size: usize, align: usize) -> Option<*mut u8> {
+    log!(
+        CALL,
+        "Allocating buffer of size {} (align {}) in bounded time.",
+        size,
+        align
+    );
+
+    if size == 0 {
+        return Some(zero_sized(align));
+    }
+
+    // BRK failed during initialization, so libc serves everything, which is not bounded.
+    #[cfg(feature = "libc_fallback")]
+    {
+        if use_system() {
+            return None;
+        }
+    }
+
+    // Postpone idle trimming.
+    mark_active();
+
+    // The size of the block, which holds the canary (if any) after the buffer.
+    let block_size = size.saturating_add(CANARY_SIZE);
+
+    // Try the local allocator, if it is initialized. Initializing it would BRK.
+    #[cfg(feature = "tls")]
+    let block = THREAD_ALLOCATOR.with(|thread_alloc| {
+        thread_alloc.replace(None).and_then(|mut thread_alloc_original| {
+            let res = thread_alloc_original
+                .get_if_initialized()
+                .and_then(|alloc| alloc.try_alloc_bounded(block_size, min_align(align)));
+
+            // Put back the original allocator.
+            thread_alloc.replace(Some(thread_alloc_original));
+
+            res
+        })
+    });
+    #[cfg(not(feature = "tls"))]
+    let block = None;
+
+    // Fall back to the global allocator, without waiting for the lock.
+    let ptr = block
+        .or_else(|| {
+            global_allocator().try_lock().and_then(|mut guard| {
+                guard
+                    .get_if_initialized()
+                    .and_then(|alloc| alloc.try_alloc_bounded(block_size, min_align(align)))
+            })
+        })
+        .map(|block| Pointer::from(block).get());
+
+    #[cfg(feature = "canary")]
+    {
+        if let Some(ptr) = ptr {
+            unsafe {
+                canary::write(ptr, size);
+            }
+        }
+    }
+
+    // Track the new allocation.
+    #[cfg(feature = "track_live")]
+    {
+        if let Some(ptr) = ptr {
+            live::insert(ptr, size);
+        }
+    }
+    #[cfg(feature = "introspection")]
+    {
+        if ptr.is_some() {
+            stats::record_alloc(size);
+        }
+    }
+    #[cfg(feature = "histogram")]
+    {
+        if ptr.is_some() {
+            record_size(size);
+        }
+    }
+
+    if let Some(ptr) = ptr {
+        hook::fire(AllocEvent::Alloc {
+            ptr: ptr,
+            size: size,
+            align: align,
+        });
+    }
+
+    ptr
+}
+
+/// Free a buffer.
+///
+/// Note that this do not have to be a buffer allocated through ralloc. The
+/// only requirement is that it is not used after the free.
+///
+/// Freeing zero bytes (e.g. a zero-sized allocation) does nothing.
+///
+/// # Important!
+///
+/// You should only allocate buffers allocated through `ralloc`. Anything else
+/// is considered invalid.
+///
+/// With the `libc_fallback` feature, after falling back to libc, only whole buffers can be freed.
+/// Freeing a part of a buffer is then ignored (with a warning logged), leaking the buffer.
+///
+/// With the `canary` feature, only whole buffers can be freed too, since the canary is placed
+/// after the whole buffer. Freeing a part of a buffer aborts the process (when freeing the tail,
+/// once the rest is freed).
+///
+/// # Errors
+///
+/// The OOM handler handles out-of-memory conditions.
+///
+/// # Safety
+///
+/// Rust assume that the allocation symbols returns correct values. For this
+/// reason, freeing invalid pointers might introduce memory unsafety.
+///
+/// Secondly, freeing an used buffer can introduce use-after-free.
+#[inline]
+pub unsafe fn free(ptr: *mut u8, size: usize) {
+    log!(CALL, "Freeing buffer of size {}.", size);
+
+    // Zero-sized allocations are not backed by memory.
+    if size == 0 {
+        return;
+    }
+
+    hook::fire(AllocEvent::Free {
+        ptr: ptr,
+        size: size,
+    });
+
+    // Untrack the freed range.
+    #[cfg(feature = "track_live")]
+    live::remove(ptr, size);
+    #[cfg(feature = "introspection")]
+    stats::record_free(size);
+
+    // Make sure the buffer wasn't overflowed (or freed already), and mark it as freed.
+    #[cfg(feature = "canary")]
+    {
+        canary::check(ptr, size);
+        canary::mark_freed(ptr, size);
+    }
+
+    // BRK failed during initialization, so libc serves everything.
+    #[cfg(feature = "libc_fallback")]
+    {
+        if use_system() {
+            system::free(ptr, size + CANARY_SIZE);
+            return;
+        }
+    }
+
+    let block = Block::from_raw_parts(Pointer::new(ptr), size + CANARY_SIZE);
+    // Keep big blocks inaccessible for a while, catching use-after-frees.
+    #[cfg(feature = "protect_freed")]
+    let block = quarantine::push(block);
+
+    get_allocator!(|alloc| alloc.free(block))
+}
+
+/// Free the allocation containing some pointer.
+///
+/// Some (FFI) code frees buffers by a pointer into their interior rather than their start. Passing
+/// such a pointer to `free` would corrupt the allocator, since the start of the block is wrong.
+/// This looks up the live allocation containing `ptr` in the live set, and frees the whole of it.
+///
+/// `false` is returned (and nothing is freed) if `ptr` is not within a tracked live allocation
+/// (see `is_live`).
+///
+/// # Safety
+///
+/// See `free`. The whole allocation must not be used after the free.
+#[cfg(feature = "track_live")]
+pub unsafe fn free_interior(ptr: *mut u8) -> bool {
+    // Take the allocation out of the live set right away, so it is freed only once, even if
+    // other threads free it at the same time.
+    if let Some((start, size)) = live::take_containing(ptr) {
+        // Logging...
+        log!(DEBUG, "Freeing {:?} through the interior pointer {:?}.", start, ptr);
+
+        free(start, size);
+
+        true
+    } else {
+        false
+    }
+}
+
+/// Free a buffer described by a `Layout`.
+///
+/// This is equivalent to `free(ptr, layout.size())`.
+///
+/// # Safety
+///
+/// See `free`.
+#[inline]
+pub unsafe fn free_layout(ptr: *mut u8, layout: Layout) {
+    free(ptr, layout.size())
+}
+
+/// Reallocate memory.
+///
+/// Reallocate the buffer starting at `ptr` with size `old_size`, to a buffer
+/// starting at the returned pointer with size `size`.
+///
+/// # Important!
+///
+/// You should only reallocate buffers allocated through `ralloc`. Anything
+/// else is considered invalid.
+///
+/// # Errors
+///
+/// The OOM handler handles out-of-memory conditions.
+///
+/// # Safety
+///
+/// Due to being able to potentially memcpy an arbitrary buffer, as well as
+/// shrinking a buffer, this is marked unsafe.
+#[inline]
+pub unsafe fn realloc(
+    ptr: *mut u8,
+    old_size: usize,
+    size: usize,
+    align: usize,
+) -> *mut u8 {
+    log!(
+        CALL,
+        "Reallocating buffer of size {} to new size {}.",
+        old_size,
+        size
+    );
+
+    // Zero-sized allocations are not backed by memory, so there is nothing to reallocate.
+    if old_size == 0 {
+        return alloc(size, align);
+    } else if size == 0 {
+        free(ptr, old_size);
+        return zero_sized(align);
+    }
+
+    // Postpone idle trimming.
+    mark_active();
+
+    // Make sure the buffer wasn't overflowed, and mark the old buffer as freed, in case it moves.
+    #[cfg(feature = "canary")]
+    {
+        canary::check(ptr, old_size);
+        canary::mark_freed(ptr, old_size);
+    }
+
+    let new = match realloc_system(
+        ptr,
+        old_size + CANARY_SIZE,
+        size.saturating_add(CANARY_SIZE),
+        align,
+    ) {
+        // BRK failed during initialization, so libc serves everything.
+        Some(new) => if new.is_null() { fail::oom() } else { new },
+        None => get_allocator!(|alloc| Pointer::from(alloc.realloc(
+            Block::from_raw_parts(Pointer::new(ptr), old_size + CANARY_SIZE),
+            size.saturating_add(CANARY_SIZE),
+            min_align(align)
+        )).get()),
+    };
+
+    // The canary might have been moved along with the data, but not to the right place.
+    #[cfg(feature = "canary")]
+    canary::write(new, size);
+
+    // Move the allocation in the live set.
+    #[cfg(feature = "track_live")]
+    {
+        live::remove(ptr, old_size);
+        live::insert(new, size);
+    }
+    #[cfg(feature = "introspection")]
+    {
+        stats::record_free(old_size);
+        stats::record_alloc(size);
+    }
+
+    hook::fire(AllocEvent::Realloc {
+        old_ptr: ptr,
+        old_size: old_size,
+        ptr: new,
+        size: size,
+        align: align,
+    });
+
+    new
+}
+
+/// Reallocate a buffer described by a `Layout` to another `Layout`.
+///
+/// This is equivalent to `realloc(ptr, old.size(), new.size(), new.align())`, so the new buffer
+/// is aligned to the alignment of `new`, even if it is stronger than the old one. `Layout`
+/// guarantees the alignment to be a non-zero power of two.
+///
+/// # Errors
+///
+/// The OOM handler handles out-of-memory conditions.
+///
+/// # Safety
+///
+/// See `realloc`. `old` must be the layout the buffer was allocated with.
+#[inline]
+pub unsafe fn realloc_layout(ptr: *mut u8, old: Layout, new: Layout) -> *mut u8 {
+    realloc(ptr, old.size(), new.size(), new.align())
+}
+
+/// Try to reallocate the buffer _inplace_.
+///
+/// In case of success, return the new buffer's size. On failure, return the
+/// old size.
+///
+/// This can be used to shrink (truncate) a buffer as well.
+///
+/// # Safety
+///
+/// Due to being able to shrink (and thus free) the buffer, this is marked
+/// unsafe.
+#[inline]
+pub unsafe fn realloc_inplace(
+    ptr: *mut u8,
+    old_size: usize,
+    size: usize,
+) -> Result<(), ()> {
+    log!(
+        CALL,
+        "Inplace reallocating buffer of size {} to new size {}.",
+        old_size,
+        size
+    );
+
+    // Zero-sized allocations are not backed by memory, so they cannot grow, but every buffer can
+    // shrink to nothing.
+    if old_size == 0 {
+        return if size == 0 { Ok(()) } else { Err(()) };
+    } else if size == 0 {
+        free(ptr, old_size);
+        return Ok(());
+    }
+
+    // BRK failed during initialization, so libc serves everything, which cannot reallocate inplace.
+    #[cfg(feature = "libc_fallback")]
+    {
+        if use_system() {
+            return Err(());
+        }
+    }
+
+    // Make sure the buffer wasn't overflowed.
+    #[cfg(feature = "canary")]
+    canary::check(ptr, old_size);
+
+    let res = get_allocator!(|alloc| {
+        if alloc
+            .realloc_inplace(
+                Block::from_raw_parts(Pointer::new(ptr), old_size + CANARY_SIZE),
+                size.saturating_add(CANARY_SIZE),
+            )
+            .is_ok()
+        {
+            Ok(())
+        } else {
+            Err(())
+        }
+    });
+
+    // On failure, the block (and thus the canary) is left intact.
+    #[cfg(feature = "canary")]
+    {
+        if res.is_ok() {
+            canary::write(ptr, size);
+        }
+    }
+
+    // Update the size in the live set.
+    #[cfg(feature = "track_live")]
+    {
+        if res.is_ok() {
+            live::remove(ptr, old_size);
+            live::insert(ptr, size);
+        }
+    }
+    #[cfg(feature = "introspection")]
+    {
+        if res.is_ok() {
+            stats::record_free(old_size);
+            stats::record_alloc(size);
+        }
+    }
+
+    res
+}
+
+/// Is `ptr` the start of a live allocation?
+///
+/// This consults the live set, and is meant for debuggers and sanitizers classifying pointers
+/// (e.g. in a crash dump). Zero-sized allocations and allocations made while the live set was
+/// full are not tracked, and thus reported as not live.
+#[cfg(feature = "track_live")]
+pub fn is_live(ptr: *const u8) -> bool {
+    live::contains(ptr)
+}
+
+/// Report every outstanding allocation.
+///
+/// `f` is called with the pointer and the size of every allocation, which is not (yet) freed. This
+/// is meant to be called at shutdown, where every outstanding allocation is a leak. Allocations
+/// made while the live set was full are not tracked, and thus not reported.
+///
+/// The tracking records are stored in a fixed-size table, so they neither leak nor allocate
+/// themselves.
+#[cfg(feature = "leak_audit")]
+pub fn report_leaks(f: fn(*mut u8, usize)) {
+    live::for_each(f)
+}
+
+/// Walk the live allocations along with their backtraces.
+///
+/// `f` is called with the pointer, the size, and the backtrace of every live allocation, i.e. the
+/// raw return addresses of the callers of the allocation function, innermost first, for offline
+/// symbolization. The backtraces are captured by walking the frame pointers, so the program must
+/// be compiled with `-C force-frame-pointers=yes`, or they might be empty.
+#[cfg(feature = "fp_backtrace")]
+pub fn walk_live(f: fn(*mut u8, usize, &[usize])) {
+    live::walk(f)
+}
+
+/// Get the size of the block backing the live allocation starting at `ptr`.
+///
+/// This is the size owned by the caller (the requested size, or the size returned by
+/// `alloc_excess`, minus whatever was freed from the tail, and plus whatever was grown in place),
+/// plus the canary (if any). Blocks are split exactly, and the alignment padding goes back to the
+/// pool, so there is no further slack. `None` is returned if `ptr` is not the start of a tracked
+/// live allocation (see `is_live`).
+#[cfg(feature = "track_live")]
+pub fn allocation_size(ptr: *const u8) -> Option<usize> {
+    live::size(ptr).map(|size| size + CANARY_SIZE)
+}
+
+/// Get the statistics of every size class.
+///
+/// Reallocations count as a free of the old size followed by an allocation of the new size.
+#[cfg(feature = "introspection")]
+pub fn class_stats() -> [stats::ClassStats; config::SIZE_CLASSES] {
+    stats::class_stats()
+}
+
+/// Count an allocation of some size in the size histogram.
+#[cfg(feature = "histogram")]
+#[inline]
+fn record_size(size: usize) {
+    // Sizes above the biggest power of two go to the last bucket.
+    let bucket = size
+        .checked_next_power_of_two()
+        .map_or(HISTOGRAM_BUCKETS - 1, |x| x.trailing_zeros() as usize);
+
+    SIZE_HISTOGRAM[bucket].fetch_add(1, atomic::Ordering::Relaxed);
+}
+
+/// Get the histogram of allocation sizes.
+///
+/// Bucket `n` holds the number of allocations of the sizes `2^(n - 1) + 1` through `2^n`, i.e. the
+/// sizes are rounded up to the nearest power of two. Reallocations are not counted.
+///
+/// Unlike `class_stats`, the counters are updated without locking, so this is cheap enough for
+/// production, but only approximate while other threads allocate.
+#[cfg(feature = "histogram")]
+pub fn size_histogram() -> [usize; HISTOGRAM_BUCKETS] {
+    let mut res = [0; HISTOGRAM_BUCKETS];
+
+    for (bucket, count) in SIZE_HISTOGRAM.iter().zip(res.iter_mut()) {
+        *count = bucket.load(atomic::Ordering::Relaxed);
+    }
+
+    res
+}
+
+/// Get the state of every global allocator shard.
+///
+/// The number of free bytes (including the size class cache, if enabled) and the length of the
+/// block pool of every shard are reported, so an imbalance between the shards (e.g. one hot shard)
+/// shows. Uninitialized shards report zeros.
+///
+/// Note that this waits for every shard lock in turn, so the shards are not captured at the same
+/// instant.
+#[cfg(feature = "introspection")]
+pub fn shard_stats() -> [(usize, usize); config::SHARDS] {
+    let mut res = [(0, 0); config::SHARDS];
+
+    for (shard, stats) in GLOBAL_ALLOCATORS.iter().zip(res.iter_mut()) {
+        if let Some(global) = shard.lock().get_if_initialized() {
+            *stats = (global.total_bytes(), global.len());
+            #[cfg(feature = "size_class_cache")]
+            {
+                stats.0 += global.cached_bytes();
+            }
+        }
+    }
+
+    res
+}
+
+/// Walk the free blocks of the global allocator.
+///
+/// `f` is called with the address and the size of every free block in the block pools of the
+/// global allocator shards, e.g. for snapshotting the free map in a debugger or visualizer. Blocks
+/// kept aside by the shards (i.e. deferred or cached blocks) are left out.
+///
+/// Every shard is locked while it is walked, so `f` must not allocate.
+#[cfg(feature = "introspection")]
+pub fn walk_free(f: fn(usize, usize)) {
+    for shard in GLOBAL_ALLOCATORS.iter() {
+        if let Some(global) = shard.lock().get_if_initialized() {
+            for (ptr, size) in global.iter_free() {
+                f(ptr, size);
+            }
+        }
+    }
+}
+
+/// A snapshot of the state of the global allocator.
+///
+/// This is gathered without allocating or blocking, and passed to the OOM prehook (see
+/// `set_oom_prehook`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// The number of free bytes in the global allocator.
+    ///
+    /// This includes the blocks in the size class caches (if enabled).
+    pub free_bytes: usize,
+    /// The size of the largest free block in the global allocator.
+    pub largest_free_block: usize,
+    /// The number of times the global allocator grew.
+    pub grows: usize,
+    /// The number of global allocator shards, which were locked, and thus left out.
+    ///
+    /// If this is non-zero, the other fields only cover a part of the global allocator.
+    pub locked_shards: usize,
+}
+
+/// Gather the statistics of the global allocator without blocking.
+///
+/// Shards locked by someone else (including the current thread) are skipped, and counted in
+/// `locked_shards`.
+pub fn try_stats() -> Stats {
+    let mut stats = Stats {
+        grows: GROWS.load(STATS_ORDERING),
+        ..Stats::default()
+    };
+
+    for shard in GLOBAL_ALLOCATORS.iter() {
+        if let Some(mut guard) = shard.try_lock() {
+            if let Some(global) = guard.get_if_initialized() {
+                stats.free_bytes += global.total_bytes();
+                #[cfg(feature = "size_class_cache")]
+                {
+                    stats.free_bytes += global.cached_bytes();
+                }
+                stats.largest_free_block =
+                    cmp::max(stats.largest_free_block, global.largest_block());
+            }
+        } else {
+            stats.locked_shards += 1;
+        }
+    }
+
+    stats
+}
+
+/// Allocate through the fallback allocator.
+///
+/// `None` is returned if no fallback allocator is set or if it returned null.
+fn fallback(size: usize, align: usize) -> Option<Block> {
+    let fallback = FALLBACK.load(atomic::Ordering::SeqCst);
+    if fallback.is_null() {
+        return None;
+    }
+
+    // Logging...
+    log!(WARNING, "BRK failed, using the fallback allocator.");
+
+    let ptr = unsafe {
+        // Transmute the atomic pointer to a function pointer and call it.
+        (mem::transmute::<_, fn(usize, usize) -> *mut u8>(fallback))(size, align)
+    };
+
+    if ptr.is_null() {
+        None
+    } else {
+        // Make sure the fallback allocator keeps its promises.
+        debug_assert!(ptr as usize % align == 0, "The fallback allocator misaligned.");
+
+        Some(unsafe { Block::from_raw_parts(Pointer::new(ptr), size) })
+    }
+}
+
+/// Acquire the initial segment of a global allocator.
+///
+/// If this is the very first BRK and it fails, we fall back to libc for good, and take the
+/// segment from libc (it is never used for allocations, but the allocator must be valid).
+#[cfg(feature = "libc_fallback")]
+fn init_segment() -> (Block, Block, Block) {
+    let size = 8 * bookkeeper::EXTRA_ELEMENTS * mem::size_of::<Block>();
+    let align = mem::align_of::<Block>();
+
+    if let Some(res) = brk::lock().try_canonical_brk(size, align) {
+        system::mark_brk();
+        return res;
+    }
+
+    if system::fall_back().is_err() {
+        fail::oom();
+    }
+
+    let ptr = unsafe { system::alloc(size, align) };
+    if ptr.is_null() {
+        fail::oom();
+    }
+
+    let block = unsafe {
+        // libc gave us the buffer, so we own it.
+        Block::from_raw_parts(Pointer::new(ptr), size)
+    };
+
+    let (aligner, excessive) = (block.empty_left(), block.empty_right());
+
+    (aligner, block, excessive)
+}
+
+/// Should allocations go to libc?
+///
+/// The first call initializes the global allocator shard of this thread to find out, whether BRK
+/// works at all.
+#[cfg(feature = "libc_fallback")]
+#[inline]
+fn use_system() -> bool {
+    if !system::is_determined() {
+        global_allocator().lock().get();
+    }
+
+    system::is_active()
+}
+
+/// Allocate a buffer through libc, if BRK failed during initialization.
+///
+/// `None` is returned if `ralloc` serves allocations. Otherwise, `Some` of the buffer (zeroed, if
+/// `zeroed` is set) is returned, which is null if libc ran out of memory.
+#[cfg(feature = "libc_fallback")]
+#[inline]
+fn alloc_system(size: usize, align: usize, zeroed: bool) -> Option<*mut u8> {
+    if !use_system() {
+        return None;
+    }
+
+    let ptr = unsafe { system::alloc(size, min_align(align)) };
+    if zeroed && !ptr.is_null() {
+        unsafe {
+            // The buffer was just allocated, so we are the sole owner.
+            ptr::write_bytes(ptr, 0, size);
+        }
+    }
+
+    Some(ptr)
+}
+
+/// Without `libc_fallback`, `ralloc` serves every allocation.
+#[cfg(not(feature = "libc_fallback"))]
+#[inline]
+fn alloc_system(_: usize, _: usize, _: bool) -> Option<*mut u8> {
+    None
+}
+
+/// Reallocate a buffer through libc, if BRK failed during initialization.
+///
+/// Like `alloc_system`, `None` is returned if `ralloc` serves allocations, and `Some(null)` if
+/// libc ran out of memory (leaving the old buffer intact).
+#[cfg(feature = "libc_fallback")]
+#[inline]
+unsafe fn realloc_system(
+    ptr: *mut u8,
+    old_size: usize,
+    size: usize,
+    align: usize,
+) -> Option<*mut u8> {
+    if use_system() {
+        Some(system::realloc(ptr, old_size, size, min_align(align)))
+    } else {
+        None
+    }
+}
+
+/// Without `libc_fallback`, `ralloc` serves every reallocation.
+#[cfg(not(feature = "libc_fallback"))]
+#[inline]
+unsafe fn realloc_system(_: *mut u8, _: usize, _: usize, _: usize) -> Option<*mut u8> {
+    None
+}
+
+/// Fold over the pools of the current thread's local allocator and of every global allocator shard.
+///
+/// Every pool is inspected under its lock, but other threads might allocate from or free to the
+/// global allocator right after, so the result is a racy snapshot.
+fn fold_pools<T, F: FnMut(T, &Bookkeeper) -> T>(init: T, mut f: F) -> T {
+    // Inspect the local allocator, if it is initialized.
+    #[cfg(feature = "tls")]
+    let init = THREAD_ALLOCATOR.with(|thread_alloc| {
+        if let Some(mut thread_alloc_original) = thread_alloc.replace(None) {
+            let res = match thread_alloc_original.get_if_initialized() {
+                Some(alloc) => f(init, &**alloc),
+                None => init,
+            };
+
+            // Put back the original allocator.
+            thread_alloc.replace(Some(thread_alloc_original));
+
+            res
+        } else {
+            init
+        }
+    });
+
+    GLOBAL_ALLOCATORS.iter().fold(init, |acc, shard| {
+        match shard.lock().get_if_initialized() {
+            Some(alloc) => f(acc, &**alloc),
+            None => acc,
+        }
+    })
+}
+
+/// Get the size of the largest free block.
+///
+/// This is the maximum over the pools of the current thread's local allocator and of every global
+/// allocator shard, i.e. the biggest allocation, which can be served without gathering new memory
+/// (alignment aside).
+///
+/// Note that this is a racy snapshot: Other threads might allocate from or free to the global
+/// allocator right after it was inspected.
+pub fn largest_free_block() -> usize {
+    fold_pools(0, |largest, pool| cmp::max(largest, pool.largest_block()))
+}
+
+/// Get the external fragmentation of the free memory.
+///
+/// This is `1 - largest / total`, where `largest` is the size of the largest free block, and
+/// `total` is the number of free bytes, both over the pools of the current thread's local
+/// allocator and of every global allocator shard. It ranges from 0 (the free memory is a single
+/// block) towards 1 (the free memory is scattered over many small blocks), and a high value
+/// suggests calling `compact`. If there is no free memory, 0 is returned.
+///
+/// Like `largest_free_block`, this is a racy snapshot.
+pub fn fragmentation() -> f32 {
+    let (largest, total) = fold_pools((0, 0), |(largest, total), pool| {
+        (
+            cmp::max(largest, pool.largest_block()),
+            total + pool.total_bytes(),
+        )
+    });
+
+    if total == 0 {
+        0.0
+    } else {
+        1.0 - largest as f32 / total as f32
+    }
+}
+
+/// Get the number of times the global allocator grew (i.e. BRK'd).
+///
+/// Grows in other threads might not be visible yet, unless the current thread synchronized with
+/// them (e.g. by joining them). The order relative to other counters is only guaranteed with the
+/// `precise_stats` feature.
+#[inline]
+pub fn grow_count() -> usize {
+    GROWS.load(STATS_ORDERING)
+}
+
+/// Get the number of grows avoided.
+///
+/// When a global allocator shard runs dry, it waits for any ongoing growth of other shards to
+/// finish, and takes the memory from them instead of growing itself, if possible. This counts how
+/// often that happened.
+///
+/// Like `grow_count`, this only covers the avoided grows the current thread synchronized with.
+#[inline]
+pub fn avoided_grow_count() -> usize {
+    AVOIDED_GROWS.load(STATS_ORDERING)
+}
+
+/// Make allocations through `GlobalAlloc` fail after `n` more successful allocations.
+///
+/// From then on, `GlobalAlloc::alloc` returns null, simulating OOM without exhausting any memory.
+/// This is useful for testing the allocation failure handling of downstream code. Note that only
+/// the `GlobalAlloc` entry point is affected, and that the allocations of all threads count.
+///
+/// `fail_after(!0)` makes the allocations never fail (the default).
+#[cfg(feature = "testing")]
+pub fn fail_after(n: usize) {
+    // Logging...
+    log!(NOTE, "Failing allocations after {} more.", n);
+
+    ALLOCS_LEFT.store(n, atomic::Ordering::SeqCst);
+}
+
+/// Count an allocation through `GlobalAlloc`, and check if it should fail.
+///
+/// See `fail_after`.
+#[cfg(feature = "testing")]
+pub fn should_fail() -> bool {
+    let mut left = ALLOCS_LEFT.load(atomic::Ordering::SeqCst);
+
+    loop {
+        if left == !0 {
+            // Failing is disabled.
+            return false;
+        } else if left == 0 {
+            return true;
+        }
+
+        // Count the allocation, unless another thread beat us to it.
+        match ALLOCS_LEFT.compare_exchange_weak(
+            left,
+            left - 1,
+            atomic::Ordering::SeqCst,
+            atomic::Ordering::SeqCst,
+        ) {
+            Ok(_) => return false,
+            Err(x) => left = x,
+        }
+    }
+}
+
+/// Set the fallback allocator.
+///
+/// The fallback allocator is consulted with the size and alignment of the allocation whenever BRK
+/// fails, before calling the OOM handler. If it returns null, the OOM handler is called as usual.
+///
+/// # Important!
+///
+/// The returned buffer must be valid for `size` bytes and aligned to `align`. The allocator takes
+/// ownership of it, so it will be reused for other allocations once freed, and must never be
+/// deallocated by the fallback allocator. The fallback allocator is called while holding the
+/// allocator locks, and must thus never allocate through ralloc.
+///
+/// As the buffer joins the pool, parts of it can be freed like any other memory. This is unlike
+/// the `libc_fallback` feature, which takes over when the very first BRK fails (in which case
+/// this fallback allocator is never consulted): libc buffers can only be freed whole (see `free`).
+pub fn set_fallback(fallback: fn(usize, usize) -> *mut u8) {
+    // Logging...
+    log!(NOTE, "Setting the fallback allocator.");
+
+    FALLBACK.store(fallback as *mut (), atomic::Ordering::SeqCst);
+}
+
+/// Compact the allocator of the current thread.
+///
+/// This frees all the free blocks of the thread-local allocator to the global allocator, which
+/// merges adjacent blocks, cleaning up the small non-adjacent blocks accumulating over time. Then,
+/// any adjacent blocks left unmerged in the global pools are merged.
+///
+/// This is safe to call at any point. If the local allocator is in use (e.g. when called while
+/// reserving) or not initialized, it is left alone, and so are locked global shards.
+pub fn compact() {
+    // Logging...
+    log!(CALL, "Compacting the allocator.");
+
+    #[cfg(feature = "tls")]
+    THREAD_ALLOCATOR.with(|thread_alloc| {
+        if let Some(mut thread_alloc_original) = thread_alloc.replace(None) {
+            if let Some(alloc) = thread_alloc_original.get_if_initialized() {
+                // Never pull the rug out under a reservation.
+                if !alloc.is_reserving() {
+                    alloc.flush();
+                }
+            }
+
+            // Put back the original allocator.
+            thread_alloc.replace(Some(thread_alloc_original));
+        }
+    });
+
+    // Merge whatever the cache and other allocators left unmerged. Locked shards (which might be
+    // held by the caller) and uninitialized shards (as initializing them would BRK) are skipped.
+    for shard in GLOBAL_ALLOCATORS.iter() {
+        if let Some(mut guard) = shard.try_lock() {
+            if let Some(global) = guard.get_if_initialized() {
+                global.coalesce_all();
+            }
+        }
+    }
+}
+
+/// Reset the allocator of the current thread.
+///
+/// This frees everything the thread-local allocator holds (including its block pool) to the global
+/// allocator, as if the thread exited, and starts over with a fresh local allocator. This keeps
+/// e.g. back-to-back tests or the jobs of a reused pool thread from interfering with each other.
+///
+/// Only free blocks are affected, so live allocations stay valid. If the local allocator is in use
+/// or not initialized, nothing happens.
+#[cfg(feature = "tls")]
+pub fn reset_thread_allocator() {
+    // Logging...
+    log!(CALL, "Resetting the local allocator.");
+
+    THREAD_ALLOCATOR.with(|thread_alloc| {
+        if let Some(mut thread_alloc_original) = thread_alloc.replace(None) {
+            // Never pull the rug out under a reservation.
+            let reset = thread_alloc_original
+                .get_if_initialized()
+                .map_or(false, |alloc| !alloc.is_reserving());
+
+            if reset {
+                thread_alloc_original.into_inner().free_all();
+                // The thread destructor is already registered, so it must not be registered again.
+                thread_alloc.replace(Some(LazyInit::new(LocalAllocator::new)));
+            } else {
+                // Put back the original allocator.
+                thread_alloc.replace(Some(thread_alloc_original));
+            }
+        }
+    });
+}
+
+/// Mark the allocator active, postponing idle trimming.
+#[inline]
+fn mark_active() {
+    if IDLE_TRIM.load(atomic::Ordering::Relaxed) {
+        ACTIVE.store(true, atomic::Ordering::Relaxed);
+    }
+}
+
+/// Configure idle trimming.
+///
+/// When enabled, the allocator releases free memory to the OS once it has been idle (that is, no
+/// allocations were made) for `config::IDLE_TRIM_TICKS` consecutive calls to `tick`. The global
+/// allocator is trimmed down to `threshold` free bytes.
+///
+/// This is useful for e.g. servers going idle between bursts of requests.
+pub fn set_idle_trim(enabled: bool, threshold: usize) {
+    // Logging...
+    log!(NOTE, "Setting idle trimming to {} (threshold {}).", enabled, threshold);
+
+    IDLE_TRIM_THRESHOLD.store(threshold, atomic::Ordering::SeqCst);
+    IDLE_TICKS.store(0, atomic::Ordering::SeqCst);
+    IDLE_TRIM.store(enabled, atomic::Ordering::SeqCst);
+}
+
+/// Advance the idle timer.
+///
+/// This is meant to be called periodically by the embedder (e.g. from its event loop). If idle
+/// trimming is enabled and the allocator has been idle for long enough, the local allocator of the
+/// calling thread is freed to the global allocator, whose shards are then trimmed (see
+/// `set_idle_trim`).
+pub fn tick() {
+    if !IDLE_TRIM.load(atomic::Ordering::SeqCst) {
+        return;
+    }
+
+    if ACTIVE.swap(false, atomic::Ordering::SeqCst) {
+        // There has been allocations since last tick, so we start over.
+        IDLE_TICKS.store(0, atomic::Ordering::SeqCst);
+
+        return;
+    }
+
+    if IDLE_TICKS.fetch_add(1, atomic::Ordering::SeqCst) + 1 < config::IDLE_TRIM_TICKS {
+        return;
+    }
+
+    // Logging...
+    log!(NOTE, "The allocator is idle, trimming.");
+
+    // Free the local allocator's memory to the global allocator.
+    compact();
+
+    // Only the shard holding the top of the program break can actually release memory, but we
+    // don't know which one that is. Uninitialized shards are skipped, as initializing them would
+    // BRK.
+    for shard in GLOBAL_ALLOCATORS.iter() {
+        if let Some(global) = shard.lock().get_if_initialized() {
+            global.trim(IDLE_TRIM_THRESHOLD.load(atomic::Ordering::SeqCst));
+        }
+    }
 }
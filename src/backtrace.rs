@@ -0,0 +1,96 @@
+//! Frame pointer backtraces.
+//!
+//! This captures the return addresses of the callers by walking the chain of frame pointers, which
+//! requires neither allocation nor unwinding information (and thus works without libbacktrace).
+//! The addresses are left raw for offline symbolization.
+//!
+//! The program (including `ralloc`) must be compiled with `-C force-frame-pointers=yes`. Otherwise
+//! the frame pointer register might hold arbitrary data, in which case the backtrace is cut short
+//! (or empty), since only frames between the current stack frame and the top of the stack (see
+//! `syscalls::stack_top`) are followed. Where the top of the stack is unknown, backtraces are
+//! empty.
+
+use core::mem;
+
+use shim::{config, syscalls};
+
+/// A backtrace.
+///
+/// The return addresses are listed from the innermost frame and out. Unused entries are zero.
+pub type Backtrace = [usize; config::BACKTRACE_DEPTH];
+
+/// Get the frame pointer of the current frame.
+///
+/// `None` is returned on architectures, for which frame pointer walking is not supported.
+#[inline(always)]
+fn frame_pointer() -> Option<usize> {
+    #[cfg(target_arch = "x86_64")]
+    {
+        let fp: usize;
+        unsafe {
+            asm!("mov %rbp, $0" : "=r"(fp) ::: "volatile");
+        }
+
+        Some(fp)
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    {
+        let fp: usize;
+        unsafe {
+            asm!("mov $0, x29" : "=r"(fp) ::: "volatile");
+        }
+
+        Some(fp)
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    {
+        None
+    }
+}
+
+/// Capture the backtrace of the current thread.
+#[inline(never)]
+pub fn capture() -> Backtrace {
+    let mut res = [0; config::BACKTRACE_DEPTH];
+
+    let mut fp = match frame_pointer() {
+        Some(fp) => fp,
+        None => return res,
+    };
+    // The bounds of the frame pointers we are willing to follow. The stack grows downwards, so
+    // the callers' frames are above the locals of this frame, and below the top of the stack.
+    let marker = 0u8;
+    let low = &marker as *const u8 as usize;
+    let high = match syscalls::stack_top() {
+        Some(high) if high > low + 2 * mem::size_of::<usize>() => high,
+        _ => return res,
+    };
+
+    for ret in res.iter_mut() {
+        // Every frame consists of the frame pointer of the caller followed by the return address.
+        if fp < low || fp > high - 2 * mem::size_of::<usize>() || fp % mem::align_of::<usize>() != 0
+        {
+            break;
+        }
+
+        let (next, addr) = unsafe {
+            // The frame lies within the stack, and is aligned, so it can be read.
+            (*(fp as *const usize), *(fp as *const usize).offset(1))
+        };
+
+        if addr == 0 {
+            break;
+        }
+        *ret = addr;
+
+        // The chain must strictly go up the stack, to avoid looping.
+        if next <= fp {
+            break;
+        }
+        fp = next;
+    }
+
+    res
+}
@@ -10,6 +10,9 @@ use prelude::*;
 
 use core::{cmp, fmt, mem, ptr};
 
+#[cfg(feature = "canary")]
+use canary;
+
 /// A contiguous memory block.
 ///
 /// This provides a number of guarantees,
@@ -55,6 +58,18 @@ impl Block {
         }
     }
 
+    /// Create an empty block starting at the empty pointer.
+    ///
+    /// Unlike `empty`, this is a `const fn`, so it can be used to initialize statics (e.g. arrays
+    /// of blocks).
+    #[inline]
+    pub const fn empty_const() -> Block {
+        Block {
+            size: 0,
+            ptr: Pointer::empty(),
+        }
+    }
+
     /// Create an empty block representing the left edge of this block
     #[inline]
     pub fn empty_left(&self) -> Block {
@@ -64,15 +79,18 @@ impl Block {
     /// Create an empty block representing the right edge of this block
     #[inline]
     pub fn empty_right(&self) -> Block {
-        Block {
-            size: 0,
-            ptr: unsafe {
-                // LAST AUDIT: 2016-08-21 (Ticki).
+        Block::empty(self.end_ptr())
+    }
 
-                // By the invariants of this type (the end is addressable),
-                // this conversion isn't overflowing.
-                self.ptr.clone().offset(self.size as isize)
-            },
+    /// Get a pointer to the end of this block, i.e. the first byte after it.
+    #[inline]
+    pub fn end_ptr(&self) -> Pointer<u8> {
+        unsafe {
+            // LAST AUDIT: 2016-08-21 (Ticki).
+
+            // By the invariants of this type (the end is addressable),
+            // this conversion isn't overflowing.
+            self.ptr.clone().offset_bytes(self.size as isize)
         }
     }
 
@@ -89,10 +107,10 @@ impl Block {
         if block.is_empty() {
             Ok(())
         } else if self.left_to(block) {
-            // Since the end of `block` is bounded by the address space, adding
-            // them cannot overflow.
-            self.size += block.pop().size;
+            // An overflowing size is treated like non-adjacent blocks.
+            self.size = self.size.checked_add(block.size).ok_or(())?;
             // We pop it to make sure it isn't aliased.
+            block.pop();
 
             Ok(())
         } else {
@@ -100,6 +118,30 @@ impl Block {
         }
     }
 
+    /// Calculate the size this block would have after merging a run of blocks into it.
+    ///
+    /// The blocks of `others` are merged from left to right (as `merge_right` would), until a block
+    /// is not adjacent to the merged block. Like `merge_right`, empty blocks are skipped. Nothing
+    /// is mutated, so this can be used to decide whether merging is worth it beforehand.
+    #[inline]
+    pub fn merged_size_if_adjacent(&self, others: &[Block]) -> usize {
+        let mut size = self.size;
+
+        for block in others.iter().filter(|x| !x.is_empty()) {
+            // Like `left_to`, the end of the merged block must not overflow.
+            if block < self || block.ptr.diff(&self.ptr) as usize != size {
+                break;
+            }
+
+            size = match size.checked_add(block.size) {
+                Some(x) => x,
+                None => break,
+            };
+        }
+
+        size
+    }
+
     /// Is this block empty/free?
     #[inline]
     pub fn is_empty(&self) -> bool {
@@ -114,7 +156,7 @@ impl Block {
     /// Is this block aligned to `align`?
     #[inline]
     pub fn aligned_to(&self, align: usize) -> bool {
-        self.ptr.get() as usize % align == 0
+        self.ptr.is_aligned_to(align)
     }
 
     /// memcpy the block to another pointer.
@@ -141,19 +183,144 @@ impl Block {
         }
     }
 
+    /// memcpy the block to another pointer, truncating it if needed.
+    ///
+    /// Unlike `copy_to`, the target block may be smaller than the source, in which case only the
+    /// part of the source fitting into it is copied.
+    #[inline]
+    pub fn copy_to_truncating(&self, block: &mut Block) {
+        log!(INTERNAL, "Copying {:?} to {:?} (truncating)", *self, *block);
+
+        unsafe {
+            // From the invariants of `Block`, this copy is well-defined, as it is bounded by both
+            // blocks.
+            ptr::copy_nonoverlapping(
+                self.ptr.get(),
+                block.ptr.get(),
+                cmp::min(self.size, block.size),
+            );
+        }
+    }
+
+    /// memmove `size` bytes starting at `offset` in the block to the start of the block.
+    ///
+    /// The ranges are allowed to overlap.
+    ///
+    /// # Panics
+    ///
+    /// This will panic if the block is smaller than `offset + size`.
+    #[inline]
+    pub fn move_down(&mut self, offset: usize, size: usize) {
+        log!(INTERNAL, "Moving {} bytes at offset {} of {:?} down", size, offset, *self);
+
+        // Bound check.
+        assert!(
+            offset.checked_add(size).map_or(false, |end| end <= self.size),
+            "Block too small."
+        );
+
+        unsafe {
+            // From the invariants of `Block` and the assertion above, this copy is well-defined.
+            ptr::copy(self.ptr.get().offset(offset as isize), self.ptr.get(), size);
+        }
+    }
+
+    /// Copy `bytes` into the block, starting at `offset`.
+    ///
+    /// # Panics
+    ///
+    /// This will panic if the block is smaller than `offset + bytes.len()`.
+    #[cfg(test)]
+    #[inline]
+    pub fn write_at(&mut self, offset: usize, bytes: &[u8]) {
+        log!(INTERNAL, "Writing {} bytes at offset {} of {:?}", bytes.len(), offset, *self);
+
+        // Bound check.
+        assert!(
+            offset.checked_add(bytes.len()).map_or(false, |end| end <= self.size),
+            "Block too small."
+        );
+
+        unsafe {
+            // From the invariants of `Block` and the assertion above, this copy is well-defined.
+            // The bytes cannot overlap the block, since we own it mutably.
+            ptr::copy_nonoverlapping(
+                bytes.as_ptr(),
+                self.ptr.get().offset(offset as isize),
+                bytes.len(),
+            );
+        }
+    }
+
+    /// Get the bytes of this block as a slice.
+    ///
+    /// Empty blocks give an empty slice.
+    ///
+    /// # Safety
+    ///
+    /// The block might be uninitialized, so reading the slice is only well-defined if the memory
+    /// has been written to.
+    #[cfg(test)]
+    #[inline]
+    pub unsafe fn as_slice(&self) -> &[u8] {
+        use core::slice;
+
+        slice::from_raw_parts(self.ptr.get(), self.size)
+    }
+
+    /// Get the bytes of this block as a mutable slice.
+    ///
+    /// Empty blocks give an empty slice.
+    ///
+    /// # Safety
+    ///
+    /// The block might be uninitialized, so reading the slice is only well-defined if the memory
+    /// has been written to.
+    #[cfg(test)]
+    #[inline]
+    pub unsafe fn as_mut_slice(&mut self) -> &mut [u8] {
+        use core::slice;
+
+        slice::from_raw_parts_mut(self.ptr.get(), self.size)
+    }
+
+    /// Zero this memory.
+    ///
+    /// Unlike `sec_zero`, this always zeroes, and uses plain (non-volatile) writes, so it is meant
+    /// for initializing memory (e.g. zeroed allocations), not for erasing secrets.
+    #[inline]
+    pub fn zero(&mut self) {
+        unsafe {
+            // The block is owned by us, so writing to it is safe.
+            ptr::write_bytes(self.ptr.get(), 0, self.size);
+        }
+    }
+
     /// Volatile zero this memory if the `security` feature is set.
+    ///
+    /// With the `canary` feature, a freed marker ending the block is kept, so double frees are
+    /// still detected.
     pub fn sec_zero(&mut self) {
         use core::intrinsics;
 
         if cfg!(feature = "security") {
             log!(INTERNAL, "Zeroing {:?}", *self);
 
+            #[cfg(feature = "canary")]
+            let size = if unsafe { canary::ends_freed(self.ptr.get(), self.size) } {
+                self.size - canary::SIZE
+            } else {
+                self.size
+            };
+            #[cfg(not(feature = "canary"))]
+            let size = self.size;
+
             unsafe {
                 // LAST AUDIT: 2016-08-21 (Ticki).
 
                 // Since the memory of the block is inaccessible (read-wise),
                 // zeroing it is fully safe.
-                intrinsics::volatile_set_memory(self.ptr.get(), 0, self.size);
+                intrinsics::volatile_set_memory(self.ptr.get(), 0, size);
             }
         }
     }
@@ -167,11 +334,21 @@ impl Block {
     }
 
     /// Is this block placed left to the given other block?
+    ///
+    /// If the end of this block overflows the address space, it is not left to any block.
     #[inline]
     pub fn left_to(&self, to: &Block) -> bool {
-        // This won't overflow due to the end being bounded by the address
-        // space.
-        self.size + self.ptr.get() as usize == to.ptr.get() as usize
+        // The distance is only meaningful if `to` lies after this block, as the end would
+        // otherwise overflow.
+        to >= self && to.ptr.diff(&self.ptr) as usize == self.size
+    }
+
+    /// Is this block placed right next to the given other block, on either side?
+    ///
+    /// This is `left_to` in both directions.
+    #[inline]
+    pub fn is_adjacent_to(&self, other: &Block) -> bool {
+        self.left_to(other) || other.left_to(self)
     }
 
     /// Split the block at some position.
@@ -201,17 +378,37 @@ impl Block {
                     // This won't overflow due to the assertion above, ensuring
                     // that it is bounded by the address
                     // space. See the `split_at_mut` source from libcore.
-                    self.ptr.offset(pos as isize)
+                    self.ptr.offset_bytes(pos as isize)
                 },
             },
         )
     }
 
+    /// Get the number of bytes wasted on aligning this block to `align`.
+    ///
+    /// This is the size of the aligner, that is, the first block returned by
+    /// [`align`](#method.align), but without splitting the block.
+    #[inline]
+    pub fn alignment_waste(&self, align: usize) -> usize {
+        // To avoid wasting space on the case where the block is already
+        // aligned, we calculate it modulo `align`.
+        (align - self.ptr.get() as usize % align) % align
+    }
+
     /// Split this block, such that the second block is aligned to `align`.
     ///
     /// Returns an `None` holding the intact block if `align` is out of bounds.
     #[inline]
     pub fn align(&mut self, align: usize) -> Option<(Block, Block)> {
+        self.align_report(align).map(|(aligner, res, _)| (aligner, res))
+    }
+
+    /// Split this block like `align`, and report the number of bytes wasted on the aligner.
+    ///
+    /// The third value is the size of the aligner, which lets profiling tools quantify the
+    /// external fragmentation caused by aligning.
+    #[inline]
+    pub fn align_report(&mut self, align: usize) -> Option<(Block, Block, usize)> {
         // Logging.
         log!(INTERNAL, "Padding {:?} to align {}", self, align);
 
@@ -220,10 +417,7 @@ impl Block {
 
         // Calculate the aligner, which defines the smallest size required as
         // precursor to align the block to `align`.
-        let aligner = (align - self.ptr.get() as usize % align) % align;
-        //                                                 ^^^^^^^^
-        // To avoid wasting space on the case where the block is already
-        // aligned, we calculate it modulo `align`.
+        let aligner = self.alignment_waste(align);
 
         // Bound check.
         if aligner < self.size {
@@ -243,9 +437,10 @@ impl Block {
                         // The aligner is bounded by the size, which itself is
                         // bounded by the address space.
                         // Therefore, this conversion cannot overflow.
-                        old.ptr.offset(aligner as isize)
+                        old.ptr.offset_bytes(aligner as isize)
                     },
                 },
+                aligner,
             ))
         } else {
             // Logging.
@@ -364,6 +559,80 @@ mod test {
         lorem.split(2).0.merge_right(&mut tmp).unwrap();
     }
 
+    #[test]
+    fn test_merge_top() {
+        // These blocks are never dereferenced, so the addresses need not be valid.
+        let (mut below, mut top, mut wrapped) = unsafe {
+            (
+                Block::from_raw_parts(Pointer::new((!0 - 31) as *mut u8), 16),
+                Block::from_raw_parts(Pointer::new((!0 - 15) as *mut u8), 17),
+                Block::from_raw_parts(Pointer::new(1 as *mut u8), 8),
+            )
+        };
+
+        // The end of `top` wraps around to the start of `wrapped`.
+        assert!(!top.left_to(&wrapped));
+        assert!(top.merge_right(&mut wrapped).is_err());
+        assert_eq!(wrapped.size(), 8);
+        assert_eq!(top.merged_size_if_adjacent(&[wrapped]), 17);
+
+        assert!(below.left_to(&top));
+        below.merge_right(&mut top).unwrap();
+        assert_eq!(below.size(), 33);
+        assert!(top.is_empty());
+
+        // Nor does the merged block reach around.
+        let mut wrapped =
+            unsafe { Block::from_raw_parts(Pointer::new(1 as *mut u8), 8) };
+        assert!(below.merge_right(&mut wrapped).is_err());
+    }
+
+    #[test]
+    fn test_is_adjacent_to() {
+        let arr = b"Lorem ipsum dolor sit amet";
+        let block = unsafe {
+            Block::from_raw_parts(
+                Pointer::new(arr.as_ptr() as *mut u8),
+                arr.len(),
+            )
+        };
+
+        let (left, rest) = block.split(6);
+        let (middle, right) = rest.split(6);
+
+        // Left and right.
+        assert!(left.is_adjacent_to(&middle));
+        assert!(middle.is_adjacent_to(&left));
+        assert!(middle.is_adjacent_to(&right));
+        assert!(right.is_adjacent_to(&middle));
+        // Not adjacent.
+        assert!(!left.is_adjacent_to(&right));
+        assert!(!right.is_adjacent_to(&left));
+    }
+
+    #[test]
+    fn test_merged_size_if_adjacent() {
+        let arr = b"Lorem ipsum dolor sit amet";
+        let block = unsafe {
+            Block::from_raw_parts(
+                Pointer::new(arr.as_ptr() as *mut u8),
+                arr.len(),
+            )
+        };
+
+        let (lorem, rest) = block.split(5);
+        let (ipsum, rest) = rest.split(6);
+        let (dolor, rest) = rest.split(6);
+        let (_sit, amet) = rest.split(4);
+        let run = [ipsum, dolor.empty_left(), dolor, amet];
+
+        // Empty blocks are skipped, and the run ends at the gap.
+        assert_eq!(lorem.merged_size_if_adjacent(&run), 17);
+        assert_eq!(lorem.merged_size_if_adjacent(&run[..1]), 11);
+        assert_eq!(run[2].merged_size_if_adjacent(&run[3..]), 6);
+        assert_eq!(lorem.merged_size_if_adjacent(&[]), 5);
+    }
+
     #[test]
     #[should_panic]
     fn test_oob() {
@@ -393,6 +662,168 @@ mod test {
         assert_eq!(arr, [0, 2, 0, 2, 255, 255]);
     }
 
+    #[test]
+    fn test_copy_to_truncating() {
+        let mut arr = [1u8, 2, 3, 4, 0, 0];
+
+        let block = unsafe {
+            Block::from_raw_parts(Pointer::new(&mut arr[0] as *mut u8), 6)
+        };
+
+        let (a, b) = block.split(4);
+        let (mut b, mut c) = b.split(1);
+        a.copy_to_truncating(&mut b);
+        // The target is big enough.
+        b.copy_to_truncating(&mut c);
+
+        assert_eq!(arr, [1, 2, 3, 4, 1, 1]);
+    }
+
+    #[test]
+    fn test_as_slice() {
+        let mut arr = [0u8, 2, 0, 0, 255, 255];
+
+        let block = unsafe {
+            Block::from_raw_parts(Pointer::new(&mut arr[0] as *mut u8), 6)
+        };
+
+        let (a, mut b) = block.split(2);
+        unsafe {
+            b.as_mut_slice()[..2].copy_from_slice(a.as_slice());
+
+            assert_eq!(a.as_slice(), [0, 2]);
+            assert_eq!(b.as_slice(), [0, 2, 255, 255]);
+            assert!(b.empty_right().as_slice().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_zero() {
+        let mut arr = [1u8, 2, 3, 4, 5, 6];
+
+        let block = unsafe {
+            Block::from_raw_parts(Pointer::new(&mut arr[0] as *mut u8), 6)
+        };
+
+        let (a, mut b) = block.split(2);
+        b.zero();
+
+        unsafe {
+            assert_eq!(a.as_slice(), [1, 2]);
+            assert_eq!(b.as_slice(), [0, 0, 0, 0]);
+        }
+    }
+
+    #[test]
+    fn test_empty_const() {
+        const EMPTY: Block = Block::empty_const();
+        const BLOCKS: [Block; 4] = [EMPTY; 4];
+
+        for block in BLOCKS.iter() {
+            assert!(block.is_empty());
+            assert_eq!(*block, Block::empty(Pointer::empty()));
+        }
+    }
+
+    #[test]
+    fn test_move_down() {
+        let mut arr = [0u8, 1, 2, 3, 4, 5];
+
+        let mut block = unsafe {
+            Block::from_raw_parts(Pointer::new(&mut arr[0] as *mut u8), 6)
+        };
+
+        // The ranges overlap.
+        block.move_down(2, 4);
+        assert_eq!(arr, [2, 3, 4, 5, 4, 5]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_move_down_oob() {
+        let mut arr = [0u8; 6];
+
+        let mut block = unsafe {
+            Block::from_raw_parts(Pointer::new(&mut arr[0] as *mut u8), 6)
+        };
+
+        block.move_down(4, 3);
+    }
+
+    #[test]
+    fn test_write_at() {
+        let mut arr = [0u8; 6];
+
+        let mut block = unsafe {
+            Block::from_raw_parts(Pointer::new(&mut arr[0] as *mut u8), 6)
+        };
+
+        block.write_at(1, &[1, 2]);
+        // Writing up to the end is fine.
+        block.write_at(4, &[4, 5]);
+        block.write_at(6, &[]);
+        assert_eq!(arr, [0, 1, 2, 0, 4, 5]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_write_at_oob() {
+        let mut arr = [0u8; 6];
+
+        let mut block = unsafe {
+            Block::from_raw_parts(Pointer::new(&mut arr[0] as *mut u8), 6)
+        };
+
+        block.write_at(5, &[1, 2]);
+    }
+
+    #[test]
+    fn test_alignment_waste() {
+        let arr = b"Lorem ipsum dolor sit amet";
+
+        for align in 1..10 {
+            let mut block = unsafe {
+                Block::from_raw_parts(
+                    Pointer::new(arr.as_ptr() as *mut u8),
+                    arr.len(),
+                )
+            };
+
+            let waste = block.alignment_waste(align);
+            let (aligner, _) = block.align(align).unwrap();
+            assert_eq!(waste, aligner.size());
+        }
+    }
+
+    #[test]
+    fn test_align_report() {
+        let arr = b"Lorem ipsum dolor sit amet";
+
+        for align in 1..10 {
+            let mut block = unsafe {
+                Block::from_raw_parts(
+                    Pointer::new(arr.as_ptr() as *mut u8),
+                    arr.len(),
+                )
+            };
+
+            let waste = block.alignment_waste(align);
+            let (aligner, res, reported) = block.align_report(align).unwrap();
+            assert_eq!(reported, waste);
+            assert_eq!(aligner.size(), waste);
+            assert_eq!(res.size(), arr.len() - waste);
+            assert!(res.aligned_to(align));
+            assert!(block.is_empty());
+        }
+
+        // Out of bounds.
+        let mut block = unsafe { Block::from_raw_parts(Pointer::new(arr.as_ptr() as *mut u8), 1) };
+        if block.alignment_waste(64) > 0 {
+            assert!(block.align_report(64).is_none());
+            assert_eq!(block.size(), 1);
+        }
+    }
+
     #[test]
     fn test_empty_lr() {
         let arr = b"Lorem ipsum dolor sit amet";
@@ -411,4 +842,26 @@ mod test {
         );
         assert_eq!(block.empty_right(), block.split(arr.len()).1);
     }
+
+    #[test]
+    fn test_end_ptr() {
+        let arr = b"Lorem ipsum dolor sit amet";
+        let block = unsafe {
+            Block::from_raw_parts(
+                Pointer::new(arr.as_ptr() as *mut u8),
+                arr.len(),
+            )
+        };
+
+        let (a, b) = block.split(6);
+        for (block, start, size) in [(&a, 0, 6), (&b, 6, arr.len() - 6)].iter() {
+            assert_eq!(block.end_ptr().get() as usize, arr.as_ptr() as usize + start + size);
+            assert_eq!(block.empty_right(), Block::empty(block.end_ptr()));
+        }
+
+        // The end of an empty block is its start.
+        let empty = a.empty_left();
+        assert_eq!(empty.end_ptr(), Pointer::from(empty.empty_left()));
+        assert_eq!(empty.end_ptr().get() as *const u8, arr.as_ptr());
+    }
 }
@@ -3,30 +3,72 @@
 use prelude::*;
 
 use core::ops::Range;
-use core::{mem, ops, ptr};
+use core::{cmp, fmt, mem, ops};
 
 use shim::config;
 
+#[cfg(feature = "size_class_cache")]
+use cache::SizeClassCache;
+use fail;
+use log::internal::BlockLogger;
+
 /// Elements required _more_ than the length as capacity.
 ///
-/// This represents how many elements that are needed to conduct a `reserve` without the
-/// stack overflowing, plus one (representing the new element):
-///
-/// 1. Aligner.
-/// 2. Excessive space.
-/// 3. The old buffer.
-/// 4. The pushed or inserted block.
-///
 /// See assumption 4.
-pub const EXTRA_ELEMENTS: usize = 4;
+pub use shim::config::EXTRA_ELEMENTS;
 
+/// Compile-time assertion of `EXTRA_ELEMENTS` covering the worst-case reserve.
+///
+/// If this fails to compile, `config::EXTRA_ELEMENTS` is smaller than `config::RESERVE_PUSHES`
+/// plus one.
+#[allow(dead_code)]
+const EXTRA_ELEMENTS_SUFFICE: [(); 0] =
+    [(); (EXTRA_ELEMENTS < config::RESERVE_PUSHES + 1) as usize];
+
+#[cfg(any(feature = "alloc_id", feature = "deferred_coalescing"))]
+use core::sync::atomic;
 #[cfg(feature = "alloc_id")]
-use core::sync::atomic::{self, AtomicUsize};
+use core::sync::atomic::AtomicUsize;
+#[cfg(feature = "deferred_coalescing")]
+use core::sync::atomic::AtomicBool;
 /// The bookkeeper ID count.
 ///
 /// This is atomically incremented whenever a new `Bookkeeper` is created.
 #[cfg(feature = "alloc_id")]
 static BOOKKEEPER_ID_COUNTER: AtomicUsize = AtomicUsize::new(0);
+/// Is the coalescing of freed blocks deferred (see `CoalescePolicy`)?
+#[cfg(feature = "deferred_coalescing")]
+static DEFER_COALESCING: AtomicBool = AtomicBool::new(false);
+/// An empty block, for initializing the deferred blocks.
+#[cfg(feature = "deferred_coalescing")]
+const EMPTY_BLOCK: Block = Block::empty_const();
+
+/// The coalescing policy of freeing.
+#[cfg(feature = "deferred_coalescing")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CoalescePolicy {
+    /// Merge freed blocks with their neighbors right away.
+    ///
+    /// This keeps fragmentation low, at the cost of searching the pool on every free.
+    Eager,
+    /// Keep freed blocks aside, and only merge them into the pool when an allocation misses.
+    ///
+    /// Up to `config::DEFERRED_FREES` blocks are kept aside per allocator, and they are reused
+    /// as they are by allocations they fit. This makes churning allocations and frees cheaper.
+    Deferred,
+}
+
+/// Set the coalescing policy of freeing.
+///
+/// The default is `CoalescePolicy::Eager`. Blocks already kept aside are merged into the pool
+/// upon the next allocation missing.
+#[cfg(feature = "deferred_coalescing")]
+pub fn set_coalesce_policy(policy: CoalescePolicy) {
+    // Logging...
+    log!(NOTE, "Setting the coalescing policy to {:?}.", policy);
+
+    DEFER_COALESCING.store(policy == CoalescePolicy::Deferred, atomic::Ordering::Relaxed);
+}
 
 /// The memory bookkeeper.
 ///
@@ -62,16 +104,49 @@ pub struct Bookkeeper {
     ///
     // TODO: Find a replacement for this "hack".
     reserving: bool,
+    /// The minimum remainder split off allocations.
+    ///
+    /// Blocks whose remainder is smaller are handed out whole. This is zero (i.e. allocations are
+    /// exact), except while allocating through `alloc_excess`.
+    min_split: usize,
+    /// The number of blocks removed from the pool since it was last reclaimed.
+    removals: usize,
+    /// The cache of free blocks of common sizes.
+    ///
+    /// Blocks in the cache are not part of the pool (nor its byte count).
+    #[cfg(feature = "size_class_cache")]
+    cache: SizeClassCache,
+    /// The freed blocks kept aside, when coalescing is deferred.
+    ///
+    /// Only the first `deferred_len` blocks are valid. Like the cache, these are not part of the
+    /// pool (nor its byte count).
+    #[cfg(feature = "deferred_coalescing")]
+    deferred: [Block; config::DEFERRED_FREES],
+    /// The number of deferred blocks.
+    #[cfg(feature = "deferred_coalescing")]
+    deferred_len: usize,
     /// The allocator ID.
     ///
     /// This is simply to be able to distinguish allocators in the locks.
     #[cfg(feature = "alloc_id")]
     id: usize,
+    /// The allocator label (e.g. "global" or "local").
+    ///
+    /// This is printed along with the ID in the logs, to tell the kinds of allocators apart.
+    #[cfg(feature = "alloc_id")]
+    label: &'static str,
+    /// The allocator tag.
+    ///
+    /// This is the shard index of a global allocator and the thread ID of the others. It is
+    /// printed after the label (e.g. `local-1234`), to tell the allocators of a kind apart.
+    #[cfg(feature = "alloc_id")]
+    tag: usize,
 }
 
 impl Bookkeeper {
-    /// Create a new bookkeeper with some initial vector.
-    pub fn new(vec: Vec<Block>) -> Bookkeeper {
+    /// Create a new bookkeeper with some initial vector, and a label and tag for the logs.
+    #[cfg_attr(not(feature = "alloc_id"), allow(unused_variables))]
+    pub fn new(vec: Vec<Block>, label: &'static str, tag: usize) -> Bookkeeper {
         // Make sure the assumptions are satisfied.
         debug_assert!(
             vec.capacity() >= EXTRA_ELEMENTS,
@@ -79,20 +154,25 @@ impl Bookkeeper {
         );
         debug_assert!(vec.is_empty(), "Initial vector isn't empty.");
 
-        // TODO: When added use expr field attributes.
-        #[cfg(feature = "alloc_id")]
         let res = Bookkeeper {
             pool: vec,
             total_bytes: 0,
             reserving: false,
+            min_split: 0,
+            removals: 0,
+            #[cfg(feature = "size_class_cache")]
+            cache: SizeClassCache::new(),
+            #[cfg(feature = "deferred_coalescing")]
+            deferred: [EMPTY_BLOCK; config::DEFERRED_FREES],
+            #[cfg(feature = "deferred_coalescing")]
+            deferred_len: 0,
             // Increment the ID counter to get a brand new ID.
+            #[cfg(feature = "alloc_id")]
             id: BOOKKEEPER_ID_COUNTER.fetch_add(1, atomic::Ordering::SeqCst),
-        };
-        #[cfg(not(feature = "alloc_id"))]
-        let res = Bookkeeper {
-            pool: vec,
-            total_bytes: 0,
-            reserving: false,
+            #[cfg(feature = "alloc_id")]
+            label: label,
+            #[cfg(feature = "alloc_id")]
+            tag: tag,
         };
 
         bk_log!(res, "Bookkeeper created.");
@@ -172,6 +252,18 @@ impl Bookkeeper {
         // Logging.
         bk_log!(self, "Iterating over the blocks of the bookkeeper...");
 
+        // Run over all the cached blocks.
+        #[cfg(feature = "size_class_cache")]
+        while let Some(block) = self.cache.pop_any() {
+            f(block);
+        }
+
+        // Run over all the deferred blocks.
+        #[cfg(feature = "deferred_coalescing")]
+        while let Some(block) = self.pop_deferred_any() {
+            f(block);
+        }
+
         // Run over all the blocks in the pool.
         for i in self.pool.pop_iter() {
             f(i);
@@ -194,6 +286,69 @@ impl Bookkeeper {
         })
     }
 
+    /// Get the total bytes of memory in the size class cache.
+    #[cfg(feature = "size_class_cache")]
+    pub fn cached_bytes(&self) -> usize {
+        self.cache.bytes()
+    }
+
+    /// Take any block from the size class cache.
+    ///
+    /// This is used for emptying the cache.
+    #[cfg(feature = "size_class_cache")]
+    pub fn pop_cached(&mut self) -> Option<Block> {
+        self.cache.pop_any()
+    }
+
+    /// Keep a freed block aside, deferring its coalescing.
+    ///
+    /// If the block is empty or there is no room, the block is returned as `Err`.
+    #[cfg(feature = "deferred_coalescing")]
+    pub fn defer(&mut self, mut block: Block) -> Result<(), Block> {
+        if block.is_empty() || self.deferred_len == config::DEFERRED_FREES {
+            return Err(block);
+        }
+
+        // When compiled with `security`, we zero this block, since it can be handed out again
+        // without passing through the pool.
+        block.sec_zero();
+
+        self.deferred[self.deferred_len] = block;
+        self.deferred_len += 1;
+
+        Ok(())
+    }
+
+    /// Take a deferred block, which can hold `size` bytes aligned to `align`.
+    ///
+    /// The returned block might be bigger than `size`.
+    #[cfg(feature = "deferred_coalescing")]
+    pub fn pop_deferred(&mut self, size: usize, align: usize) -> Option<Block> {
+        let ind = self.deferred[..self.deferred_len]
+            .iter()
+            .position(|x| x.size() >= size && x.aligned_to(align))?;
+
+        // Swap remove it.
+        self.deferred_len -= 1;
+        self.deferred.swap(ind, self.deferred_len);
+
+        Some(mem::replace(&mut self.deferred[self.deferred_len], EMPTY_BLOCK))
+    }
+
+    /// Take any deferred block.
+    ///
+    /// This is used for emptying the deferred blocks.
+    #[cfg(feature = "deferred_coalescing")]
+    pub fn pop_deferred_any(&mut self) -> Option<Block> {
+        if self.deferred_len == 0 {
+            None
+        } else {
+            self.deferred_len -= 1;
+
+            Some(mem::replace(&mut self.deferred[self.deferred_len], EMPTY_BLOCK))
+        }
+    }
+
     /// Get the length of the pool.
     pub fn len(&self) -> usize {
         self.pool.len()
@@ -204,6 +359,69 @@ impl Bookkeeper {
         self.total_bytes
     }
 
+    /// Get the size of the largest block in the pool.
+    pub fn largest_block(&self) -> usize {
+        self.pool.iter().map(|x| x.size()).max().unwrap_or(0)
+    }
+
+    /// Iterate over the free blocks of the pool.
+    ///
+    /// The address and size of every non-empty block is yielded in order of address. Blocks kept
+    /// aside (i.e. deferred or cached blocks) are not in the pool, and hence left out.
+    pub fn iter_free<'a>(&'a self) -> impl Iterator<Item = (usize, usize)> + 'a {
+        self.iter_blocks()
+            .map(|x| (Pointer::from(x.empty_left()).get() as usize, x.size()))
+    }
+
+    /// Get the top block of the pool, i.e. the one with the highest address.
+    pub fn top(&self) -> Option<&Block> {
+        self.pool.last()
+    }
+
+    /// Iterate over the non-empty blocks of the pool, in order of address.
+    pub fn iter_blocks<'a>(&'a self) -> impl Iterator<Item = &'a Block> + 'a {
+        self.pool.iter().filter(|x| !x.is_empty())
+    }
+
+    /// Is this bookkeeper currently reserving?
+    pub fn is_reserving(&self) -> bool {
+        self.reserving
+    }
+
+    /// Merge every pair of adjacent blocks of the pool.
+    ///
+    /// Freeing merges adjacent blocks already, but blocks coming back through other routes (e.g.
+    /// from the size class cache or another allocator) might be left unmerged. This restores the
+    /// "no two blocks are adjacent" assumption in a single pass over the (sorted) pool, dropping the
+    /// empty blocks on the way. The byte count stays the same.
+    pub fn coalesce_all(&mut self) {
+        // Logging.
+        bk_log!(self, "Coalescing the pool.");
+
+        // The number of blocks kept, which are moved to the front.
+        let mut len = 0;
+        for i in 0..self.pool.len() {
+            if self.pool[i].is_empty() {
+                continue;
+            }
+
+            if len > 0 && self.pool[len - 1].left_to(&self.pool[i]) {
+                let mut block = self.pool[i].pop();
+                self.pool[len - 1]
+                    .merge_right(&mut block)
+                    .expect("Unable to merge adjacent blocks.");
+            } else {
+                self.pool.swap(len, i);
+                len += 1;
+            }
+        }
+        // Everything after the kept blocks is empty now.
+        self.pool.truncate(len);
+
+        // Check consistency.
+        self.check();
+    }
+
     /// Perform consistency checks.
     ///
     /// This will check for the following conditions:
@@ -211,81 +429,127 @@ impl Bookkeeper {
     /// 1. The list is sorted.
     /// 2. No blocks are adjacent.
     ///
-    /// This is NOOP in release mode.
+    /// This is NOOP in release mode, unless the `force_checks` feature is enabled.
     fn check(&self) {
-        if cfg!(debug_assertions) {
-            // Logging.
-            bk_log!(self, "Checking...");
+        if cfg!(any(debug_assertions, feature = "force_checks")) {
+            self.check_invariants();
+        }
+    }
+
+    /// Assert the consistency of the pool.
+    ///
+    /// Unlike the internal consistency checks, this runs in release mode as well, and can thus be
+    /// used to assert the invariants after a sequence of operations in tests.
+    ///
+    /// # Panics
+    ///
+    /// This panics if the pool is inconsistent (e.g. unsorted).
+    #[cfg(any(test, feature = "force_checks"))]
+    #[allow(dead_code)]
+    pub fn assert_consistent(&self) {
+        self.check_invariants();
+    }
 
-            // The total number of bytes.
-            let mut total_bytes = 0;
-            // Reverse iterator over the blocks.
-            let mut it = self.pool.iter().enumerate().rev();
+    /// Check the invariants of the pool, regardless of the build mode.
+    ///
+    /// See [`check`](#method.check) for the conditions checked.
+    fn check_invariants(&self) {
+        // Logging.
+        bk_log!(self, "Checking...");
+
+        // The total number of bytes.
+        let mut total_bytes = 0;
+        // Reverse iterator over the blocks.
+        let mut it = self.pool.iter().enumerate().rev();
+
+        // Check that the capacity is large enough.
+        assert!(
+            self.reserving || self.pool.len() + EXTRA_ELEMENTS <= self.pool.capacity(),
+            "The capacity should be at least {} more than the length of the pool.",
+            EXTRA_ELEMENTS
+        );
 
-            // Check that the capacity is large enough.
-            assert!(
-                self.reserving || self.pool.len() + EXTRA_ELEMENTS <= self.pool.capacity(),
-                "The capacity should be at least {} more than the length of the pool.",
-                EXTRA_ELEMENTS
-            );
+        if let Some((_, x)) = it.next() {
+            // Make sure there are no leading empty blocks.
+            assert!(!x.is_empty(), "The leading block is empty.");
 
-            if let Some((_, x)) = it.next() {
-                // Make sure there are no leading empty blocks.
-                assert!(!x.is_empty(), "The leading block is empty.");
-
-                total_bytes += x.size();
-
-                let mut next = x;
-                for (n, i) in it {
-                    total_bytes += i.size();
-
-                    // Check if sorted.
-                    assert!(
-                        next >= i,
-                        "The block pool is not sorted at index, {} ({:?} < {:?}).",
-                        n,
-                        next,
-                        i
-                    );
-                    // Make sure no blocks are adjacent.
-                    assert!(
-                        !i.left_to(next) || i.is_empty(),
-                        "Adjacent blocks at index, {} ({:?} and \
-                         {:?})",
-                        n,
-                        i,
-                        next
-                    );
-                    // Make sure an empty block has the same address as its right neighbor.
-                    assert!(
-                        !i.is_empty() || i == next,
-                        "Empty block not adjacent to right neighbor \
-                         at index {} ({:?} and {:?})",
-                        n,
-                        i,
-                        next
-                    );
-
-                    // Set the variable tracking the previous block.
-                    next = i;
-                }
+            total_bytes += x.size();
 
-                // Check for trailing empty blocks.
+            let mut next = x;
+            for (n, i) in it {
+                total_bytes += i.size();
+
+                // Check if sorted.
+                assert!(
+                    next >= i,
+                    "The block pool is not sorted at index, {} ({:?} < {:?}).",
+                    n,
+                    next,
+                    i
+                );
+                // Make sure no blocks are adjacent.
+                assert!(
+                    !i.left_to(next) || i.is_empty(),
+                    "Adjacent blocks at index, {} ({:?} and \
+                     {:?})",
+                    n,
+                    i,
+                    next
+                );
+                // Make sure an empty block has the same address as its right neighbor.
                 assert!(
-                    !self.pool.last().unwrap().is_empty(),
-                    "Trailing empty blocks."
+                    !i.is_empty() || i == next,
+                    "Empty block not adjacent to right neighbor \
+                     at index {} ({:?} and {:?})",
+                    n,
+                    i,
+                    next
                 );
+
+                // Set the variable tracking the previous block.
+                next = i;
             }
 
-            // Make sure the sum is maintained properly.
+            // Check for trailing empty blocks.
             assert!(
-                total_bytes == self.total_bytes,
-                "The sum is not equal to the 'total_bytes' \
-                 field: {} ≠ {}.",
-                total_bytes,
-                self.total_bytes
+                !self.pool.last().unwrap().is_empty(),
+                "Trailing empty blocks."
             );
         }
+
+        // Make sure the sum is maintained properly.
+        assert!(
+            total_bytes == self.total_bytes,
+            "The sum is not equal to the 'total_bytes' \
+             field: {} ≠ {}.",
+            total_bytes,
+            self.total_bytes
+        );
+    }
+}
+
+impl fmt::Debug for Bookkeeper {
+    /// Dump the layout of the block pool.
+    ///
+    /// The pool is rendered like in the logs (e.g. `xxx__xx_`). This writes directly to the
+    /// formatter without allocating or locking, so it can be used while holding the allocator.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut res = f.debug_struct("Bookkeeper");
+
+        #[cfg(feature = "alloc_id")]
+        res.field("label", &self.label)
+            .field("tag", &self.tag)
+            .field("id", &self.id);
+
+        res.field(
+            "pool",
+            &BlockLogger {
+                cur: (),
+                blocks: &self.pool,
+            },
+        ).field("len", &self.pool.len())
+            .field("total_bytes", &self.total_bytes)
+            .finish()
     }
 }
 
@@ -304,7 +568,7 @@ impl Bookkeeper {
 /// Please note that whenever a guarantee is mentioned, it relies on that the all the methods
 /// overwritten are upholding the guarantees specified in the documentation.
 pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
-    /// Allocate _fresh_ space.
+    /// Try to allocate _fresh_ space.
     ///
     /// "Fresh" means that the space is allocated through some breaker (be it SBRK or the global
     /// allocator).
@@ -312,15 +576,60 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
     /// The returned pointer is assumed to be aligned to `align`. If this is not held, all future
     /// guarantees are invalid.
     ///
+    /// `None` is returned if the breaker is out of memory. In that case, the pool must be left
+    /// untouched.
+    ///
     /// # Assumptions
     ///
     /// This is assumed to not modify the order. If some block `b` is associated with index `i`
     /// prior to call of this function, it should be too after it.
-    fn alloc_fresh(&mut self, size: usize, align: usize) -> Block;
+    fn try_alloc_fresh(&mut self, size: usize, align: usize) -> Option<Block>;
+
+    /// Is the block returned by the last call to `try_alloc_fresh` known to be zeroed?
+    ///
+    /// If so, `try_alloc_zeroed` does not zero fresh blocks again. This defaults to `false`, which
+    /// is always sound.
+    fn fresh_is_zeroed(&self) -> bool {
+        false
+    }
 
     /// Called right before new memory is added to the pool.
     fn on_new_memory(&mut self) {}
 
+    /// Are freed blocks put into the size class cache?
+    ///
+    /// This defaults to `true`. If `false`, the cache stays empty, so the pool serves everything.
+    #[cfg(feature = "size_class_cache")]
+    fn caches_blocks(&self) -> bool {
+        true
+    }
+
+    /// Get the coalescing policy of freeing.
+    ///
+    /// This defaults to the policy set through `set_coalesce_policy`.
+    #[cfg(feature = "deferred_coalescing")]
+    fn coalesce_policy(&self) -> CoalescePolicy {
+        if DEFER_COALESCING.load(atomic::Ordering::Relaxed) {
+            CoalescePolicy::Deferred
+        } else {
+            CoalescePolicy::Eager
+        }
+    }
+
+    /// Try to extend a block inplace with free memory of the breaker.
+    ///
+    /// This is called by `realloc`, when the block cannot be extended with the memory of the pool.
+    /// If the memory to the right of the block is free in the breaker (e.g. the global allocator),
+    /// it can be merged into the block, avoiding the copy. On success, the extended block of
+    /// exactly `new_size` bytes is returned, otherwise the _intact_ block is returned as `Err`.
+    ///
+    /// # Assumptions
+    ///
+    /// Like `try_alloc_fresh`, this is assumed to not modify the order of the pool.
+    fn try_extend_fresh(&mut self, block: Block, _new_size: usize) -> Result<Block, Block> {
+        Err(block)
+    }
+
     /// Allocate a chunk of memory.
     ///
     /// This function takes a size and an alignment. From these a fitting block is found, to which
@@ -363,35 +672,139 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
     /// ```
     ///
     /// A block representing the marked area is then returned.
+    ///
+    /// # Failure
+    ///
+    /// The OOM handler is called if no memory could be acquired.
     fn alloc(&mut self, size: usize, align: usize) -> Block {
+        self.try_alloc(size, align).unwrap_or_else(|| fail::oom())
+    }
+
+    /// Try to allocate a chunk of memory.
+    ///
+    /// This is equivalent to `alloc`, except that `None` is returned instead of calling the OOM
+    /// handler, when the breaker is unable to acquire the memory. The pool is left consistent.
+    ///
+    /// Note that reserving space for the block pool itself might still call the OOM handler.
+    fn try_alloc(&mut self, size: usize, align: usize) -> Option<Block> {
         // Logging.
         bk_log!(self, "Allocating {} bytes with alignment {}.", size, align);
 
-        if let Some((n, b)) = self
-            .pool
-            .iter_mut()
-            .enumerate()
-            .filter_map(|(n, i)| {
-                if i.size() >= size {
-                    // Try to split at the aligner.
-                    i.align(align).and_then(|(mut a, mut b)| {
-                        if b.size() >= size {
-                            // Override the old block.
-                            *i = a;
-                            Some((n, b))
-                        } else {
-                            // Put the split block back together and place it back in its spot.
-                            a.merge_right(&mut b).expect("Unable to merge block right.");
-                            *i = a;
-                            None
-                        }
-                    })
-                } else {
-                    None
-                }
-            })
-            .next()
+        if let Some(res) = self.try_alloc_reused(size, align) {
+            Some(res)
+        } else {
+            // No fitting block found. Allocate a new block.
+            self.try_alloc_external(size, align)
+        }
+    }
+
+    /// Allocate a chunk of memory, which might be bigger than requested.
+    ///
+    /// This is equivalent to `alloc`, except that if the remainder of the fitting block would be
+    /// smaller than `config::MIN_SPLIT`, the block is returned whole instead of leaving a tiny stub
+    /// in the pool. The caller must free the block by its true size.
+    ///
+    /// # Failure
+    ///
+    /// The OOM handler is called if no memory could be acquired.
+    fn alloc_excess(&mut self, size: usize, align: usize) -> Block {
+        // Logging.
+        bk_log!(self, "Allocating {} bytes or more with alignment {}.", size, align);
+
+        self.min_split = config::MIN_SPLIT;
+        let res = self.try_alloc(size, align);
+        self.min_split = 0;
+
+        res.unwrap_or_else(|| fail::oom())
+    }
+
+    /// Split the excess off a block allocated for `size` bytes, and free it.
+    ///
+    /// If the excess is smaller than the minimum split (see `alloc_excess`), the block is left
+    /// whole.
+    fn split_excess(&mut self, block: Block, size: usize) -> Block {
+        if block.size() - size < self.min_split {
+            return block;
+        }
+
+        let (res, excessive) = block.split(size);
+        self.free(excessive);
+
+        res
+    }
+
+    /// Allocate a chunk of zeroed memory.
+    ///
+    /// This is equivalent to `alloc`, except that the returned block is zeroed.
+    ///
+    /// # Failure
+    ///
+    /// The OOM handler is called if no memory could be acquired.
+    fn alloc_zeroed(&mut self, size: usize, align: usize) -> Block {
+        self.try_alloc_zeroed(size, align).unwrap_or_else(|| fail::oom())
+    }
+
+    /// Try to allocate a chunk of zeroed memory.
+    ///
+    /// This is equivalent to `try_alloc`, except that the returned block is zeroed. Reused memory
+    /// is always zeroed, but fresh memory is left as is, if `fresh_is_zeroed` says it is zeroed
+    /// already (e.g. pages fresh from the OS), sparing the zeroing.
+    fn try_alloc_zeroed(&mut self, size: usize, align: usize) -> Option<Block> {
+        // Logging.
+        bk_log!(self, "Allocating {} zeroed bytes with alignment {}.", size, align);
+
+        if let Some(mut res) = self.try_alloc_reused(size, align) {
+            res.zero();
+
+            Some(res)
+        } else {
+            let mut res = self.try_alloc_external(size, align)?;
+            if !self.fresh_is_zeroed() {
+                res.zero();
+            }
+
+            Some(res)
+        }
+    }
+
+    /// Allocate a chunk of memory, which was used before.
+    ///
+    /// This takes the block from the size class cache, the deferred blocks, or the pool, but never
+    /// allocates fresh memory. If none fits, `None` is returned.
+    fn try_alloc_reused(&mut self, size: usize, align: usize) -> Option<Block> {
+        // Check the size class cache first.
+        #[cfg(feature = "size_class_cache")]
         {
+            if let Some(block) = self.cache.pop(size, align) {
+                // The cached block might be bigger than needed, so we free the excess.
+                return Some(self.split_excess(block, size));
+            }
+        }
+
+        // Then the deferred blocks. If none fits, this is a miss, and we merge them into the pool,
+        // unless reserving (which must not modify the pool).
+        #[cfg(feature = "deferred_coalescing")]
+        {
+            if let Some(block) = self.pop_deferred(size, align) {
+                return Some(self.split_excess(block, size));
+            } else if !self.is_reserving() {
+                self.coalesce_deferred();
+            }
+        }
+
+        self.try_alloc_pooled(size, align)
+    }
+
+    /// Allocate a chunk of memory from the block pool only.
+    ///
+    /// Unlike `try_alloc`, this never allocates fresh memory: If no block in the pool fits,
+    /// `None` is returned.
+    fn try_alloc_pooled(&mut self, size: usize, align: usize) -> Option<Block> {
+        if let Some(n) = self.find_fit(size, align, config::FIT_STRATEGY) {
+            // Split at the aligner, and override the old block.
+            let (a, b) = self.pool[n].align(align).expect("Unable to align fitting block.");
+            self.pool[n] = a;
+
             // Update the pool byte count.
             self.total_bytes -= b.size();
 
@@ -400,27 +813,122 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
                 let _ = self.remove_at(n);
             }
 
-            // Split and mark the block uninitialized to the debugger.
-            let (res, excessive) = b.mark_uninitialized().split(size);
-
-            // There are many corner cases that make knowing where to insert it difficult
-            // so we search instead.
-            self.free(excessive);
+            // Split and mark the block uninitialized to the debugger. There are many corner cases
+            // that make knowing where to insert the excess difficult, so it is freed (searching)
+            // instead.
+            let res = self.split_excess(b.mark_uninitialized(), size);
 
             // Check consistency.
             self.check();
             debug_assert!(res.aligned_to(align), "Alignment failed.");
             debug_assert!(
-                res.size() == size,
+                res.size() >= size,
                 "Requested space does not match with the returned \
                  block."
             );
 
-            res
+            Some(res)
         } else {
-            // No fitting block found. Allocate a new block.
-            self.alloc_external(size, align)
+            None
+        }
+    }
+
+    /// Allocate a chunk of memory in bounded time.
+    ///
+    /// Only the size class cache (when enabled) and the last block of the pool are considered, so
+    /// unlike `try_alloc_pooled`, this never scans the pool. Neither does it allocate fresh memory
+    /// nor reserve, so if no considered block fits, or the excess cannot be placed without
+    /// reserving, `None` is returned and the pool is left untouched. Like every allocation (but
+    /// `alloc_excess`), the block is split exactly, since the caller frees it by `size`.
+    fn try_alloc_bounded(&mut self, size: usize, align: usize) -> Option<Block> {
+        // Logging.
+        bk_log!(self, "Allocating {} bytes with alignment {} in bounded time.", size, align);
+
+        #[cfg(feature = "size_class_cache")]
+        {
+            if let Some(block) = self.cache.pop(size, align) {
+                // Freeing the excess to the pool isn't bounded, so it has to fit in the cache. If it
+                // doesn't (e.g. it is too small to be cached), the block is put back, and the pool
+                // is tried instead.
+                let (mut res, excessive) = block.split(size);
+                if excessive.is_empty() {
+                    return Some(res);
+                }
+
+                match self.cache.push(excessive) {
+                    Ok(()) => return Some(res),
+                    Err(mut excessive) => {
+                        res.merge_right(&mut excessive)
+                            .expect("Unable to merge the split block back together.");
+                        // The block came from the cache, so it fits back in.
+                        let _ = self.cache.push(res);
+                    }
+                }
+            }
+        }
+
+        let n = match self.pool.len() {
+            0 => return None,
+            len => len - 1,
+        };
+
+        // Check that the last block fits, and that the excess can be pushed without reserving.
+        let aligner = self.pool[n].alignment_waste(align);
+        if aligner >= self.pool[n].size()
+            || self.pool[n].size() - aligner < size
+            || self.pool.len() + 1 + EXTRA_ELEMENTS > self.pool.capacity()
+        {
+            return None;
+        }
+
+        // Split at the aligner, and override the old block.
+        let (a, b) = self.pool[n].align(align).expect("Unable to align fitting block.");
+        self.pool[n] = a;
+
+        // Split and mark the block uninitialized to the debugger.
+        let (res, excessive) = b.mark_uninitialized().split(size);
+        self.total_bytes -= size;
+
+        // The excess is the rightmost block, so it goes to the end of the pool.
+        if !excessive.is_empty() {
+            if self.pool[n].is_empty() {
+                self.pool[n] = excessive.mark_free();
+            } else {
+                self.pool
+                    .push(excessive.mark_free())
+                    .expect("Pushing the excess exceeded the capacity.");
+            }
+        } else if self.pool[n].is_empty() {
+            // For the no trailing empty blocks assumption.
+            let _ = self.remove_at(n);
         }
+
+        debug_assert!(res.aligned_to(align), "Alignment failed.");
+        debug_assert!(
+            res.size() == size,
+            "Requested space does not match with the returned block."
+        );
+
+        Some(res)
+    }
+
+    /// Find the block to allocate a block of some size and alignment from.
+    ///
+    /// This picks a block according to `strategy`, amongst the blocks which can hold `size` bytes
+    /// after being aligned to `align`. The index of the block is returned, or `None` if no block
+    /// fits.
+    fn find_fit(&self, size: usize, align: usize, strategy: config::FitStrategy) -> Option<usize> {
+        let mut fits = self.pool.iter().enumerate().filter(|&(_, i)| {
+            let aligner = i.alignment_waste(align);
+
+            // This mirrors the bound check of `Block::align`.
+            aligner < i.size() && i.size() - aligner >= size
+        });
+
+        match strategy {
+            config::FitStrategy::FirstFit => fits.next(),
+            config::FitStrategy::BestFit => fits.min_by_key(|&(_, i)| i.size()),
+        }.map(|(n, _)| n)
     }
 
     /// Free a memory block.
@@ -470,11 +978,54 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
         // Just logging for the unlucky people debugging this shit. No problem.
         bk_log!(self, "Freeing {:?}...", block);
 
+        // Small blocks go to the size class cache, if there is room.
+        #[cfg(feature = "size_class_cache")]
+        let block = if self.caches_blocks() {
+            match self.cache.push(block) {
+                Ok(()) => return,
+                Err(block) => block,
+            }
+        } else {
+            block
+        };
+
+        // With deferred coalescing, the block is kept aside, if there is room.
+        #[cfg(feature = "deferred_coalescing")]
+        let block = if self.coalesce_policy() == CoalescePolicy::Deferred {
+            match self.defer(block) {
+                Ok(()) => return,
+                Err(block) => block,
+            }
+        } else {
+            block
+        };
+
         // Binary search for the block.
         let bound = self.find_bound(&block);
 
         // Free the given block.
         self.free_bound(bound, block);
+
+        // Trigger the new memory event handler. This might modify the pool, so it must happen
+        // after the search, and the freed block is accounted for (and even released, if on top).
+        self.on_new_memory();
+
+        // The pool might have shrunk through merging.
+        self.reclaim();
+    }
+
+    /// Merge the deferred blocks into the pool.
+    #[cfg(feature = "deferred_coalescing")]
+    fn coalesce_deferred(&mut self) {
+        while let Some(block) = self.pop_deferred_any() {
+            // Logging.
+            bk_log!(self, "Coalescing the deferred {:?}.", block);
+
+            self.on_new_memory();
+
+            let bound = self.find_bound(&block);
+            self.free_bound(bound, block);
+        }
     }
 
     /// Reallocate memory.
@@ -515,8 +1066,27 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
         // Logging.
         bk_log!(self;ind, "Reallocating {:?} to size {} with align {}...", block, new_size, align);
 
-        // Try to do an inplace reallocation.
-        match self.realloc_inplace_bound(ind, block, new_size) {
+        // Inplace reallocation keeps the pointer, so if the block doesn't satisfy the requested
+        // alignment, we have to go for the copying path.
+        let inplace = if block.aligned_to(align) {
+            // If the memory to the right isn't in our pool, it might be free in the breaker.
+            self.realloc_inplace_bound(ind.clone(), block, new_size)
+                .or_else(|block| self.try_extend_fresh(block, new_size))
+        } else {
+            // Logging.
+            bk_log!(self;ind, "{:?} is not aligned to {}, skipping inplace.", block, align);
+
+            Err(block)
+        };
+
+        // If we cannot extend to the right, we try to merge with the block to the left, moving
+        // the data down, which at least avoids a fresh allocation.
+        let inplace = match inplace {
+            Ok(block) => Ok(block),
+            Err(block) => self.realloc_left_bound(ind, block, new_size, align),
+        };
+
+        match inplace {
             Ok(block) => block,
             Err(block) => {
                 // Reallocation cannot be done inplace.
@@ -524,8 +1094,9 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
                 // Allocate a new block with the same size.
                 let mut res = self.alloc(new_size, align);
 
-                // Copy the old data to the new location.
-                block.copy_to(&mut res);
+                // Copy the old data to the new location. Since we might be shrinking (when the
+                // alignment doesn't match), we only copy the part which fits into the new block.
+                block.copy_to_truncating(&mut res);
 
                 // Free the old block.
                 // Allocation may have moved insertion so we search again.
@@ -601,8 +1172,19 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
 
             // Split the block in two segments, the main segment and the excessive segment.
             let (block, excessive) = block.split(new_size);
-            // Free the excessive segment.
-            self.free_bound(ind, excessive);
+            // Free the excessive segment. Small segments go to the size class cache, where they are
+            // reusable right away, while the rest goes to the pool.
+            #[cfg(feature = "size_class_cache")]
+            let excessive = if self.caches_blocks() {
+                self.cache.push(excessive)
+            } else {
+                Err(excessive)
+            };
+            #[cfg(not(feature = "size_class_cache"))]
+            let excessive: Result<(), Block> = Err(excessive);
+            if let Err(excessive) = excessive {
+                self.free_bound(ind, excessive);
+            }
 
             // Make some assertions to avoid dumb bugs.
             debug_assert!(block.size() == new_size, "Block wasn't shrinked properly.");
@@ -614,10 +1196,11 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
 
         // We check if `ind` is the end of the array.
         } else {
-            let mut mergable = false;
-            if let Some(entry) = self.pool.get_mut(ind.end) {
-                mergable = entry.size() + block.size() >= new_size && block.left_to(entry);
-            }
+            // The block can grow inplace, if merging the block at the end of the range (if any) is
+            // enough.
+            let mergable = self.pool.get(ind.end..ind.end + 1).map_or(false, |next| {
+                block.merged_size_if_adjacent(next) >= new_size
+            });
             // Note that we are sure that no segments in the array are adjacent (unless they have size
             // 0). This way we know that we will, at maximum, need one and only one block for extending
             // the current block.
@@ -653,6 +1236,61 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
         Err(block)
     }
 
+    /// Reallocate a block on a known index bound by merging it with the block to the left.
+    ///
+    /// If the block to the left is adjacent and free, and the merged block is large enough and
+    /// aligned to `align`, the data is moved down to the start of the merged block, which is then
+    /// returned. Note that unlike [`realloc_inplace_bound`](#method.realloc_inplace_bound.html),
+    /// the returned block starts at a lower address than `block`, so this is only usable for
+    /// reallocations, which are allowed to move the buffer.
+    ///
+    /// On failure, return `Err(Block)` with the old _intact_ block.
+    fn realloc_left_bound(
+        &mut self,
+        ind: Range<usize>,
+        mut block: Block,
+        new_size: usize,
+        align: usize,
+    ) -> Result<Block, Block> {
+        // Logging.
+        bk_log!(self;ind, "Try reallocating {:?} to size {} by merging left.", block, new_size);
+
+        let mut mergable = false;
+        if ind.start > 0 {
+            let entry = &self.pool[ind.start - 1];
+            mergable = entry.size() + block.size() >= new_size
+                && entry.left_to(&block)
+                && entry.aligned_to(align);
+        }
+
+        if !mergable {
+            return Err(block);
+        }
+
+        // Logging...
+        bk_log!(self;ind, "Merging {:?} to the left.", block);
+
+        let mut res = self.remove_at(ind.start - 1);
+        let offset = res.size();
+
+        // Move the data down. Note that the source and the destination overlap if the left block
+        // is smaller than the data, so we merge first.
+        let size = cmp::min(block.size(), new_size);
+        res.merge_right(&mut block)
+            .expect("Unable to merge block left, to the start of the range.");
+        res.move_down(offset, size);
+
+        // Place the excessive block back. It might be adjacent to the block to the right, so we
+        // go through `free`.
+        let (res, excessive) = res.split(new_size);
+        self.free(excessive);
+
+        // Run a consistency check.
+        self.check();
+
+        Ok(res)
+    }
+
     /// Free a block placed in some index bound.
     ///
     /// This will at maximum insert one element.
@@ -683,8 +1321,11 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
              index."
         );
 
+        // The pool is sorted, so a block at `ind.end` or after can only be adjacent on the right,
+        // and one before `ind.start` only on the left.
+
         // Try to merge it with the block to the right.
-        if ind.end < self.pool.len() && block.left_to(&self.pool[ind.end]) {
+        if ind.end < self.pool.len() && block.is_adjacent_to(&self.pool[ind.end]) {
             // Merge the block with the rightmost block in the range.
             block
                 .merge_right(&mut self.remove_at(ind.end))
@@ -694,13 +1335,14 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
             let size = block.size();
             if ind.start != 0 && self.pool[ind.start - 1].merge_right(&mut block).is_ok() {
                 self.total_bytes += size;
-            }
-            // Check consistency.
-            self.check();
 
-            return;
+                // Check consistency.
+                self.check();
+
+                return;
+            }
         // Dammit, let's try to merge left.
-        } else if ind.start != 0 && self.pool[ind.start - 1].left_to(&block) {
+        } else if ind.start != 0 && block.is_adjacent_to(&self.pool[ind.start - 1]) {
             let size = block.size();
             if self.pool[ind.start - 1].merge_right(&mut block).is_ok() {
                 self.total_bytes += size;
@@ -723,7 +1365,19 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
     /// "Fresh" means that the space is allocated through the breaker.
     ///
     /// The returned pointer is guaranteed to be aligned to `align`.
+    ///
+    /// # Failure
+    ///
+    /// The OOM handler is called if the breaker is out of memory.
     fn alloc_external(&mut self, size: usize, align: usize) -> Block {
+        self.try_alloc_external(size, align).unwrap_or_else(|| fail::oom())
+    }
+
+    /// Try to allocate external ("fresh") space.
+    ///
+    /// This is equivalent to `alloc_external`, except that `None` is returned if the breaker is
+    /// out of memory.
+    fn try_alloc_external(&mut self, size: usize, align: usize) -> Option<Block> {
         // Logging.
         bk_log!(
             self,
@@ -733,7 +1387,7 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
         );
 
         // Break it to me!
-        let res = self.alloc_fresh(size, align);
+        let res = self.try_alloc_fresh(size, align);
 
         // Check consistency.
         self.check();
@@ -752,22 +1406,31 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
 
         // Short-circuit in case on empty block.
         if !block.is_empty() {
+            // A previous push might have reserved, and thereby pushed fresh blocks beyond this one
+            // (e.g. when pushing the aligner and then the excessive block of a fresh allocation).
+            // In that case, we cannot push, so we free it instead.
+            if self.pool.last().map_or(false, |x| &block < x) {
+                // Logging.
+                bk_log!(self;self.pool.len(), "Unable to push {:?}, freeing instead.", block);
+
+                self.free(block);
+
+                return;
+            }
+
             // Trigger the new memory event handler.
             self.on_new_memory();
 
-            // Update the pool byte count.
-            self.total_bytes += block.size();
-
-            // Some assertions...
-            debug_assert!(
-                self.pool.is_empty() || &block > self.pool.last().unwrap(),
-                "Pushing will \
-                 make the list unsorted."
-            );
+            // The size of the pushed block. Note that the pool byte count is only updated once the
+            // block is in the pool, since reserving checks the consistency.
+            let size = block.size();
 
             // We will try to simply merge it with the last block.
             if let Some(x) = self.pool.last_mut() {
                 if x.merge_right(&mut block).is_ok() {
+                    // Update the pool byte count.
+                    self.total_bytes += size;
+
                     return;
                 }
             }
@@ -784,6 +1447,9 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
             // merge with. This has actually happened in testing.
             if let Some(x) = self.pool.last_mut() {
                 if x.merge_right(&mut block).is_ok() {
+                    // Update the pool byte count.
+                    self.total_bytes += size;
+
                     return;
                 }
             }
@@ -798,12 +1464,12 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
 
                 // Make some assertions.
                 debug_assert!(res.is_ok(), "Push failed (buffer full).");
-            } else {
-                // `free` handles the count, so we set it back.
-                // TODO: Find a better way to do so.
-                self.total_bytes -= block.size();
 
-                // Can't push because reserve changed the end of the pool.
+                // Update the pool byte count.
+                self.total_bytes += size;
+            } else {
+                // Can't push because reserve changed the end of the pool. `free` handles the
+                // count.
                 self.free(block);
             }
         }
@@ -826,9 +1492,13 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
             && (self.pool.capacity() < self.pool.len() + EXTRA_ELEMENTS
                 || self.pool.capacity() < min_cap + EXTRA_ELEMENTS)
         {
-            // Reserve a little extra for performance reasons.
+            // Reserve a little extra for performance reasons. For big pools, the capacity is
+            // doubled, so the number of reservations stays logarithmic in the pool size.
             // TODO: This should be moved to some new method.
-            let new_cap = min_cap + EXTRA_ELEMENTS + config::extra_fresh(min_cap);
+            let new_cap = cmp::max(
+                min_cap + EXTRA_ELEMENTS + config::extra_fresh(min_cap),
+                2 * self.pool.capacity(),
+            );
 
             // Catch 'em all.
             debug_assert!(new_cap > self.pool.capacity(), "Reserve shrinks?!");
@@ -843,15 +1513,62 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
             // Go back to the original state.
             self.reserving = false;
 
+            // Move the pool to the new buffer. Note that the pushes made while reserving might
+            // have filled up the old one, so we must not check before this.
+            let old_buf = self.pool.refill(new_buf);
+
             // Check consistency.
             self.check();
 
-            Some(self.pool.refill(new_buf))
+            Some(old_buf)
         } else {
             None
         }
     }
 
+    /// Reclaim the excess capacity of the block pool.
+    ///
+    /// Merging blocks leaves empty entries behind, so after a spike in the number of free blocks,
+    /// the pool stays inflated. This drops the empty entries, and if the unused capacity then
+    /// exceeds `config::MAX_POOL_SLACK` on top of the length (the headroom left by doubling in
+    /// `reserve`), the buffer is shrunk to twice the length, and the end of it is freed.
+    ///
+    /// This scans the pool, so it is only done once per `len / 2` removals, keeping it amortized
+    /// constant time.
+    fn reclaim(&mut self) {
+        if self.reserving || self.removals <= self.pool.len() / 2 + EXTRA_ELEMENTS {
+            return;
+        }
+        self.removals = 0;
+
+        // Small pools are not worth it.
+        if self.pool.capacity() <= config::MAX_POOL_SLACK {
+            return;
+        }
+
+        // Move the empty entries to the end (keeping the order of the rest), and cut them off.
+        let mut len = 0;
+        for i in 0..self.pool.len() {
+            if !self.pool[i].is_empty() {
+                self.pool.swap(len, i);
+                len += 1;
+            }
+        }
+        self.pool.truncate(len);
+
+        if self.pool.capacity() - len > config::MAX_POOL_SLACK + len {
+            // Logging.
+            bk_log!(self, "Reclaiming the block pool (capacity {}).", self.pool.capacity());
+
+            // Keep room for pushing without reserving.
+            let tail = self.pool.shrink(len + cmp::max(len, EXTRA_ELEMENTS));
+            self.free(tail);
+        }
+
+        // Check consistency.
+        self.check();
+    }
+
     /// Insert a block entry at some index.
     ///
     /// If the space is non-empty, the elements will be pushed filling out the empty gaps to the
@@ -937,9 +1654,6 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
         );
         debug_assert!(!block.is_empty(), "Inserting an empty block.");
 
-        // Trigger the new memory event handler.
-        self.on_new_memory();
-
         // Find the next gap, where a used block were.
         let gap = self.pool
             .iter()
@@ -958,43 +1672,32 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
         // The old vector's buffer.
         let mut old_buf = None;
 
-        unsafe {
-            // LAST AUDIT: 2016-08-21 (Ticki).
-
-            // Memmove the elements to make a gap to the new block.
-            ptr::copy(
-                self.pool.get_unchecked(ind) as *const Block,
-                self.pool.get_unchecked_mut(ind + 1) as *mut Block,
-                // The gap defaults to the end of the pool.
-                gap.unwrap_or_else(|| {
-                    // We will only extend the length if we were unable to fit it into the current length.
-
-                    // Loooooooging...
-                    bk_log!(self;ind, "Block pool not long enough for shift. Extending.");
+        let size = block.size();
 
-                    // Reserve space. This does not break order, due to the assumption that
-                    // `reserve` never breaks order.
-                    old_buf = unborrow!(self.reserve(self.pool.len() + 1));
-
-                    // We will move a block into reserved memory but outside of the vec's bounds. For
-                    // that reason, we push an uninitialized element to extend the length, which will
-                    // be assigned in the memcpy.
-                    let res = self.pool.push(mem::uninitialized());
+        if let Some(gap) = gap {
+            // Rotate the empty gap to the index, and replace it by the block (marked free).
+            self.pool[ind..gap + 1].rotate_right(1);
+            self.pool[ind] = block.mark_free();
+        } else {
+            // We will only extend the length if we were unable to fit it into the current length.
 
-                    // Just some assertions...
-                    debug_assert!(res.is_ok(), "Push failed (buffer full).");
+            // Loooooooging...
+            bk_log!(self;ind, "Block pool not long enough for shift. Extending.");
 
-                    self.pool.len() - 1
-                }) - ind,
-            );
+            // Reserve space. This does not break order, due to the assumption that `reserve`
+            // never breaks order.
+            old_buf = unborrow!(self.reserve(self.pool.len() + 1));
 
-            // Update the pool byte count.
-            self.total_bytes += block.size();
+            // Shift the blocks to the right, and insert the block (marked free).
+            let res = self.pool.insert(ind, block.mark_free());
 
-            // Mark it free and set the element.
-            ptr::write(self.pool.get_unchecked_mut(ind), block.mark_free());
+            // Just some assertions...
+            debug_assert!(res.is_ok(), "Insert failed (buffer full).");
         }
 
+        // Update the pool byte count.
+        self.total_bytes += size;
+
         // Free the old buffer, if it exists.
         if let Some(block) = old_buf {
             self.free(block);
@@ -1009,6 +1712,8 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
         // Logging.
         bk_log!(self;ind, "Removing block at {}.", ind);
 
+        self.removals += 1;
+
         let res = if ind + 1 == self.pool.len() {
             let block = self.pool[ind].pop();
             // Make sure there are no trailing empty blocks.
@@ -1053,3 +1758,794 @@ pub trait Allocator: ops::DerefMut<Target = Bookkeeper> {
         res.mark_uninitialized()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use prelude::*;
+
+    use core::{mem, ops};
+
+    use super::*;
+
+    /// The number of bytes left unused after every segment broken from the test arena.
+    ///
+    /// This makes sure that no two segments are adjacent.
+    const GAP: usize = 8;
+
+    /// A test allocator.
+    ///
+    /// This breaks its fresh memory from a fixed arena, and simulates the worst case of the global
+    /// allocator: Every fresh allocation pushes a non-empty aligner and excessive block.
+    struct TestAllocator {
+        /// The inner bookkeeper.
+        inner: Bookkeeper,
+        /// The address of the unused part of the arena.
+        cur: usize,
+        /// The address of the end of the arena.
+        end: usize,
+        /// The number of fresh allocations made.
+        fresh: usize,
+        /// A free block owned by the breaker, which can be used to extend blocks inplace.
+        upstream: Option<Block>,
+        /// The coalescing policy.
+        #[cfg(feature = "deferred_coalescing")]
+        policy: CoalescePolicy,
+        /// Are freed blocks put into the size class cache?
+        ///
+        /// Tests of the pool layout turn this off, so the pool gets every freed block.
+        #[cfg(feature = "size_class_cache")]
+        caching: bool,
+    }
+
+    impl TestAllocator {
+        /// Create a new test allocator breaking from `arena`.
+        ///
+        /// The initial block pool has the minimal capacity.
+        fn new(arena: &mut [u64]) -> TestAllocator {
+            let start = arena.as_mut_ptr() as usize;
+            let pool_size = EXTRA_ELEMENTS * mem::size_of::<Block>();
+
+            TestAllocator {
+                inner: Bookkeeper::new(
+                    unsafe {
+                        Vec::from_raw_parts(
+                            Block::from_raw_parts(Pointer::new(start as *mut u8), pool_size),
+                            0,
+                        )
+                    },
+                    "test",
+                    ::shim::syscalls::thread_id(),
+                ),
+                cur: start + pool_size + GAP,
+                end: start + arena.len() * mem::size_of::<u64>(),
+                fresh: 0,
+                upstream: None,
+                #[cfg(feature = "deferred_coalescing")]
+                policy: CoalescePolicy::Eager,
+                #[cfg(feature = "size_class_cache")]
+                caching: true,
+            }
+        }
+
+        /// Break a segment of `size` bytes from the arena.
+        fn brk(&mut self, size: usize) -> Block {
+            assert!(self.cur + size + GAP <= self.end, "The test arena is exhausted.");
+
+            let res = unsafe { Block::from_raw_parts(Pointer::new(self.cur as *mut u8), size) };
+            self.cur += size + GAP;
+
+            res
+        }
+    }
+
+    impl ops::Deref for TestAllocator {
+        type Target = Bookkeeper;
+
+        fn deref(&self) -> &Bookkeeper {
+            &self.inner
+        }
+    }
+
+    impl ops::DerefMut for TestAllocator {
+        fn deref_mut(&mut self) -> &mut Bookkeeper {
+            &mut self.inner
+        }
+    }
+
+    impl Allocator for TestAllocator {
+        fn try_alloc_fresh(&mut self, size: usize, align: usize) -> Option<Block> {
+            // Make sure the aligner is never empty.
+            let aligner = align - self.cur % align;
+
+            // Fail when the arena is exhausted.
+            if self.cur + aligner + size + 2 * GAP > self.end {
+                return None;
+            }
+
+            self.fresh += 1;
+
+            let segment = self.brk(aligner + size + GAP);
+            let (aligner, rest) = segment.split(aligner);
+            let (res, excessive) = rest.split(size);
+
+            self.push(aligner);
+            self.push(excessive);
+
+            Some(res)
+        }
+
+        fn try_extend_fresh(&mut self, mut block: Block, new_size: usize) -> Result<Block, Block> {
+            if let Some(mut upstream) = self.upstream.take() {
+                if block.left_to(&upstream) && block.size() + upstream.size() >= new_size {
+                    block
+                        .merge_right(&mut upstream)
+                        .expect("Unable to merge the upstream block.");
+
+                    let (res, excessive) = block.split(new_size);
+                    self.upstream = Some(excessive);
+
+                    return Ok(res);
+                }
+
+                self.upstream = Some(upstream);
+            }
+
+            Err(block)
+        }
+
+        #[cfg(feature = "size_class_cache")]
+        fn caches_blocks(&self) -> bool {
+            self.caching
+        }
+
+        #[cfg(feature = "deferred_coalescing")]
+        fn coalesce_policy(&self) -> CoalescePolicy {
+            self.policy
+        }
+    }
+
+    #[test]
+    fn test_extra_elements() {
+        let mut arena = [0u64; 1 << 14];
+        let mut bk = TestAllocator::new(&mut arena);
+
+        // Push non-adjacent blocks, making the pool reserve again and again. Every reserve pushes
+        // an aligner and an excessive block to the old buffer, and then frees the old buffer, so
+        // if `EXTRA_ELEMENTS` is too small, the pool overflows.
+        for _ in 0..256 {
+            let block = bk.brk(16);
+            bk.push(block);
+        }
+
+        // Allocate blocks fitting nowhere in the pool, and then free them again.
+        for i in 1..16 {
+            let block = bk.alloc(64 * i, 16);
+            bk.free(block);
+        }
+
+        assert!(bk.fresh > 0);
+    }
+
+    #[test]
+    fn test_reserve_amortized() {
+        /// The number of blocks to push.
+        const BLOCKS: usize = 1 << 14;
+        /// The arena, which is too big to live on the stack.
+        static mut ARENA: [u64; 1 << 18] = [0; 1 << 18];
+
+        let mut bk = TestAllocator::new(unsafe { &mut ARENA });
+
+        // Push non-adjacent blocks, and count how often the pool is reallocated.
+        let mut reserves = 0;
+        for _ in 0..BLOCKS {
+            let cap = bk.pool.capacity();
+            let block = bk.brk(16);
+            bk.push(block);
+
+            if bk.pool.capacity() != cap {
+                reserves += 1;
+            }
+        }
+
+        assert!(bk.len() >= BLOCKS);
+        assert!(
+            reserves <= BLOCKS.trailing_zeros() as usize,
+            "{} reserves for {} blocks",
+            reserves,
+            BLOCKS
+        );
+        bk.check();
+    }
+
+    #[test]
+    fn test_reclaim() {
+        /// The number of blocks to free, spiking the number of free blocks beyond the ceiling.
+        const BLOCKS: usize = 2 * config::MAX_POOL_SLACK;
+        /// The arena, which is too big to live on the stack.
+        static mut ARENA: [u64; 1 << 18] = [0; 1 << 18];
+
+        let mut bk = TestAllocator::new(unsafe { &mut ARENA });
+        // Small frees bypass the pool when cached.
+        #[cfg(feature = "size_class_cache")]
+        {
+            bk.caching = false;
+        }
+
+        let base = Pointer::from(bk.brk(2 * BLOCKS * 16)).get();
+        let block =
+            |i: usize| unsafe { Block::from_raw_parts(Pointer::new(base.offset(16 * i as isize)), 16) };
+
+        // Free every other block, so none of them can be merged.
+        for i in 0..BLOCKS {
+            bk.free(block(2 * i));
+        }
+        assert!(bk.len() >= BLOCKS);
+        let cap = bk.pool.capacity();
+
+        // Free the rest, merging all of them into one block.
+        for i in 0..BLOCKS {
+            bk.free(block(2 * i + 1));
+        }
+
+        assert!(bk.pool.capacity() < cap);
+        assert!(bk.pool.capacity() - bk.len() <= config::MAX_POOL_SLACK + bk.len());
+        bk.check();
+    }
+
+    #[test]
+    fn test_iter_free() {
+        let mut arena = [0u64; 1 << 10];
+        let mut bk = TestAllocator::new(&mut arena);
+        // Small frees bypass the pool when cached.
+        #[cfg(feature = "size_class_cache")]
+        {
+            bk.caching = false;
+        }
+
+        let (a, rest) = bk.brk(256).split(64);
+        let (_b, c) = rest.split(64);
+        let a_ptr = Pointer::from(a.empty_left()).get() as usize;
+        let c_ptr = Pointer::from(c.empty_left()).get() as usize;
+
+        assert_eq!(bk.iter_free().count(), 0);
+
+        bk.free(c);
+        bk.free(a);
+
+        // The pool might hold other blocks (e.g. an old buffer of the pool itself).
+        assert!(bk.iter_free().any(|x| x == (a_ptr, 64)));
+        assert!(bk.iter_free().any(|x| x == (c_ptr, 128)));
+        assert!(bk.iter_free().all(|(_, size)| size > 0));
+        assert!(bk.iter_free().zip(bk.iter_free().skip(1)).all(|(x, y)| x.0 < y.0));
+    }
+
+    #[test]
+    fn test_realloc_merge_left() {
+        let mut arena = [0u64; 1 << 10];
+        let mut bk = TestAllocator::new(&mut arena);
+        // Small frees bypass the pool when cached.
+        #[cfg(feature = "size_class_cache")]
+        {
+            bk.caching = false;
+        }
+
+        let (left, rest) = bk.brk(256).split(64);
+        let (mut block, _right) = rest.split(64);
+        let left_ptr = Pointer::from(left.empty_left()).get();
+
+        unsafe {
+            for (i, x) in block.as_mut_slice().iter_mut().enumerate() {
+                *x = i as u8;
+            }
+        }
+
+        // The block to the right is not free, so we can only merge to the left.
+        bk.free(left);
+        let fresh = bk.fresh;
+        let res = bk.realloc(block, 100, 8);
+
+        assert_eq!(Pointer::from(res.empty_left()).get(), left_ptr);
+        assert_eq!(res.size(), 100);
+        unsafe {
+            for (i, x) in res.as_slice()[..64].iter().enumerate() {
+                assert_eq!(*x, i as u8);
+            }
+        }
+        assert_eq!(bk.fresh, fresh);
+        bk.check();
+    }
+
+    #[test]
+    fn test_realloc_merge_left_overlap() {
+        let mut arena = [0u64; 1 << 10];
+        let mut bk = TestAllocator::new(&mut arena);
+        // Small frees bypass the pool when cached.
+        #[cfg(feature = "size_class_cache")]
+        {
+            bk.caching = false;
+        }
+
+        // The left block is smaller than the data, so moving it down overlaps.
+        let (left, rest) = bk.brk(256).split(16);
+        let (mut block, _right) = rest.split(64);
+        let left_ptr = Pointer::from(left.empty_left()).get();
+
+        unsafe {
+            for (i, x) in block.as_mut_slice().iter_mut().enumerate() {
+                *x = i as u8;
+            }
+        }
+
+        bk.free(left);
+        let res = bk.realloc(block, 72, 8);
+
+        assert_eq!(Pointer::from(res.empty_left()).get(), left_ptr);
+        assert_eq!(res.size(), 72);
+        unsafe {
+            for (i, x) in res.as_slice()[..64].iter().enumerate() {
+                assert_eq!(*x, i as u8);
+            }
+        }
+        bk.check();
+    }
+
+    #[test]
+    fn test_realloc_shrink_copy() {
+        let mut arena = [0u64; 1 << 10];
+        let mut bk = TestAllocator::new(&mut arena);
+
+        // The block is misaligned, so shrinking it has to copy.
+        let (_, rest) = bk.brk(256).split(1);
+        let (mut block, _right) = rest.split(64);
+
+        unsafe {
+            for (i, x) in block.as_mut_slice().iter_mut().enumerate() {
+                *x = i as u8;
+            }
+        }
+
+        let res = bk.realloc(block, 16, 64);
+        assert!(res.aligned_to(64));
+        assert_eq!(res.size(), 16);
+        unsafe {
+            for (i, x) in res.as_slice().iter().enumerate() {
+                assert_eq!(*x, i as u8);
+            }
+        }
+        bk.check();
+    }
+
+    #[test]
+    fn test_alloc_excess() {
+        let mut arena = [0u64; 1 << 10];
+        let mut bk = TestAllocator::new(&mut arena);
+
+        let block = bk.brk(256);
+        let ptr = Pointer::from(block.empty_left()).get();
+        bk.push(block);
+
+        // The remainder would be too small, so the whole block is handed out.
+        let res = bk.alloc_excess(256 - config::MIN_SPLIT + 1, 1);
+        assert_eq!(Pointer::from(res.empty_left()).get(), ptr);
+        assert_eq!(res.size(), 256);
+        bk.free(res);
+
+        // Big enough remainders are still split off.
+        let res = bk.alloc_excess(256 - config::MIN_SPLIT, 1);
+        assert_eq!(res.size(), 256 - config::MIN_SPLIT);
+        bk.free(res);
+
+        // Other allocations are always exact.
+        let res = bk.alloc(255, 1);
+        assert_eq!(res.size(), 255);
+        bk.free(res);
+        bk.check();
+    }
+
+    #[test]
+    fn test_coalesce_all() {
+        let mut arena = [0u64; 1 << 10];
+        let mut bk = TestAllocator::new(&mut arena);
+
+        // Pushing makes room for more blocks.
+        let block = bk.brk(16);
+        bk.push(block);
+        let (len, total_bytes) = (bk.len(), bk.total_bytes());
+
+        // Split a block in three, and put the parts in the pool without merging, as the cache
+        // might.
+        let (a, rest) = bk.brk(192).split(64);
+        let (b, c) = rest.split(64);
+        let d = bk.brk(32);
+        bk.pool.push(a).unwrap();
+        bk.pool.push(b).unwrap();
+        bk.pool.push(c).unwrap();
+        bk.pool.push(d).unwrap();
+        bk.total_bytes += 224;
+
+        bk.coalesce_all();
+
+        // The three parts are merged, but the block after the gap isn't.
+        assert_eq!(bk.len(), len + 2);
+        assert_eq!(bk.largest_block(), 192);
+        assert_eq!(bk.total_bytes(), total_bytes + 224);
+        bk.assert_consistent();
+    }
+
+    #[test]
+    fn test_try_alloc_bounded() {
+        let mut arena = [0u64; 1 << 10];
+        let mut bk = TestAllocator::new(&mut arena);
+
+        let block = bk.brk(256);
+        bk.push(block);
+        let block = bk.brk(32);
+        let ptr = Pointer::from(block.empty_left()).get();
+        bk.push(block);
+        let (len, total_bytes, fresh) = (bk.len(), bk.total_bytes(), bk.fresh);
+
+        // The old pool buffer was freed to the size class cache, where it would fit.
+        #[cfg(feature = "size_class_cache")]
+        while bk.pop_cached().is_some() {}
+
+        // Only the last block is considered, so this doesn't fit, even though the first does.
+        assert!(bk.try_alloc_bounded(64, 1).is_none());
+        assert_eq!(bk.len(), len);
+        assert_eq!(bk.total_bytes(), total_bytes);
+
+        let block = bk.try_alloc_bounded(16, 1).unwrap();
+        assert_eq!(Pointer::from(block.empty_left()).get(), ptr);
+        assert_eq!(bk.total_bytes(), total_bytes - 16);
+        bk.check();
+
+        // The rest of the last block is taken as a whole.
+        let rest = bk.try_alloc_bounded(16, 1).unwrap();
+        assert_eq!(bk.len(), len - 1);
+        bk.check();
+
+        // Nothing was BRK'd.
+        assert_eq!(bk.fresh, fresh);
+
+        bk.free(block);
+        bk.free(rest);
+        bk.check();
+    }
+
+    #[test]
+    fn test_realloc_extend_fresh() {
+        let mut arena = [0u64; 1 << 10];
+        let mut bk = TestAllocator::new(&mut arena);
+
+        // The memory right of the block is free, but owned by the breaker.
+        let (block, upstream) = bk.brk(128).split(64);
+        let ptr = Pointer::from(block.empty_left()).get();
+        bk.upstream = Some(upstream);
+
+        let block = bk.realloc(block, 100, 1);
+        assert_eq!(Pointer::from(block.empty_left()).get(), ptr);
+        assert_eq!(block.size(), 100);
+        assert_eq!(bk.upstream.as_ref().unwrap().size(), 28);
+
+        // The breaker cannot extend it any further, so this copies.
+        let block = bk.realloc(block, 200, 1);
+        assert!(Pointer::from(block.empty_left()).get() != ptr);
+        bk.free(block);
+        bk.check();
+    }
+
+    #[test]
+    fn test_free_merge_right() {
+        let mut arena = [0u64; 1 << 10];
+        let mut bk = TestAllocator::new(&mut arena);
+        // Small frees bypass the pool when cached.
+        #[cfg(feature = "size_class_cache")]
+        {
+            bk.caching = false;
+        }
+
+        let (_left, rest) = bk.brk(256).split(64);
+        let (block, right) = rest.split(64);
+        let ptr = Pointer::from(block.empty_left()).get();
+
+        // The block to the left is not free, so we can only merge to the right.
+        bk.free(right);
+        bk.free(block);
+
+        assert!(
+            bk.pool
+                .iter()
+                .any(|x| Pointer::from(x.empty_left()).get() == ptr && x.size() == 192)
+        );
+        bk.check();
+    }
+
+    #[test]
+    #[cfg(feature = "size_class_cache")]
+    fn test_size_class_cache() {
+        let mut arena = [0u64; 1 << 12];
+        let mut bk = TestAllocator::new(&mut arena);
+
+        let block = bk.alloc(48, 8);
+        let ptr = Pointer::from(block.empty_left()).get();
+        let (len, total_bytes) = (bk.len(), bk.total_bytes());
+
+        // The block is cached rather than put in the pool.
+        bk.free(block);
+        assert_eq!(bk.len(), len);
+        assert_eq!(bk.total_bytes(), total_bytes);
+
+        // Allocating the same size again takes it from the cache.
+        let block = bk.alloc(48, 8);
+        assert_eq!(Pointer::from(block.empty_left()).get(), ptr);
+        assert_eq!(block.size(), 48);
+        bk.check();
+    }
+
+    #[test]
+    #[cfg(feature = "size_class_cache")]
+    fn test_shrink_to_cache() {
+        let mut arena = [0u64; 1 << 12];
+        let mut bk = TestAllocator::new(&mut arena);
+
+        let block = bk.alloc(10000, 8);
+        let ptr = Pointer::from(block.empty_left()).get();
+        let (len, total_bytes) = (bk.len(), bk.total_bytes());
+
+        // The small tail is cached rather than put in the pool.
+        let block = bk.realloc_inplace(block, 9952).unwrap();
+        assert_eq!(bk.len(), len);
+        assert_eq!(bk.total_bytes(), total_bytes);
+
+        let tail = bk.alloc(48, 8);
+        assert_eq!(Pointer::from(tail.empty_left()).get(), unsafe { ptr.offset(9952) });
+
+        // Too big for the cache, so it goes to the pool.
+        let block = bk.realloc_inplace(block, 8).unwrap();
+        assert!(bk.total_bytes() >= total_bytes + 9944);
+        assert_eq!(block.size(), 8);
+        bk.check();
+    }
+
+    #[test]
+    fn test_find_fit() {
+        let mut arena = [0u64; 1 << 10];
+        let mut bk = TestAllocator::new(&mut arena);
+
+        // Break three non-adjacent blocks, the middle one being misaligned to 32.
+        let seg = bk.brk(1024);
+        let aligner = seg.alignment_waste(32);
+        let (_, seg) = seg.split(aligner);
+        let (a, seg) = seg.split(256);
+        let (_, seg) = seg.split(8);
+        let (b, seg) = seg.split(40);
+        let (_, seg) = seg.split(24);
+        let (c, _) = seg.split(64);
+        let ptrs = [
+            Pointer::from(a.empty_left()).get(),
+            Pointer::from(b.empty_left()).get(),
+            Pointer::from(c.empty_left()).get(),
+        ];
+
+        // Fill the pool directly, as freeing could reserve and thus leave more blocks. This leaves
+        // less than `EXTRA_ELEMENTS` spare capacity, so the pool must not be checked.
+        bk.pool.push(a).unwrap();
+        bk.pool.push(b).unwrap();
+        bk.pool.push(c).unwrap();
+        bk.total_bytes = 256 + 40 + 64;
+
+        let found = |bk: &TestAllocator, size, align, strategy| {
+            bk.find_fit(size, align, strategy)
+                .map(|n| Pointer::from(bk.pool[n].empty_left()).get())
+        };
+
+        // First-fit takes the first block, best-fit the smallest.
+        assert_eq!(found(&bk, 24, 8, config::FitStrategy::FirstFit), Some(ptrs[0]));
+        assert_eq!(found(&bk, 24, 8, config::FitStrategy::BestFit), Some(ptrs[1]));
+        // The middle block is big enough, but too small after being aligned.
+        assert_eq!(found(&bk, 24, 32, config::FitStrategy::BestFit), Some(ptrs[2]));
+        // Nothing fits.
+        assert_eq!(found(&bk, 512, 8, config::FitStrategy::BestFit), None);
+        assert_eq!(found(&bk, 512, 8, config::FitStrategy::FirstFit), None);
+    }
+
+    #[test]
+    fn test_assert_consistent() {
+        let mut arena = [0u64; 1 << 12];
+        let mut bk = TestAllocator::new(&mut arena);
+
+        // Allocate, and then free the blocks piecewise.
+        for i in 0..32 {
+            let size = 16 * (i % 5 + 1);
+            let block = bk.alloc(size, 8);
+            let (a, b) = block.split(size / 2);
+            bk.free(b);
+            bk.assert_consistent();
+            bk.free(a);
+            bk.assert_consistent();
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_assert_consistent_unsorted() {
+        let mut arena = [0u64; 1 << 10];
+        let mut bk = TestAllocator::new(&mut arena);
+
+        let a = bk.brk(16);
+        let b = bk.brk(16);
+        bk.pool.push(b).unwrap();
+        bk.pool.push(a).unwrap();
+        bk.total_bytes = 32;
+
+        bk.assert_consistent();
+    }
+
+    #[test]
+    #[cfg(feature = "log")]
+    fn test_log_label() {
+        use core::sync::atomic::AtomicBool;
+
+        use fail;
+        use shim::config;
+
+        static LABELED: AtomicBool = AtomicBool::new(false);
+        static TAGGED: AtomicBool = AtomicBool::new(false);
+
+        fn handler(s: &str) -> usize {
+            if s == "test" {
+                LABELED.store(true, atomic::Ordering::SeqCst);
+            }
+            if s.parse() == Ok(::shim::syscalls::thread_id()) {
+                TAGGED.store(true, atomic::Ordering::SeqCst);
+            }
+            s.len()
+        }
+
+        let mut arena = [0u64; 1 << 10];
+
+        let _lock = fail::LOG_HANDLER_TEST_LOCK.lock();
+
+        fail::set_log_handler(handler);
+        let bk = TestAllocator::new(&mut arena);
+        fail::set_log_handler(config::log);
+
+        assert!(LABELED.load(atomic::Ordering::SeqCst));
+        assert!(TAGGED.load(atomic::Ordering::SeqCst));
+        assert_eq!(bk.label, "test");
+        assert_eq!(bk.tag, ::shim::syscalls::thread_id());
+    }
+
+    #[test]
+    fn test_debug() {
+        use core::fmt::Write;
+
+        /// A fixed-size buffer to format into.
+        struct Buf {
+            /// The buffer.
+            data: [u8; 256],
+            /// The number of bytes written.
+            len: usize,
+        }
+
+        impl Write for Buf {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                self.data[self.len..self.len + s.len()].copy_from_slice(s.as_bytes());
+                self.len += s.len();
+
+                Ok(())
+            }
+        }
+
+        let mut arena = [0u64; 1 << 10];
+        let mut bk = TestAllocator::new(&mut arena);
+
+        let a = bk.brk(16);
+        let b = bk.brk(32);
+        bk.push(a);
+        bk.push(b);
+
+        let mut buf = Buf {
+            data: [0; 256],
+            len: 0,
+        };
+        write!(buf, "{:?}", *bk).unwrap();
+        let s = core::str::from_utf8(&buf.data[..buf.len]).unwrap();
+
+        let mut tail = Buf {
+            data: [0; 256],
+            len: 0,
+        };
+        write!(tail, ", len: {}, total_bytes: {} }}", bk.len(), bk.total_bytes()).unwrap();
+        let tail = core::str::from_utf8(&tail.data[..tail.len]).unwrap();
+
+        // No blocks are empty, and the pushes might have reserved (and thus pushed more blocks).
+        let pool = &s[s.find("pool: ").unwrap() + 6..s.len() - tail.len()];
+        assert!(s.starts_with("Bookkeeper {"));
+        assert!(s.ends_with(tail));
+        assert_eq!(pool.len(), bk.len());
+        assert!(pool.bytes().all(|x| x == b'x'));
+    }
+
+    #[test]
+    // Small frees bypass the pool when cached.
+    #[cfg(all(feature = "deferred_coalescing", not(feature = "size_class_cache")))]
+    fn test_deferred_coalescing() {
+        let mut arena = [0u64; 1 << 10];
+        let mut bk = TestAllocator::new(&mut arena);
+        bk.policy = CoalescePolicy::Deferred;
+
+        let block = bk.alloc(256, 8);
+        let ptr = Pointer::from(block.empty_left()).get();
+        let (a, rest) = block.split(64);
+        let (b, rest) = rest.split(64);
+        let (c, d) = rest.split(64);
+        let (len, total_bytes, fresh) = (bk.len(), bk.total_bytes(), bk.fresh);
+
+        // The frees don't touch the pool.
+        bk.free(a);
+        bk.free(b);
+        bk.free(c);
+        assert_eq!(bk.len(), len);
+        assert_eq!(bk.total_bytes(), total_bytes);
+
+        // Deferred blocks are reused as they are.
+        let block = bk.alloc(64, 8);
+        assert_eq!(bk.len(), len);
+        bk.free(block);
+
+        // None of the blocks fits, so they are coalesced (possibly along with the aligner to the
+        // left), and the merged block is used.
+        let block = bk.alloc(150, 8);
+        let res = Pointer::from(block.empty_left()).get() as usize;
+        assert!(res <= ptr as usize && res + 150 <= ptr as usize + 192);
+        assert_eq!(bk.fresh, fresh);
+        bk.assert_consistent();
+
+        bk.free(block);
+        bk.free(d);
+    }
+
+    #[test]
+    #[cfg(feature = "deferred_coalescing")]
+    fn test_deferred_full() {
+        let mut arena = [0u64; 1 << 10];
+        let mut bk = TestAllocator::new(&mut arena);
+
+        for _ in 0..config::DEFERRED_FREES {
+            let block = bk.brk(16);
+            assert!(bk.defer(block).is_ok());
+        }
+
+        // There is no room left, and empty blocks are never deferred anyway.
+        let block = bk.brk(16);
+        assert!(bk.defer(block).is_err());
+        assert!(bk.defer(Block::empty_const()).is_err());
+
+        let mut n = 0;
+        while let Some(block) = bk.pop_deferred_any() {
+            assert_eq!(block.size(), 16);
+            n += 1;
+        }
+        assert_eq!(n, config::DEFERRED_FREES);
+    }
+
+    #[test]
+    fn test_try_alloc_exhausted() {
+        let mut arena = [0u64; 1 << 10];
+        let mut bk = TestAllocator::new(&mut arena);
+
+        let block = bk.try_alloc(64, 8).unwrap();
+        bk.free(block);
+        let (len, total_bytes) = (bk.len(), bk.total_bytes());
+
+        // The arena is exhausted, so this must fail without touching the pool.
+        assert!(bk.try_alloc(1 << 16, 8).is_none());
+        assert_eq!(bk.len(), len);
+        assert_eq!(bk.total_bytes(), total_bytes);
+        bk.check();
+
+        // The pool is still usable.
+        let block = bk.try_alloc(16, 8).unwrap();
+        bk.free(block);
+    }
+}
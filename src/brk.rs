@@ -5,7 +5,8 @@
 use prelude::*;
 
 use core::convert::TryInto;
-use core::ptr;
+use core::{cmp, ptr};
+use core::sync::atomic::{self, AtomicUsize};
 
 use shim::{config, syscalls};
 
@@ -14,7 +15,13 @@ use {fail, sync};
 /// The BRK mutex.
 ///
 /// This is used for avoiding data races in multiple allocator.
-static BRK_MUTEX: Mutex<BrkState> = Mutex::new(BrkState { current_brk: None });
+static BRK_MUTEX: Mutex<BrkState> = Mutex::new(BrkState {
+    current_brk: None,
+    high_water: 0,
+    zeroed_from: !0,
+});
+/// The factor, which the extra space given by `config::extra_brk` is multiplied by.
+static EXTRA_MULTIPLIER: AtomicUsize = AtomicUsize::new(1);
 
 /// A cache of the BRK state.
 ///
@@ -22,6 +29,14 @@ static BRK_MUTEX: Mutex<BrkState> = Mutex::new(BrkState { current_brk: None });
 struct BrkState {
     /// The program break's end
     current_brk: Option<Pointer<u8>>,
+    /// The highest program break so far, or zero if the break was never moved.
+    ///
+    /// Like caching `current_brk`, this assumes that ralloc is the only user of BRK, since memory
+    /// given to and taken back from the data segment by others is not tracked.
+    high_water: usize,
+    /// The start of the memory acquired by the last BRK, which was never part of the data segment
+    /// before, and hence is still zeroed (by the OS).
+    zeroed_from: usize,
 }
 
 /// A BRK lock.
@@ -58,6 +73,13 @@ impl BrkLock {
             // Update the program break cache.
             self.state.current_brk = Some(expected_brk.clone());
 
+            // Shrinking the break leaves the data in the remaining part of the page, and the
+            // initial break might be in the middle of the data segment, so only the memory above
+            // both the old break and the highest break so far is untouched.
+            let high_water = cmp::max(self.state.high_water, old_brk.get() as usize);
+            self.state.zeroed_from = high_water;
+            self.state.high_water = cmp::max(high_water, expected_brk.get() as usize);
+
             // Return the old break.
             Ok(old_brk)
         } else {
@@ -73,7 +95,7 @@ impl BrkLock {
     /// If failed, we return the memory.
     pub fn release(&mut self, block: Block) -> Result<(), Block> {
         // Check if we are actually next to the program break.
-        if self.current_brk() == Pointer::from(block.empty_right()) {
+        if self.can_release(&block) {
             // Logging...
             log!(DEBUG, "Releasing {:?} to the OS.", block);
 
@@ -99,6 +121,83 @@ impl BrkLock {
         }
     }
 
+    /// Can a block be released to the OS, i.e. does it end at the program break?
+    pub fn can_release(&mut self, block: &Block) -> bool {
+        self.current_brk() == block.end_ptr()
+    }
+
+    /// Is a block acquired by the last BRK known to be zeroed?
+    ///
+    /// This holds if the block lies entirely in memory, which was added to the data segment by the
+    /// last BRK and never was part of it before. The OS hands out zeroed pages, and shrinking the
+    /// break followed by growing it again gives the old data back, so other blocks might be dirty.
+    pub fn is_zeroed(&mut self, block: &Block) -> bool {
+        let start = Pointer::from(block.empty_left()).get() as usize;
+        let end = block.end_ptr().get() as usize;
+
+        start >= self.state.zeroed_from && end <= self.current_brk().get() as usize
+    }
+
+    /// Give the physical memory of a free block back to the OS.
+    ///
+    /// Unlike `release`, the block stays mapped (and is thus still usable afterwards, reading
+    /// zeros), so this works on blocks anywhere below the program break. Only the page-aligned
+    /// interior of the block is advised.
+    ///
+    /// If the block does not span any whole page or the OS failed, we return `Err(())`.
+    pub fn advise_free(&mut self, block: &Block) -> Result<(), ()> {
+        let (start, end) = whole_pages(block).ok_or_else(|| {
+            // Logging...
+            log!(DEBUG, "{:?} spans no whole pages, unable to advise.", block);
+        })?;
+
+        // Logging...
+        log!(DEBUG, "Advising 0x{:x}[{}] free to the OS.", start, end - start);
+
+        // The block is free, so nothing refers to its content.
+        let res = unsafe { syscalls::madvise(start as *const u8, end - start) };
+
+        if res == 0 {
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+
+    /// Make a free block inaccessible, such that any access to it faults.
+    ///
+    /// Only whole pages can be protected, so just the page-aligned interior of the block is, and
+    /// the partial pages at its ends stay accessible. BRK'd memory cannot be unmapped, so the
+    /// block stays in the address space, and `unprotect` makes it usable again.
+    ///
+    /// If the block does not span any whole page or the OS failed, we return `Err(())`.
+    pub fn protect(&mut self, block: &Block) -> Result<(), ()> {
+        self.set_accessible(block, false)
+    }
+
+    /// Make a block protected through `protect` accessible again.
+    ///
+    /// If the block does not span any whole page or the OS failed, we return `Err(())`.
+    pub fn unprotect(&mut self, block: &Block) -> Result<(), ()> {
+        self.set_accessible(block, true)
+    }
+
+    /// Change the access protection of the page-aligned interior of a block.
+    fn set_accessible(&mut self, block: &Block, accessible: bool) -> Result<(), ()> {
+        let (start, end) = whole_pages(block).ok_or(())?;
+
+        // Logging...
+        log!(DEBUG, "Setting 0x{:x}[{}] accessible: {}.", start, end - start, accessible);
+
+        let res = unsafe { syscalls::mprotect(start as *const u8, end - start, accessible) };
+
+        if res == 0 {
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+
     /// Get the current program break.
     ///
     /// If not available in the cache, requested it from the OS.
@@ -132,11 +231,52 @@ impl BrkLock {
     ///
     /// # Failure
     ///
-    /// This method calls the OOM handler if it is unable to acquire the needed space.
+    /// If the extra space cannot be acquired, this retries without it (see `try_exact_brk`). If
+    /// that fails too, the OOM handler is called.
     // TODO: This method is possibly unsafe.
     pub fn canonical_brk(&mut self, size: usize, align: usize) -> (Block, Block, Block) {
+        self.try_canonical_brk(size, align)
+            .or_else(|| self.try_exact_brk(size, align))
+            .unwrap_or_else(|| fail::oom())
+    }
+
+    /// Try to BRK new space.
+    ///
+    /// This is equivalent to `canonical_brk`, except that `None` is returned instead of calling
+    /// the OOM handler, if unable to acquire the needed space. In that case, the program break is
+    /// left untouched.
+    // TODO: This method is possibly unsafe.
+    pub fn try_canonical_brk(
+        &mut self,
+        size: usize,
+        align: usize,
+    ) -> Option<(Block, Block, Block)> {
         // Calculate the canonical size (extra space is allocated to limit the number of system calls).
-        let brk_size = size + config::extra_brk(size) + align;
+        let extra = config::extra_brk(size)
+            .saturating_mul(EXTRA_MULTIPLIER.load(atomic::Ordering::Relaxed));
+
+        self.brk_with_extra(size, align, extra)
+    }
+
+    /// Try to BRK new space, without extra space.
+    ///
+    /// This is equivalent to `try_canonical_brk`, except that only the space needed for the block
+    /// and its alignment is acquired, so the excessive block is smaller than `align`. This is
+    /// meant as a last resort, when the extra space itself would exhaust the memory.
+    pub fn try_exact_brk(&mut self, size: usize, align: usize) -> Option<(Block, Block, Block)> {
+        self.brk_with_extra(size, align, 0)
+    }
+
+    /// BRK a block of `size` bytes aligned to `align`, followed by `extra` bytes.
+    ///
+    /// See `try_canonical_brk`.
+    fn brk_with_extra(
+        &mut self,
+        size: usize,
+        align: usize,
+        extra: usize,
+    ) -> Option<(Block, Block, Block)> {
+        let brk_size = size.checked_add(extra)?.checked_add(align)?;
 
         // Use SBRK to allocate extra data segment. The alignment is used as precursor for our
         // allocated block. This ensures that it is properly memory aligned to the requested value.
@@ -146,9 +286,8 @@ impl BrkLock {
 
             Block::from_raw_parts(
                 // Important! The conversion is failable to avoid arithmetic overflow-based
-                // attacks.
-                self.sbrk(brk_size.try_into().unwrap())
-                    .unwrap_or_else(|()| fail::oom()),
+                // attacks. Sizes beyond `isize::MAX` can never be BRK'd, so they fail like OOM.
+                self.sbrk(brk_size.try_into().ok()?).ok()?,
                 brk_size,
             )
         }.align(align)
@@ -164,7 +303,7 @@ impl BrkLock {
             "BRK memory leak."
         );
 
-        (alignment_block, res, excessive)
+        Some((alignment_block, res, excessive))
     }
 }
 
@@ -175,6 +314,20 @@ pub fn lock() -> BrkLock {
     }
 }
 
+/// Set the factor, which the extra space of every BRK is multiplied by.
+///
+/// To limit the number of system calls, more memory than needed is BRK'd, as determined by
+/// `config::extra_brk`. This scales that extra space, e.g. batch jobs might want bigger BRKs,
+/// whereas latency-sensitive programs might want smaller ones. `0` means no extra space at all.
+///
+/// The default is `1`. The new factor is used by the next BRK.
+pub fn set_brk_extra_multiplier(n: usize) {
+    // Logging...
+    log!(NOTE, "Setting the BRK extra multiplier to {}.", n);
+
+    EXTRA_MULTIPLIER.store(n, atomic::Ordering::Relaxed);
+}
+
 /// `SBRK` symbol which can coexist with the allocator.
 ///
 /// `SBRK`-ing directly (from the `BRK` syscall or libc) might make the state inconsistent. This
@@ -202,6 +355,25 @@ fn current_brk() -> Pointer<u8> {
     }
 }
 
+/// Get the page-aligned interior of a block.
+///
+/// The start and the end address of the whole pages spanned by the block are returned, or `None`
+/// if it spans none.
+fn whole_pages(block: &Block) -> Option<(usize, usize)> {
+    let start = Pointer::from(block.empty_left()).get() as usize;
+    let end = start + block.size();
+
+    // Shrink the range to page boundaries.
+    let start = (start + config::PAGE_SIZE - 1) & !(config::PAGE_SIZE - 1);
+    let end = end & !(config::PAGE_SIZE - 1);
+
+    if start < end {
+        Some((start, end))
+    } else {
+        None
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -214,6 +386,39 @@ mod test {
         assert!(brk.1 <= brk.2);
     }
 
+    #[test]
+    fn test_extra_multiplier() {
+        set_brk_extra_multiplier(0);
+        let (_, _, excessive) = lock().canonical_brk(20, 1);
+        // Only the precursor space for alignment is left.
+        assert!(excessive.size() <= 1);
+
+        set_brk_extra_multiplier(3);
+        let (_, _, excessive) = lock().canonical_brk(20, 1);
+        assert!(excessive.size() >= 3 * config::extra_brk(20));
+
+        set_brk_extra_multiplier(1);
+    }
+
+    #[test]
+    fn test_exact() {
+        let (aligner, res, excessive) = lock().try_exact_brk(20, 8).unwrap();
+
+        assert_eq!(res.size(), 20);
+        assert!(res.aligned_to(8));
+        // Only the precursor space for alignment is left.
+        assert_eq!(aligner.size() + excessive.size(), 8);
+    }
+
+    #[test]
+    fn test_huge() {
+        let mut brk = lock();
+
+        // This doesn't fit in an `isize`, let alone the address space.
+        assert!(brk.try_canonical_brk(!0 - 16, 1).is_none());
+        assert!(brk.try_canonical_brk(!0 >> 1, 1).is_none());
+    }
+
     #[test]
     fn test_brk_grow_up() {
         unsafe {
@@ -223,4 +428,114 @@ mod test {
             assert!(brk1.get() < brk2.get());
         }
     }
+
+    #[test]
+    #[cfg(feature = "mock_syscalls")]
+    fn test_mock_oom() {
+        let mut brk = lock();
+        let cur = brk.current_brk();
+
+        // The simulated address space is exhausted.
+        assert!(brk.try_canonical_brk(config::MOCK_ARENA_SIZE, 1).is_none());
+        assert!(brk.current_brk() == cur);
+    }
+
+    #[test]
+    #[cfg(feature = "mock_syscalls")]
+    fn test_mock_release() {
+        let mut brk = lock();
+        let (_, _, excessive) = brk.canonical_brk(20, 1);
+        let cur = brk.current_brk().get() as usize;
+        let size = excessive.size();
+
+        // The excessive block is at the top, so it can be released.
+        assert!(brk.release(excessive).is_ok());
+        assert_eq!(brk.current_brk().get() as usize, cur - size);
+    }
+
+    #[test]
+    fn test_zeroed() {
+        let mut brk = lock();
+        let (_, mut res, excessive) = brk.try_exact_brk(64, 1).unwrap();
+        let ptr = Pointer::from(res.empty_left()).get() as usize;
+
+        assert!(brk.is_zeroed(&res));
+        unsafe {
+            assert!(res.as_slice().iter().all(|&x| x == 0));
+        }
+        res.write_at(0, &[0xFF; 64]);
+
+        // Give the block back, and take it again. Nothing else moves the break while the lock is
+        // held, so the same memory is handed out, which is dirty now.
+        assert!(brk.release(excessive).is_ok());
+        assert!(brk.release(res).is_ok());
+        let (_, res, _) = brk.try_exact_brk(64, 1).unwrap();
+        assert_eq!(Pointer::from(res.empty_left()).get() as usize, ptr);
+        assert!(!brk.is_zeroed(&res));
+    }
+
+    #[test]
+    fn test_protect() {
+        let mut brk = lock();
+        let (_, res, _) = brk.canonical_brk(4 * config::PAGE_SIZE, config::PAGE_SIZE);
+
+        // Cut off a bit of the first page, which must stay accessible.
+        let (mut head, mut block) = res.split(1);
+        assert!(brk.protect(&block).is_ok());
+
+        head.write_at(0, &[0xFF]);
+        // The rest of the first page is not a whole page.
+        block.write_at(0, &[0xFF; config::PAGE_SIZE - 1]);
+
+        assert!(brk.unprotect(&block).is_ok());
+        unsafe {
+            for x in block.as_mut_slice() {
+                *x = 0xFF;
+            }
+        }
+
+        // Less than a page cannot be protected.
+        assert!(brk.protect(&head).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "mock_syscalls")]
+    fn test_mock_advise_free() {
+        let mut brk = lock();
+        let (_, mut res, _) = brk.canonical_brk(4 * config::PAGE_SIZE, config::PAGE_SIZE);
+
+        unsafe {
+            for x in res.as_mut_slice() {
+                *x = 0xFF;
+            }
+        }
+
+        // Cut off a bit of the first page, which must be left untouched.
+        let (head, block) = res.split(1);
+        assert!(brk.advise_free(&block).is_ok());
+
+        unsafe {
+            assert_eq!(head.as_slice(), [0xFF]);
+            assert!(block.as_slice()[..config::PAGE_SIZE - 1].iter().all(|&x| x == 0xFF));
+            assert!(block.as_slice()[config::PAGE_SIZE - 1..].iter().all(|&x| x == 0));
+        }
+
+        // Less than a page cannot be advised.
+        assert!(brk.advise_free(&head).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "mock_syscalls")]
+    fn test_mock_align() {
+        let mut brk = lock();
+
+        for &align in &[1, 2, 8, 64, 4096, 1 << 16] {
+            let (aligner, res, excessive) = brk.canonical_brk(30, align);
+
+            assert!(res.aligned_to(align));
+            assert_eq!(res.size(), 30);
+            assert!(aligner.left_to(&res));
+            assert!(res.left_to(&excessive));
+        }
+    }
 }
@@ -0,0 +1,355 @@
+//! Segregated free lists.
+//!
+//! This keeps free blocks of common (small) sizes in separate lists by size class, in front of the
+//! general block pool, so that allocating such sizes does not require searching the pool.
+//!
+//! The classes are spaced geometrically with `2^k` classes per power of two (see
+//! `config::SIZE_CLASS_CACHE_STEPS_LOG2`), e.g. with `k = 0`, class `n` holds the blocks of sizes
+//! `2^n` through `2^(n + 1) - 1`. Any block of a class fits an allocation of size up to the lower
+//! bound of the class. The lists are intrusive: Every cached block holds a header with the address
+//! of the next block in the list and its own size. Hence, blocks smaller than the header are never
+//! cached.
+
+use prelude::*;
+
+use core::{cmp, mem, ptr};
+
+use shim::config;
+
+/// The header written to the start of every cached block.
+#[derive(Clone, Copy)]
+struct Header {
+    /// The address of the next block in the list, or zero if this is the last block.
+    next: usize,
+    /// The size of this block.
+    size: usize,
+}
+
+/// The size class of the smallest cacheable blocks.
+///
+/// This is the smallest class, which can hold the header.
+#[inline]
+fn min_class() -> usize {
+    class_fitting(mem::size_of::<Header>())
+}
+
+/// Get the size class a block of some size belongs to.
+///
+/// This rounds down to the lower bound of the class. `size` must be non-zero.
+#[inline]
+fn size_to_class(size: usize) -> usize {
+    class_with_steps(size, config::SIZE_CLASS_CACHE_STEPS_LOG2)
+}
+
+/// Get the size class of some size with `2^k` classes per power of two.
+///
+/// With `k = 0`, `2^n` through `2^(n + 1) - 1` is class `n`.
+#[inline]
+fn class_with_steps(size: usize, k: usize) -> usize {
+    let steps = 1 << k;
+
+    // The sizes below `2^k` are too small to split, so they get a class each.
+    if size < steps {
+        return size - 1;
+    }
+
+    let log = mem::size_of::<usize>() * 8 - 1 - size.leading_zeros() as usize;
+    // The step within the power of two, i.e. the `k` bits following the most significant one.
+    let step = (size >> (log - k)) & (steps - 1);
+
+    (log - k) * steps + step + steps - 1
+}
+
+/// Get the smallest size class, all of whose blocks fit some size.
+///
+/// This rounds up, i.e. the size maps to the class, whose lower bound is the smallest at least
+/// `size`.
+#[inline]
+fn class_fitting(size: usize) -> usize {
+    if size <= 1 {
+        0
+    } else {
+        size_to_class(size - 1) + 1
+    }
+}
+
+/// A cache of free blocks, segregated by size class.
+pub struct SizeClassCache {
+    /// The address of the first block of every class, or zero if the class is empty.
+    heads: [usize; config::SIZE_CLASS_CACHE_CLASSES],
+    /// The number of blocks of every class.
+    lens: [usize; config::SIZE_CLASS_CACHE_CLASSES],
+    /// The total size of the cached blocks.
+    bytes: usize,
+}
+
+impl SizeClassCache {
+    /// Create a new, empty cache.
+    pub const fn new() -> SizeClassCache {
+        SizeClassCache {
+            heads: [0; config::SIZE_CLASS_CACHE_CLASSES],
+            lens: [0; config::SIZE_CLASS_CACHE_CLASSES],
+            bytes: 0,
+        }
+    }
+
+    /// Get the total size of the cached blocks.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Cache a free block.
+    ///
+    /// If the block is too small or too big for any class, or its class is full, the block is
+    /// returned as `Err`.
+    pub fn push(&mut self, mut block: Block) -> Result<(), Block> {
+        if block.size() < mem::size_of::<Header>() {
+            return Err(block);
+        }
+
+        let class = size_to_class(block.size());
+        if class >= config::SIZE_CLASS_CACHE_CLASSES
+            || self.lens[class] >= config::SIZE_CLASS_CACHE_LEN
+        {
+            return Err(block);
+        }
+
+        // When compiled with `security`, we zero this block.
+        block.sec_zero();
+
+        let header = Header {
+            next: self.heads[class],
+            size: block.size(),
+        };
+        let ptr = Pointer::from(block.mark_free()).get();
+
+        unsafe {
+            // The block is free, so we own it, and it is big enough to hold the header. Blocks
+            // have no alignment, hence the unaligned write.
+            ptr::write_unaligned(ptr as *mut Header, header);
+        }
+
+        self.heads[class] = ptr as usize;
+        self.lens[class] += 1;
+        self.bytes += header.size;
+
+        Ok(())
+    }
+
+    /// Take a cached block, which can hold `size` bytes aligned to `align`.
+    ///
+    /// Only the first blocks of the class of `size` (whose blocks might be too small) and of the
+    /// smallest class fitting `size` are considered, so `None` might be returned even though some
+    /// cached block fits. The returned block might be bigger than `size`.
+    pub fn pop(&mut self, size: usize, align: usize) -> Option<Block> {
+        // Programs tend to allocate the same sizes over and over, so a freed block of exactly the
+        // right size is likely to be first in its class.
+        if size >= mem::size_of::<Header>() {
+            let class = size_to_class(size);
+            if self.fits(class, size, align) {
+                return Some(self.pop_class(class));
+            }
+        }
+
+        let class = cmp::max(class_fitting(size), min_class());
+        if self.fits(class, size, align) {
+            Some(self.pop_class(class))
+        } else {
+            None
+        }
+    }
+
+    /// Does the first block of some class fit `size` bytes aligned to `align`?
+    ///
+    /// Unaligned blocks are left for the general path, so they never fit.
+    fn fits(&self, class: usize, size: usize, align: usize) -> bool {
+        if class >= config::SIZE_CLASS_CACHE_CLASSES || self.heads[class] == 0 {
+            return false;
+        }
+
+        let header = unsafe {
+            // The header was written when the block was cached.
+            ptr::read_unaligned(self.heads[class] as *const Header)
+        };
+
+        header.size >= size && self.heads[class] % align == 0
+    }
+
+    /// Take any cached block.
+    ///
+    /// This is used for emptying the cache.
+    pub fn pop_any(&mut self) -> Option<Block> {
+        (0..config::SIZE_CLASS_CACHE_CLASSES)
+            .find(|&class| self.heads[class] != 0)
+            .map(|class| self.pop_class(class))
+    }
+
+    /// Take the first block of a non-empty class.
+    fn pop_class(&mut self, class: usize) -> Block {
+        debug_assert!(self.heads[class] != 0, "Popping from an empty class.");
+
+        let ptr = self.heads[class] as *mut u8;
+        let header = unsafe {
+            // The header was written when the block was cached.
+            ptr::read_unaligned(ptr as *const Header)
+        };
+
+        self.heads[class] = header.next;
+        self.lens[class] -= 1;
+        self.bytes -= header.size;
+
+        let mut block = unsafe {
+            // The block was given to us by `push`, which checked the size.
+            Block::from_raw_parts(Pointer::new(ptr), header.size)
+        };
+        // Don't leak the header to the user.
+        block.sec_zero();
+
+        block.mark_uninitialized()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use prelude::*;
+
+    use super::*;
+
+    /// Get a block of some size at some offset of `arena`.
+    fn block(arena: &mut [u64], offset: usize, size: usize) -> Block {
+        unsafe {
+            Block::from_raw_parts(
+                Pointer::new(arena.as_mut_ptr()).offset_bytes(offset as isize).cast(),
+                size,
+            )
+        }
+    }
+
+    /// Get the lower bound of a size class with `2^k` classes per power of two.
+    fn lower_bound(class: usize, k: usize) -> usize {
+        (1..)
+            .find(|&size| class_with_steps(size, k) == class)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_classes() {
+        assert_eq!(class_with_steps(1, 0), 0);
+        assert_eq!(class_with_steps(16, 0), 4);
+        assert_eq!(class_with_steps(31, 0), 4);
+        assert_eq!(class_with_steps(32, 0), 5);
+
+        if config::SIZE_CLASS_CACHE_STEPS_LOG2 == 0 {
+            assert_eq!(class_fitting(1), 0);
+            assert_eq!(class_fitting(16), 4);
+            assert_eq!(class_fitting(17), 5);
+            assert_eq!(class_fitting(32), 5);
+        }
+    }
+
+    #[test]
+    fn test_classes_steps() {
+        // 4 classes per power of two, like jemalloc.
+        let bounds: [usize; 12] = [16, 20, 24, 28, 32, 40, 48, 56, 64, 80, 96, 112];
+        for (&a, &b) in bounds.iter().zip(bounds[1..].iter()) {
+            let class = class_with_steps(a, 2);
+            assert_eq!(lower_bound(class, 2), a);
+            assert_eq!(class_with_steps(b - 1, 2), class);
+            assert_eq!(class_with_steps(b, 2), class + 1);
+        }
+
+        assert_eq!(class_with_steps(1, 2), 0);
+        assert_eq!(class_with_steps(3, 2), 2);
+        assert_eq!(class_with_steps(4, 2), 3);
+        assert_eq!(class_with_steps(1000, 2), class_with_steps(896, 2));
+        assert_eq!(class_with_steps(1024, 2), class_with_steps(1023, 2) + 1);
+    }
+
+    #[test]
+    fn test_classes_waste() {
+        for k in 0..4 {
+            for size in 1..5000 {
+                let class = class_with_steps(size, k);
+                let bound = lower_bound(class, k);
+                let next = lower_bound(class + 1, k);
+
+                // The classes partition the sizes.
+                assert!(bound <= size && size < next);
+                // The block serving an allocation exceeds it by less than a `2^k`th.
+                assert!((next - bound) << k <= cmp::max(bound, 1 << k));
+            }
+        }
+
+        let k = config::SIZE_CLASS_CACHE_STEPS_LOG2;
+        for size in 2..5000 {
+            // The smallest class fitting the size has the smallest lower bound above it.
+            let bound = lower_bound(class_fitting(size), k);
+            assert!(bound >= size);
+            assert!((bound - size) << k < cmp::max(size, 1 << k));
+        }
+    }
+
+    #[test]
+    fn test_push_pop() {
+        let mut arena = [0u64; 256];
+        let mut cache = SizeClassCache::new();
+
+        // Too small for the header.
+        assert!(cache.push(block(&mut arena, 0, 1)).is_err());
+
+        cache.push(block(&mut arena, 0, 48)).unwrap();
+        cache.push(block(&mut arena, 64, 40)).unwrap();
+
+        // 40 and 48 are in the class of 32 through 63 bytes, but only the first block is
+        // considered, and 40 is too small for 44 bytes.
+        assert!(cache.pop(44, 1).is_none());
+        assert_eq!(cache.pop(32, 8).unwrap().size(), 40);
+        assert_eq!(cache.pop(44, 1).unwrap().size(), 48);
+        assert!(cache.pop(32, 1).is_none());
+
+        // Blocks of the next class always fit.
+        cache.push(block(&mut arena, 128, 100)).unwrap();
+        assert_eq!(cache.pop(33, 1).unwrap().size(), 100);
+
+        assert!(cache.pop_any().is_none());
+    }
+
+    #[test]
+    fn test_pop_misaligned() {
+        let mut arena = [0u64; 32];
+        let mut cache = SizeClassCache::new();
+
+        // Place the block right between two multiples of 16.
+        let offset = if arena.as_ptr() as usize % 16 == 0 { 8 } else { 0 };
+        cache.push(block(&mut arena, offset, 64)).unwrap();
+
+        // The block is not aligned to 16, so it stays cached.
+        assert!(cache.pop(64, 16).is_none());
+        assert_eq!(cache.pop(64, 8).unwrap().size(), 64);
+    }
+
+    #[test]
+    fn test_class_full() {
+        let mut arena = [0u64; 4096];
+        let mut cache = SizeClassCache::new();
+
+        for i in 0..config::SIZE_CLASS_CACHE_LEN {
+            cache.push(block(&mut arena, 32 * i, 32)).unwrap();
+        }
+        assert!(
+            cache
+                .push(block(&mut arena, 32 * config::SIZE_CLASS_CACHE_LEN, 32))
+                .is_err()
+        );
+
+        assert_eq!(cache.bytes(), 32 * config::SIZE_CLASS_CACHE_LEN);
+
+        let mut n = 0;
+        while let Some(block) = cache.pop_any() {
+            assert_eq!(block.size(), 32);
+            n += 1;
+        }
+        assert_eq!(n, config::SIZE_CLASS_CACHE_LEN);
+        assert_eq!(cache.bytes(), 0);
+    }
+}
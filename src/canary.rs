@@ -0,0 +1,193 @@
+//! Heap overflow canaries.
+//!
+//! Every allocation is followed by a few extra bytes holding a magic value, the canary, which is
+//! checked when the allocation is freed or reallocated. If the caller wrote past the end of its
+//! buffer, the canary is (most likely) overwritten, and the process is aborted.
+//!
+//! The caller passes the requested size on free, so the canary is always found right after the
+//! buffer, at `ptr + size`, and the true size of the underlying block is `size + SIZE`. This rules
+//! out partial frees, since a part of a buffer isn't followed by a canary, so it is aborted on like
+//! an overflow.
+//!
+//! The canary is chosen once per process (see `value`), so an overflow cannot just write a
+//! well-known value back. With the `secure_random` feature, it is seeded by the OS.
+//!
+//! When the buffer is freed, the canary is replaced by its complement (see `freed`), so freeing the
+//! buffer again (before its memory is reused) finds the freed marker, and is reported as a double
+//! free. Neither is ever zero, so an overflow writing zeros is reported as such. With the
+//! `security` feature, freed blocks are zeroed, but a trailing marker is kept (see `ends_freed`),
+//! so a double free is still reported as such.
+
+use core::sync::atomic::{self, AtomicUsize};
+use core::{intrinsics, mem, ptr};
+
+#[cfg(feature = "secure_random")]
+use shim::syscalls;
+
+/// The number of bytes reserved for the canary after every allocation.
+pub const SIZE: usize = 4;
+
+/// The magic value the canary is derived from.
+const MAGIC: u32 = 0xCAFEBABE;
+
+/// The canary of this process, or zero if not yet chosen.
+static CANARY: AtomicUsize = AtomicUsize::new(0);
+
+/// Get the canary of this process.
+///
+/// The canary is derived from `seed` on first use, and cached afterwards.
+#[inline]
+fn value() -> u32 {
+    let cur = CANARY.load(atomic::Ordering::Relaxed);
+    if cur != 0 {
+        return cur as u32;
+    }
+
+    let seed = seed();
+    // Fibonacci hashing. The upper bits are the best mixed ones. The low bits are set to `01`, so
+    // neither the canary nor its complement (the freed marker) is ever zero.
+    let hash = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15u64 as usize);
+    let new = (hash >> (8 * mem::size_of::<usize>() - 32)) as u32 & !0b11 | 0b01;
+
+    // Another thread might have chosen first, in which case its canary is used.
+    match CANARY.compare_exchange(
+        0,
+        new as usize,
+        atomic::Ordering::Relaxed,
+        atomic::Ordering::Relaxed,
+    ) {
+        Ok(_) => new,
+        Err(cur) => cur as u32,
+    }
+}
+
+/// Get the marker replacing the canary of a freed buffer.
+///
+/// This is the complement of the canary, so it never equals the canary, and it is never zero.
+#[inline]
+fn freed() -> u32 {
+    !value()
+}
+
+/// Get a seed for the canary.
+///
+/// With the `secure_random` feature, the seed is taken from the OS (without blocking). Otherwise,
+/// or if that fails, it is derived from the addresses of a static and of the stack, which are
+/// randomized by ASLR (if enabled).
+#[cold]
+fn seed() -> usize {
+    #[cfg(feature = "secure_random")]
+    {
+        let mut buf = [0u8; mem::size_of::<usize>()];
+        if syscalls::getrandom(&mut buf) == buf.len() {
+            return unsafe { ptr::read_unaligned(buf.as_ptr() as *const usize) };
+        }
+
+        // Logging...
+        log!(WARNING, "getrandom failed, deriving the canary from addresses.");
+    }
+
+    let marker = 0u8;
+    (&CANARY as *const AtomicUsize as usize)
+        ^ (&marker as *const u8 as usize).rotate_left(16)
+        ^ MAGIC as usize
+}
+
+/// Write the canary after the buffer of `size` bytes at `ptr`.
+///
+/// # Safety
+///
+/// The block at `ptr` must be at least `size + SIZE` bytes long.
+#[inline]
+pub unsafe fn write(ptr: *mut u8, size: usize) {
+    // The canary is not necessarily aligned.
+    ptr::write_unaligned(ptr.offset(size as isize) as *mut u32, value());
+}
+
+/// Check the canary after the buffer of `size` bytes at `ptr`.
+///
+/// If the canary is corrupted, or the buffer is marked freed, the process is aborted.
+///
+/// # Safety
+///
+/// The block at `ptr` must be at least `size + SIZE` bytes long.
+#[inline]
+pub unsafe fn check(ptr: *mut u8, size: usize) {
+    let canary = ptr::read_unaligned(ptr.offset(size as isize) as *const u32);
+
+    if canary == freed() {
+        log!(
+            ERROR,
+            "Double free: The canary after {:?}[{}] is marked freed.",
+            ptr,
+            size
+        );
+
+        intrinsics::abort();
+    } else if canary != value() {
+        log!(
+            ERROR,
+            "Heap overflow: The canary after {:?}[{}] is corrupted (or the buffer is only a part \
+             of an allocation, which cannot be freed with canaries).",
+            ptr,
+            size
+        );
+
+        intrinsics::abort();
+    }
+}
+
+/// Replace the canary after the buffer of `size` bytes at `ptr` by the freed marker.
+///
+/// # Safety
+///
+/// The block at `ptr` must be at least `size + SIZE` bytes long.
+#[inline]
+pub unsafe fn mark_freed(ptr: *mut u8, size: usize) {
+    ptr::write_unaligned(ptr.offset(size as isize) as *mut u32, freed());
+}
+
+/// Check if the block of `size` bytes at `ptr` ends with the freed marker.
+///
+/// This is used to keep the marker when zeroing freed blocks.
+///
+/// # Safety
+///
+/// The block at `ptr` must be at least `size` bytes long.
+#[inline]
+pub unsafe fn ends_freed(ptr: *const u8, size: usize) -> bool {
+    size >= SIZE
+        && ptr::read_unaligned(ptr.offset((size - SIZE) as isize) as *const u32) == freed()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_value() {
+        // The canary is never zero, and never changes.
+        assert!(value() != 0);
+        assert_eq!(value(), value());
+        // Nor is the freed marker, which differs from the canary.
+        assert!(freed() != 0);
+        assert!(freed() != value());
+    }
+
+    #[test]
+    #[cfg(feature = "secure_random")]
+    fn test_seed() {
+        use core::mem;
+
+        use shim::syscalls;
+
+        // Without `getrandom` (e.g. on old kernels), the seed is derived from addresses instead.
+        let mut buf = [0u8; mem::size_of::<usize>()];
+        if syscalls::getrandom(&mut buf) == buf.len() {
+            // The bytes come from the OS, so they are (most likely) not all zero.
+            assert!(buf.iter().any(|&x| x != 0));
+        }
+
+        assert!(seed() != 0);
+    }
+}
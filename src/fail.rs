@@ -2,9 +2,10 @@
 
 use prelude::*;
 
-use core::mem;
 use core::sync::atomic::{self, AtomicPtr};
+use core::{mem, ptr};
 
+use allocator::{self, Stats};
 use shim::config;
 
 #[cfg(feature = "tls")]
@@ -12,6 +13,20 @@ use tls;
 
 /// The global OOM handler.
 static OOM_HANDLER: AtomicPtr<()> = AtomicPtr::new(config::default_oom_handler as *mut ());
+/// The global OOM handler set through `set_oom_handler_with`, if any.
+///
+/// This takes precedence over `OOM_HANDLER`.
+static OOM_HANDLER_WITH: Mutex<Option<&'static (Fn() + Sync)>> = Mutex::new(None);
+/// The OOM prehook, or null if none is set.
+static OOM_PREHOOK: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+/// The log handler.
+#[cfg(feature = "log")]
+static LOG_HANDLER: AtomicPtr<()> = AtomicPtr::new(config::log as *mut ());
+/// The lock held by the tests replacing the log handler.
+///
+/// The log handler is global, so tests replacing it would otherwise race with each other.
+#[cfg(all(test, feature = "log"))]
+pub static LOG_HANDLER_TEST_LOCK: Mutex<()> = Mutex::new(());
 #[cfg(feature = "tls")]
 tls! {
     /// The thread-local OOM handler.
@@ -31,6 +46,18 @@ tls! {
 /// The rule of thumb is that this should be called, if and only if unwinding (which allocates)
 /// will hit the same error.
 pub fn oom() -> ! {
+    let prehook = OOM_PREHOOK.load(atomic::Ordering::SeqCst);
+    if !prehook.is_null() {
+        log!(DEBUG, "Calling the OOM prehook.");
+
+        let stats = allocator::try_stats();
+
+        unsafe {
+            // Transmute the atomic pointer to a function pointer and call it.
+            (mem::transmute::<_, fn(&Stats)>(prehook))(&stats);
+        }
+    }
+
     // If TLS is enabled, we will use the thread-local OOM.
     #[cfg(feature = "tls")]
     {
@@ -43,6 +70,15 @@ pub fn oom() -> ! {
 
     log!(DEBUG, "Calling the global OOM handler.");
 
+    // Copy the handler out, so the lock is not held while it runs.
+    let handler = *OOM_HANDLER_WITH.lock();
+    if let Some(handler) = handler {
+        handler();
+
+        // The handler returned, so fall back to the default.
+        config::default_oom_handler();
+    }
+
     unsafe {
         // LAST AUDIT: 2016-08-21 (Ticki).
 
@@ -60,6 +96,52 @@ pub fn set_oom_handler(handler: fn() -> !) {
     log!(NOTE, "Setting the global OOM handler.");
 
     OOM_HANDLER.store(handler as *mut (), atomic::Ordering::SeqCst);
+    // The last handler set wins.
+    *OOM_HANDLER_WITH.lock() = None;
+}
+
+/// Set the OOM handler to a closure.
+///
+/// Unlike `set_oom_handler`, the handler can capture state (e.g. a logger handle). It replaces
+/// any handler set before, by either function. Should the handler return, the default OOM handler
+/// is called, aborting the process.
+///
+/// # Important!
+///
+/// `ralloc` cannot allocate to store the handler, so it is taken by `'static` reference: The
+/// handler must be allocated (or leaked, e.g. through `Box::leak`) up front. Since it runs out of
+/// memory, any state it needs must be pre-allocated as well, and it must **never** allocate.
+#[inline]
+pub fn set_oom_handler_with<F: Fn() + Sync>(handler: &'static F) {
+    // Logging...
+    log!(NOTE, "Setting the global OOM handler (closure).");
+
+    *OOM_HANDLER_WITH.lock() = Some(handler);
+}
+
+/// Set the OOM prehook.
+///
+/// The prehook is called on out-of-memory, before the (thread or global) OOM handler, with a
+/// snapshot of the allocator statistics. This is useful for dumping diagnostics or flushing state
+/// before the process is aborted.
+///
+/// # Important!
+///
+/// The prehook runs in a degraded state: The process is out of memory, and the allocator might
+/// be locked by the current thread, so shards that couldn't be locked are left out of the
+/// statistics (see `Stats::locked_shards`). The prehook must **never** allocate, nor call back
+/// into `ralloc` in any other way, as it might deadlock or recurse.
+///
+/// `None` clears the prehook.
+#[inline]
+pub fn set_oom_prehook(prehook: Option<fn(&Stats)>) {
+    // Logging...
+    log!(NOTE, "Setting the OOM prehook.");
+
+    OOM_PREHOOK.store(
+        prehook.map_or(ptr::null_mut(), |prehook| prehook as *mut ()),
+        atomic::Ordering::SeqCst,
+    );
 }
 
 /// Override the OOM handler for the current thread.
@@ -84,6 +166,39 @@ pub fn set_thread_oom_handler(handler: fn() -> !) {
     });
 }
 
+/// Write to the log.
+///
+/// This calls the log handler, which defaults to `shim::config::log`. The return value is `!0` on
+/// failure.
+#[cfg(feature = "log")]
+pub fn log(s: &str) -> usize {
+    unsafe {
+        // Transmute the atomic pointer to a function pointer and call it.
+        (mem::transmute::<_, fn(&str) -> usize>(LOG_HANDLER.load(atomic::Ordering::SeqCst)))(s)
+    }
+}
+
+/// Set the log handler.
+///
+/// All the log output of the allocator is passed to this handler (e.g. to redirect it to a ring
+/// buffer or a serial port), instead of going to `shim::config::log`. It should return the number
+/// of bytes written, or `!0` on failure.
+///
+/// # Important!
+///
+/// The handler is called from **within** the allocator, while the allocator locks are held. It
+/// must **never** allocate (directly or indirectly, e.g. through `format!` or `println!`), nor
+/// call back into `ralloc` in any other way. Doing so will deadlock or corrupt the allocator
+/// state. In debug builds, re-entering a held lock aborts the process instead of deadlocking.
+#[inline]
+#[cfg(feature = "log")]
+pub fn set_log_handler(handler: fn(&str) -> usize) {
+    // Logging...
+    log!(NOTE, "Setting the log handler.");
+
+    LOG_HANDLER.store(handler as *mut (), atomic::Ordering::SeqCst);
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -99,6 +214,33 @@ mod test {
         oom();
     }
 
+    #[test]
+    #[should_panic(expected = "The prehook was called.")]
+    fn test_oom_prehook() {
+        fn infinite() -> ! {
+            #[allow(empty_loop)]
+            loop {}
+        }
+        fn prehook(stats: &Stats) {
+            assert!(stats.largest_free_block <= stats.free_bytes);
+            panic!("The prehook was called.");
+        }
+
+        /// Clears the prehook at the end of the test, even when unwinding.
+        struct Reset;
+
+        impl Drop for Reset {
+            fn drop(&mut self) {
+                set_oom_prehook(None);
+            }
+        }
+
+        let _reset = Reset;
+        set_oom_handler(infinite);
+        set_oom_prehook(Some(prehook));
+        oom();
+    }
+
     #[test]
     #[should_panic]
     #[cfg(feature = "tls")]
@@ -115,4 +257,25 @@ mod test {
         set_thread_oom_handler(panic);
         oom();
     }
+
+    #[test]
+    #[cfg(feature = "log")]
+    fn test_log_handler() {
+        use core::sync::atomic::AtomicUsize;
+
+        static WRITTEN: AtomicUsize = AtomicUsize::new(0);
+
+        fn handler(s: &str) -> usize {
+            WRITTEN.fetch_add(s.len(), atomic::Ordering::SeqCst);
+            s.len()
+        }
+
+        let _lock = LOG_HANDLER_TEST_LOCK.lock();
+
+        set_log_handler(handler);
+        log!(ERROR, "Hello, log handler.");
+        set_log_handler(config::log);
+
+        assert!(WRITTEN.load(atomic::Ordering::SeqCst) >= "Hello, log handler.".len());
+    }
 }
@@ -0,0 +1,109 @@
+//! Allocation hooks.
+//!
+//! This allows tracing every allocation, deallocation, and reallocation (e.g. for building a heap
+//! profiler on top of `ralloc`).
+
+use prelude::*;
+
+use core::sync::atomic::{self, AtomicPtr};
+use core::{mem, ptr};
+
+#[cfg(feature = "event_stream")]
+use stream;
+#[cfg(feature = "tls")]
+use tls;
+
+/// The allocation hook, or null if none is set.
+static ALLOC_HOOK: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+#[cfg(feature = "tls")]
+tls! {
+    /// Is the allocation hook currently running on this thread?
+    static IN_HOOK: MoveCell<bool> = MoveCell::new(false);
+}
+
+/// An allocation event.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AllocEvent {
+    /// A buffer was allocated.
+    Alloc {
+        /// The pointer to the buffer.
+        ptr: *mut u8,
+        /// The size of the buffer.
+        size: usize,
+        /// The alignment of the buffer.
+        align: usize,
+    },
+    /// A buffer is about to be freed.
+    Free {
+        /// The pointer to the buffer.
+        ptr: *mut u8,
+        /// The size of the buffer.
+        size: usize,
+    },
+    /// A buffer was reallocated.
+    Realloc {
+        /// The pointer to the old buffer.
+        old_ptr: *mut u8,
+        /// The size of the old buffer.
+        old_size: usize,
+        /// The pointer to the new buffer.
+        ptr: *mut u8,
+        /// The size of the new buffer.
+        size: usize,
+        /// The alignment of the new buffer.
+        align: usize,
+    },
+}
+
+/// Fire an allocation event.
+///
+/// If no hook is set, this is a single atomic load.
+#[inline]
+pub fn fire(event: AllocEvent) {
+    #[cfg(feature = "event_stream")]
+    stream::emit(&event);
+
+    let hook = ALLOC_HOOK.load(atomic::Ordering::Relaxed);
+
+    if !hook.is_null() {
+        call(hook, event);
+    }
+}
+
+/// Call the allocation hook.
+#[cold]
+fn call(hook: *mut (), event: AllocEvent) {
+    // Make sure the hook doesn't recurse, should it allocate.
+    #[cfg(feature = "tls")]
+    {
+        if IN_HOOK.with(|x| x.replace(true)) {
+            return;
+        }
+    }
+
+    unsafe {
+        // Transmute the atomic pointer to a function pointer and call it.
+        (mem::transmute::<_, fn(AllocEvent)>(hook))(event);
+    }
+
+    #[cfg(feature = "tls")]
+    IN_HOOK.with(|x| x.replace(false));
+}
+
+/// Set the allocation hook.
+///
+/// The hook is called after every allocation and reallocation, and before every free, with the
+/// pointer, size, and alignment in question.
+///
+/// # Important!
+///
+/// The hook is called outside the allocator locks, but allocations made by the hook itself are
+/// not traced (when the `tls` feature is enabled). Without `tls`, the hook must **never** allocate
+/// through `ralloc`, since it would recurse infinitely.
+#[inline]
+pub fn set_alloc_hook(hook: fn(AllocEvent)) {
+    // Logging...
+    log!(NOTE, "Setting the allocation hook.");
+
+    ALLOC_HOOK.store(hook as *mut (), atomic::Ordering::SeqCst);
+}
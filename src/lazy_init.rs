@@ -53,6 +53,17 @@ impl<F: FnMut() -> T, T> LazyInit<F, T> {
         }
     }
 
+    /// Get a mutable reference to the inner value, if initialized.
+    ///
+    /// Unlike `get`, this never runs the initializer.
+    #[inline]
+    pub fn get_if_initialized(&mut self) -> Option<&mut T> {
+        match self.state {
+            State::Initialized(ref mut x) => Some(x),
+            State::Uninitialized(_) => None,
+        }
+    }
+
     /// Get the inner of the container.
     ///
     /// This won't mutate the container itself, since it consumes it. The initializer will (if
@@ -16,6 +16,7 @@
     allocator_api, const_fn, core_intrinsics, stmt_expr_attributes, optin_builtin_traits,
     type_ascription, thread_local, linkage
 )]
+#![cfg_attr(feature = "fp_backtrace", feature(asm))]
 #![warn(missing_docs)]
 
 extern crate ralloc_shim as shim;
@@ -30,34 +31,86 @@ mod tls;
 mod unborrow;
 
 mod allocator;
+#[cfg(feature = "fp_backtrace")]
+mod backtrace;
 mod block;
 mod bookkeeper;
 mod brk;
+#[cfg(feature = "size_class_cache")]
+mod cache;
+#[cfg(feature = "canary")]
+mod canary;
 mod cell;
 mod fail;
+mod hook;
 mod lazy_init;
 mod leak;
+#[cfg(feature = "track_live")]
+mod live;
 mod prelude;
 mod ptr;
+#[cfg(feature = "protect_freed")]
+mod quarantine;
+mod scratch;
+#[cfg(feature = "introspection")]
+mod stats;
+#[cfg(feature = "event_stream")]
+mod stream;
+#[cfg(feature = "c_symbols")]
+mod symbols;
 mod sync;
+#[cfg(feature = "libc_fallback")]
+mod system;
 mod vec;
 
+// The libc fallback calls `malloc`, which would be `ralloc` itself.
+#[cfg(all(feature = "c_symbols", feature = "libc_fallback"))]
+compile_error!("The `c_symbols` and `libc_fallback` features are mutually exclusive.");
+
 use core::alloc::GlobalAlloc;
-use core::alloc::{Alloc, AllocErr, CannotReallocInPlace, Layout};
+use core::alloc::{Alloc, AllocErr, CannotReallocInPlace, Excess, Layout};
 use core::ptr::NonNull;
 
-pub use allocator::{alloc, free, realloc, realloc_inplace};
-pub use brk::sbrk;
-pub use fail::set_oom_handler;
+pub use allocator::{
+    alloc, alloc_excess, alloc_layout, alloc_rt, alloc_secure, avoided_grow_count, compact,
+    fragmentation, free, free_layout, grow_count, largest_free_block, realloc, realloc_inplace,
+    realloc_layout, set_fallback, set_idle_trim, tick, try_alloc, Stats,
+};
+#[cfg(feature = "histogram")]
+pub use allocator::size_histogram;
+#[cfg(feature = "introspection")]
+pub use allocator::{class_stats, shard_stats, walk_free};
+#[cfg(feature = "testing")]
+pub use allocator::fail_after;
+#[cfg(feature = "leak_audit")]
+pub use allocator::report_leaks;
+#[cfg(feature = "tls")]
+pub use allocator::reset_thread_allocator;
+#[cfg(feature = "track_live")]
+pub use allocator::{allocation_size, free_interior, is_live};
+#[cfg(feature = "fp_backtrace")]
+pub use allocator::walk_live;
+#[cfg(feature = "deferred_coalescing")]
+pub use bookkeeper::{set_coalesce_policy, CoalescePolicy};
+pub use brk::{sbrk, set_brk_extra_multiplier};
+#[cfg(feature = "log")]
+pub use fail::set_log_handler;
+pub use fail::{set_oom_handler, set_oom_handler_with, set_oom_prehook};
 #[cfg(feature = "tls")]
 pub use fail::set_thread_oom_handler;
+pub use hook::{set_alloc_hook, AllocEvent};
+pub use scratch::{with_scratch, ScratchAllocator};
+#[cfg(feature = "introspection")]
+pub use stats::ClassStats;
+#[cfg(feature = "event_stream")]
+pub use stream::set_event_stream;
 
 /// The rallocator
 pub struct Allocator;
 
 unsafe impl<'a> Alloc for &'a Allocator {
     unsafe fn alloc(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocErr> {
-        let ptr = allocator::alloc(layout.size(), layout.align());
+        let ptr = allocator::alloc_layout(layout);
         if ptr.is_null() {
             Err(AllocErr)
         } else {
@@ -65,8 +118,13 @@ unsafe impl<'a> Alloc for &'a Allocator {
         }
     }
 
+    unsafe fn alloc_excess(&mut self, layout: Layout) -> Result<Excess, AllocErr> {
+        let (ptr, size) = allocator::alloc_excess(layout.size(), layout.align());
+        Ok(Excess(NonNull::new_unchecked(ptr), size))
+    }
+
     unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
-        allocator::free(ptr.as_ptr(), layout.size());
+        allocator::free_layout(ptr.as_ptr(), layout);
     }
 
     unsafe fn realloc(
@@ -77,10 +135,17 @@ unsafe impl<'a> Alloc for &'a Allocator {
     ) -> Result<NonNull<u8>, AllocErr> {
         let ptr = allocator::realloc(ptr.as_ptr(), layout.size(), new_size, layout.align());
         if ptr.is_null() {
-            Err(AllocErr)
-        } else {
-            Ok(NonNull::new_unchecked(ptr))
+            return Err(AllocErr);
         }
+
+        // The buffer must be aligned to `layout.align()` by the contract of `Alloc`, which
+        // `realloc` takes care of.
+        debug_assert!(
+            ptr as usize % layout.align() == 0,
+            "Reallocation returned a misaligned buffer."
+        );
+
+        Ok(NonNull::new_unchecked(ptr))
     }
 
     unsafe fn grow_in_place(
@@ -110,16 +175,60 @@ unsafe impl<'a> Alloc for &'a Allocator {
     }
 
     fn usable_size(&self, layout: &Layout) -> (usize, usize) {
-        // Yay! It matches exactly.
+        // Yay! It matches exactly. Only `alloc_excess` hands out bigger blocks, and it reports
+        // their size.
         (layout.size(), layout.size())
     }
 }
 
 unsafe impl GlobalAlloc for Allocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        allocator::alloc(layout.size(), layout.align())
+        // Simulate OOM, if requested.
+        #[cfg(feature = "testing")]
+        {
+            if allocator::should_fail() {
+                return core::ptr::null_mut();
+            }
+        }
+
+        if overflows(layout.size(), layout.align()) {
+            return core::ptr::null_mut();
+        }
+
+        allocator::alloc_layout(layout)
+    }
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        // Simulate OOM, if requested.
+        #[cfg(feature = "testing")]
+        {
+            if allocator::should_fail() {
+                return core::ptr::null_mut();
+            }
+        }
+
+        if overflows(layout.size(), layout.align()) {
+            return core::ptr::null_mut();
+        }
+
+        allocator::alloc_secure(layout.size(), layout.align())
     }
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        allocator::free(ptr, layout.size());
+        allocator::free_layout(ptr, layout);
     }
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if overflows(new_size, layout.align()) {
+            return core::ptr::null_mut();
+        }
+
+        allocator::realloc(ptr, layout.size(), new_size, layout.align())
+    }
+}
+
+/// Does padding a buffer of `size` bytes to `align` overflow?
+///
+/// Such a buffer can never be allocated, so `GlobalAlloc` returns null rather than calling the OOM
+/// handler (or overflowing further down, when the break is extended).
+#[inline]
+fn overflows(size: usize, align: usize) -> bool {
+    size.checked_add(align).is_none()
 }
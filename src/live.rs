@@ -0,0 +1,300 @@
+//! Live allocation tracking.
+//!
+//! This keeps a side table of the allocations currently handed out by `ralloc`, which can be
+//! queried by debuggers and sanitizers (e.g. to classify pointers in a crash dump).
+//!
+//! The table is fixed-size (see `config::LIVE_SET_CAPACITY`), since we cannot allocate from
+//! within the allocator. Allocations beyond the capacity are simply not tracked.
+
+use shim::config;
+
+#[cfg(all(feature = "leak_audit", feature = "tls"))]
+use prelude::*;
+
+#[cfg(feature = "fp_backtrace")]
+use backtrace::{self, Backtrace};
+use sync;
+#[cfg(all(feature = "leak_audit", feature = "tls"))]
+use tls;
+
+/// The global live set.
+static LIVE_SET: sync::Mutex<LiveSet> = sync::Mutex::new(LiveSet::new());
+#[cfg(all(feature = "leak_audit", feature = "tls"))]
+tls! {
+    /// The number of bytes allocated on this thread, minus the number of bytes freed on it.
+    static OUTSTANDING: MoveCell<usize> = MoveCell::new(0);
+}
+
+/// A live allocation.
+#[derive(Clone, Copy)]
+struct Entry {
+    /// The address of the start of the allocation.
+    ptr: usize,
+    /// The size of the allocation.
+    size: usize,
+    /// The backtrace of the allocation.
+    #[cfg(feature = "fp_backtrace")]
+    backtrace: Backtrace,
+}
+
+impl Entry {
+    /// Create a new entry without a backtrace.
+    const fn new(ptr: usize, size: usize) -> Entry {
+        Entry {
+            ptr: ptr,
+            size: size,
+            #[cfg(feature = "fp_backtrace")]
+            backtrace: [0; config::BACKTRACE_DEPTH],
+        }
+    }
+
+    /// Get the address of the end of the allocation.
+    #[inline]
+    fn end(&self) -> usize {
+        self.ptr + self.size
+    }
+}
+
+/// A set of live allocations.
+struct LiveSet {
+    /// The entries.
+    ///
+    /// Only the first `len` entries are valid.
+    entries: [Entry; config::LIVE_SET_CAPACITY],
+    /// The number of entries.
+    len: usize,
+}
+
+impl LiveSet {
+    /// Create a new, empty live set.
+    const fn new() -> LiveSet {
+        LiveSet {
+            entries: [Entry::new(0, 0); config::LIVE_SET_CAPACITY],
+            len: 0,
+        }
+    }
+
+    /// Add an entry to the set.
+    fn push(&mut self, entry: Entry) {
+        if self.len == config::LIVE_SET_CAPACITY {
+            // Logging.
+            log!(WARNING, "The live set is full, leaving {:x} untracked.", entry.ptr);
+
+            return;
+        }
+
+        self.entries[self.len] = entry;
+        self.len += 1;
+    }
+
+    /// Find the index of the entry containing the range `[ptr, ptr + size)`.
+    fn find(&self, ptr: usize, size: usize) -> Option<usize> {
+        self.entries[..self.len]
+            .iter()
+            .position(|e| e.ptr <= ptr && ptr + size <= e.end())
+    }
+
+    /// Remove the range `[ptr, ptr + size)` from the set.
+    ///
+    /// If the range only covers a part of an allocation (i.e. partial frees), the rest of the
+    /// allocation is kept in the set.
+    fn remove(&mut self, ptr: usize, size: usize) {
+        let ind = match self.find(ptr, size) {
+            Some(ind) => ind,
+            // Freeing buffers not allocated through ralloc is allowed, so this is not an error.
+            None => return,
+        };
+
+        let entry = self.entries[ind];
+        // The part to the right of the removed range.
+        let right = Entry {
+            ptr: ptr + size,
+            size: entry.end() - (ptr + size),
+            #[cfg(feature = "fp_backtrace")]
+            backtrace: entry.backtrace,
+        };
+
+        if ptr == entry.ptr {
+            if right.size == 0 {
+                // The whole allocation is removed. Swap remove it.
+                self.len -= 1;
+                self.entries[ind] = self.entries[self.len];
+            } else {
+                self.entries[ind] = right;
+            }
+        } else {
+            // Keep the left part.
+            self.entries[ind].size = ptr - entry.ptr;
+
+            if right.size != 0 {
+                self.push(right);
+            }
+        }
+    }
+}
+
+/// Record a new live allocation.
+///
+/// Zero-sized allocations are not tracked. With `fp_backtrace`, the backtrace of the caller is
+/// recorded along with the allocation.
+pub fn insert(ptr: *mut u8, size: usize) {
+    if size != 0 {
+        // Capture the backtrace before locking, to keep the critical section short.
+        let entry = Entry {
+            ptr: ptr as usize,
+            size: size,
+            #[cfg(feature = "fp_backtrace")]
+            backtrace: backtrace::capture(),
+        };
+
+        LIVE_SET.lock().push(entry);
+
+        #[cfg(all(feature = "leak_audit", feature = "tls"))]
+        OUTSTANDING.with(|x| {
+            let cur = x.replace(0);
+            x.replace(cur.wrapping_add(size));
+        });
+    }
+}
+
+/// Record that a (possibly partial) range of an allocation was freed.
+pub fn remove(ptr: *mut u8, size: usize) {
+    if size != 0 {
+        LIVE_SET.lock().remove(ptr as usize, size);
+
+        #[cfg(all(feature = "leak_audit", feature = "tls"))]
+        OUTSTANDING.with(|x| {
+            let cur = x.replace(0);
+            x.replace(cur.wrapping_sub(size));
+        });
+    }
+}
+
+/// Get the number of bytes allocated on the current thread, but not freed on it.
+///
+/// Unlike the live set, this is never capped, but it is negative if the thread freed more memory
+/// (allocated on other threads) than it allocated.
+#[cfg(all(feature = "leak_audit", feature = "tls"))]
+pub fn outstanding() -> isize {
+    OUTSTANDING.with(|x| {
+        let cur = x.replace(0);
+        x.replace(cur);
+
+        cur as isize
+    })
+}
+
+/// Is `ptr` the start of a live allocation?
+pub fn contains(ptr: *const u8) -> bool {
+    let set = LIVE_SET.lock();
+
+    set.entries[..set.len]
+        .iter()
+        .any(|e| e.ptr == ptr as usize)
+}
+
+/// Get the size of the live allocation starting at `ptr`.
+pub fn size(ptr: *const u8) -> Option<usize> {
+    let set = LIVE_SET.lock();
+
+    set.entries[..set.len]
+        .iter()
+        .find(|e| e.ptr == ptr as usize)
+        .map(|e| e.size)
+}
+
+/// Remove the live allocation containing `ptr` from the set, and get its start and size.
+///
+/// The lookup and the removal happen under the same lock, so of several threads freeing the same
+/// allocation, only one gets it. The outstanding byte count is left as is, as freeing the
+/// allocation (through `remove`) accounts for it.
+pub fn take_containing(ptr: *const u8) -> Option<(*mut u8, usize)> {
+    let mut set = LIVE_SET.lock();
+
+    let ind = set.find(ptr as usize, 1)?;
+    let entry = set.entries[ind];
+    set.remove(entry.ptr, entry.size);
+
+    Some((entry.ptr as *mut u8, entry.size))
+}
+
+/// Call `f` with the pointer and size of every live allocation.
+///
+/// The set is only locked while reading an entry, so `f` may allocate, but allocations made while
+/// walking the set might or might not be visited.
+#[cfg(feature = "leak_audit")]
+pub fn for_each(f: fn(*mut u8, usize)) {
+    let mut i = 0;
+
+    while let Some(entry) = get(i) {
+        f(entry.ptr as *mut u8, entry.size);
+        i += 1;
+    }
+}
+
+/// Call `f` with the pointer, size, and backtrace of every live allocation.
+///
+/// The backtrace is cut at the first missing frame. Like `for_each`, the set is only locked while
+/// reading an entry.
+#[cfg(feature = "fp_backtrace")]
+pub fn walk(f: fn(*mut u8, usize, &[usize])) {
+    let mut i = 0;
+
+    while let Some(entry) = get(i) {
+        let frames = entry.backtrace.iter().take_while(|&&x| x != 0).count();
+        f(entry.ptr as *mut u8, entry.size, &entry.backtrace[..frames]);
+        i += 1;
+    }
+}
+
+/// Get a copy of the `i`'th entry of the set.
+///
+/// `None` is returned if `i` is out of bounds.
+#[cfg(any(feature = "leak_audit", feature = "fp_backtrace"))]
+fn get(i: usize) -> Option<Entry> {
+    let set = LIVE_SET.lock();
+
+    if i < set.len {
+        Some(set.entries[i])
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_partial_remove() {
+        let mut set = LiveSet::new();
+
+        set.push(Entry::new(100, 50));
+        set.push(Entry::new(200, 10));
+
+        // Free the middle.
+        set.remove(110, 10);
+        assert_eq!(set.len, 3);
+        assert!(set.find(100, 10).is_some());
+        assert!(set.find(110, 1).is_none());
+        assert!(set.find(120, 30).is_some());
+
+        // Free the head and the tail.
+        set.remove(100, 5);
+        set.remove(140, 10);
+        assert!(set.find(100, 1).is_none());
+        assert!(set.find(105, 5).is_some());
+        assert!(set.find(120, 20).is_some());
+        assert!(set.find(140, 1).is_none());
+
+        // Interior pointers are found in their allocation, but the ends are exclusive.
+        assert_eq!(set.find(125, 1), set.find(120, 20));
+        assert!(set.find(140, 1).is_none());
+
+        // Free whole allocations.
+        set.remove(200, 10);
+        set.remove(105, 5);
+        set.remove(120, 20);
+        assert_eq!(set.len, 0);
+    }
+}
@@ -67,7 +67,7 @@ macro_rules! bk_log {
         {
             use log::internal::{IntoCursor, BlockLogger};
 
-            log!(INTERNAL, "({:2}) {:10?} : {}", $bk.id, BlockLogger {
+            log!(INTERNAL, "({}-{} {:2}) {:10?} : {}", $bk.label, $bk.tag, $bk.id, BlockLogger {
                 cur: $cur.clone().into_cursor(),
                 blocks: &$bk.pool,
             }, format_args!($( $arg ),*));
@@ -78,18 +78,17 @@ macro_rules! bk_log {
 /// Make a runtime assertion.
 ///
 /// The only way it differs from the one provided by `libcore` is the panicking strategy, which
-/// allows for aborting, non-allocating panics when running the tests.
+/// allows for aborting, non-allocating panics when running the tests. The message is logged along
+/// with the stringified condition and the location of the assertion.
 #[macro_export]
 #[cfg(feature = "write")]
 macro_rules! assert {
-    ($e:expr) => {
-        assert!($e, "No description.");
-    };
-    ($e:expr, $( $arg:expr ),*) => {{
+    // The stringified condition is passed separately, so `assert_eq` can report its operands.
+    (@[$cond:expr] $e:expr, $( $arg:expr ),*) => {{
         use core::intrinsics;
 
         if !$e {
-            log!(ERROR, $( $arg ),*);
+            log!(ERROR, "Assertion `{}` failed: {}", $cond, format_args!($( $arg ),*));
 
             #[allow(unused_unsafe)]
             unsafe {
@@ -100,7 +99,13 @@ macro_rules! assert {
                 intrinsics::abort();
             }
         }
-    }}
+    }};
+    ($e:expr) => {
+        assert!($e, "No description.");
+    };
+    ($e:expr, $( $arg:expr ),*) => {
+        assert!(@[stringify!($e)] $e, $( $arg ),*);
+    };
 }
 
 /// Make a runtime assertion in debug mode.
@@ -130,38 +135,52 @@ macro_rules! assert_eq {
         let left = &$left;
         let right = &$right;
 
-        assert!(left == right, "(left: '{:?}', right: '{:?}')", left, right)
+        assert!(
+            @[concat!(stringify!($left), " == ", stringify!($right))]
+            left == right,
+            "(left: '{:?}', right: '{:?}')",
+            left,
+            right
+        )
     }};
 }
 
 /// Top-secret module.
-#[cfg(feature = "log")]
+///
+/// The block logger is also used for the `Debug` implementation of the bookkeeper, so only the
+/// log writer is gated behind the `log` feature.
 pub mod internal {
     use prelude::*;
 
+    #[cfg(feature = "log")]
     use core::cell::Cell;
     use core::fmt;
+    #[cfg(feature = "log")]
     use core::ops::Range;
 
+    #[cfg(feature = "log")]
     use shim::config;
 
-    use sync;
+    #[cfg(feature = "log")]
+    use {fail, sync};
 
     /// The log lock.
     ///
     /// This lock is used to avoid bungling and intertwining the log.
-    #[cfg(not(feature = "no_log_lock"))]
+    #[cfg(all(feature = "log", not(feature = "no_log_lock")))]
     pub static LOG_LOCK: Mutex<()> = Mutex::new(());
 
     /// A log writer.
     ///
-    /// This writes to the shim logger.
+    /// This writes to the log handler (see `set_log_handler`).
+    #[cfg(feature = "log")]
     pub struct LogWriter {
         /// The inner lock.
         #[cfg(not(feature = "no_log_lock"))]
         _lock: sync::MutexGuard<'static, ()>,
     }
 
+    #[cfg(feature = "log")]
     impl LogWriter {
         /// Standard error output.
         pub fn new() -> LogWriter {
@@ -177,9 +196,10 @@ pub mod internal {
         }
     }
 
+    #[cfg(feature = "log")]
     impl fmt::Write for LogWriter {
         fn write_str(&mut self, s: &str) -> fmt::Result {
-            if config::log(s) == !0 {
+            if fail::log(s) == !0 {
                 Err(fmt::Error)
             } else {
                 Ok(())
@@ -209,6 +229,7 @@ pub mod internal {
     }
 
     /// Types that can be converted into a cursor.
+    #[cfg(feature = "log")]
     pub trait IntoCursor {
         /// The end result.
         type Cursor: Cursor;
@@ -218,6 +239,7 @@ pub mod internal {
     }
 
     /// A single-point cursor.
+    #[cfg(feature = "log")]
     pub struct UniCursor {
         /// The position where this cursor will be placed.
         pos: usize,
@@ -227,6 +249,7 @@ pub mod internal {
         is_printed: Cell<bool>,
     }
 
+    #[cfg(feature = "log")]
     impl Cursor for UniCursor {
         fn at(&self, f: &mut fmt::Formatter, n: usize) -> fmt::Result {
             if self.pos == n {
@@ -246,6 +269,7 @@ pub mod internal {
         }
     }
 
+    #[cfg(feature = "log")]
     impl IntoCursor for usize {
         type Cursor = UniCursor;
 
@@ -267,6 +291,7 @@ pub mod internal {
         }
     }
 
+    #[cfg(feature = "log")]
     impl IntoCursor for () {
         type Cursor = ();
 
@@ -278,11 +303,13 @@ pub mod internal {
     /// A interval/range cursor.
     ///
     /// The start of the range is marked by `[` and the end by `]`.
+    #[cfg(feature = "log")]
     pub struct RangeCursor {
         /// The range of this cursor.
         range: Range<usize>,
     }
 
+    #[cfg(feature = "log")]
     impl Cursor for RangeCursor {
         fn at(&self, f: &mut fmt::Formatter, n: usize) -> fmt::Result {
             if self.range.start == n {
@@ -299,6 +326,7 @@ pub mod internal {
         }
     }
 
+    #[cfg(feature = "log")]
     impl IntoCursor for Range<usize> {
         type Cursor = RangeCursor;
 
@@ -349,6 +377,7 @@ pub mod internal {
     }
 
     /// Check if this log level is enabled.
+    #[cfg(feature = "log")]
     #[inline]
     pub fn level(lv: u8) -> bool {
         lv >= config::MIN_LOG_LEVEL
@@ -79,6 +79,33 @@ impl<T> Pointer<T> {
         Pointer::new(self.ptr.as_ptr().offset(diff))
     }
 
+    /// Offset this pointer by some number of bytes.
+    ///
+    /// Unlike `offset`, this is not multiplied by the size of T.
+    ///
+    /// # Safety
+    ///
+    /// This is unsafe, due to OOB offsets being undefined behavior.
+    #[inline]
+    pub unsafe fn offset_bytes(self, bytes: isize) -> Pointer<T> {
+        Pointer::new((self.get() as *mut u8).offset(bytes) as *mut T)
+    }
+
+    /// Get the distance in bytes from another pointer to this pointer.
+    ///
+    /// This is negative if this pointer lies before `other`. Unlike `offset`, this is not divided
+    /// by the size of T.
+    #[inline]
+    pub fn diff(&self, other: &Pointer<T>) -> isize {
+        (self.get() as usize).wrapping_sub(other.get() as usize) as isize
+    }
+
+    /// Is this pointer aligned to `align`?
+    #[inline]
+    pub fn is_aligned_to(&self, align: usize) -> bool {
+        self.get() as usize % align == 0
+    }
+
     pub fn get(&self) -> *mut T {
         self.ptr.as_ptr()
     }
@@ -117,6 +144,47 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_offset_bytes() {
+        let mut x = [0x11223344u32, 0x55667788];
+
+        unsafe {
+            let ptr = Pointer::new(&mut x[0] as *mut u32);
+            assert_eq!(*ptr.clone().offset_bytes(4).get(), 0x55667788);
+            assert_eq!(*ptr.clone().offset(1).offset_bytes(-4).get(), 0x11223344);
+            assert_eq!(
+                ptr.clone().offset_bytes(2).get() as usize,
+                ptr.get() as usize + 2
+            );
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let mut x = [0u32; 4];
+
+        unsafe {
+            let ptr = Pointer::new(&mut x[0] as *mut u32);
+            assert_eq!(ptr.diff(&ptr), 0);
+            assert_eq!(ptr.clone().offset(3).diff(&ptr), 12);
+            assert_eq!(ptr.diff(&ptr.clone().offset_bytes(5)), -5);
+        }
+    }
+
+    #[test]
+    fn test_is_aligned_to() {
+        let mut x = [0u32; 2];
+
+        unsafe {
+            let ptr = Pointer::new(&mut x[0] as *mut u32);
+            assert!(ptr.is_aligned_to(1));
+            assert!(ptr.is_aligned_to(4));
+            assert!(ptr.clone().offset_bytes(2).is_aligned_to(2));
+            assert!(!ptr.clone().offset_bytes(2).is_aligned_to(4));
+            assert!(!ptr.offset_bytes(1).is_aligned_to(2));
+        }
+    }
+
     #[test]
     fn test_empty() {
         assert_eq!(Pointer::<u8>::empty().get() as usize, 1);
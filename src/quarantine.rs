@@ -0,0 +1,65 @@
+//! Quarantine of protected freed blocks.
+//!
+//! With the `protect_freed` feature, big freed blocks are made inaccessible (see
+//! `BrkLock::protect`) and kept aside for a while rather than freed right away, so that
+//! use-after-frees fault instead of silently corrupting memory.
+//!
+//! The allocator itself writes to free blocks (e.g. the size class cache headers, or zeroing with
+//! the `security` feature), so protected blocks must never reach a block pool. Only when evicted
+//! from the quarantine, a block is made accessible again and freed for real.
+
+use prelude::*;
+
+use core::mem;
+
+use shim::config;
+use {brk, sync};
+
+/// An empty quarantine slot.
+const EMPTY_BLOCK: Block = Block::empty_const();
+
+/// A ring buffer of protected blocks.
+struct Quarantine {
+    /// The blocks. Unused slots hold empty blocks.
+    blocks: [Block; config::PROTECT_FREED_QUARANTINE],
+    /// The slot of the oldest block, which is evicted next.
+    next: usize,
+}
+
+/// The quarantine.
+static QUARANTINE: sync::Mutex<Quarantine> = sync::Mutex::new(Quarantine {
+    blocks: [EMPTY_BLOCK; config::PROTECT_FREED_QUARANTINE],
+    next: 0,
+});
+
+/// Quarantine a freed block.
+///
+/// The block is protected and kept aside, and the block evicted to make room for it is returned
+/// accessible, to be freed for real. If the block is too small or cannot be protected, it is
+/// returned right away.
+pub fn push(block: Block) -> Block {
+    if block.size() < config::PROTECT_FREED_MIN || brk::lock().protect(&block).is_err() {
+        return block;
+    }
+
+    // Logging...
+    log!(DEBUG, "Quarantining {:?}.", block);
+
+    let old = {
+        let mut quarantine = QUARANTINE.lock();
+        let next = quarantine.next;
+        quarantine.next = (next + 1) % config::PROTECT_FREED_QUARANTINE;
+
+        mem::replace(&mut quarantine.blocks[next], block)
+    };
+
+    if old.is_empty() || brk::lock().unprotect(&old).is_ok() {
+        old
+    } else {
+        // Logging...
+        log!(WARNING, "Unable to unprotect {:?}, leaking it.", old);
+
+        // A protected block must never be reused.
+        old.empty_left()
+    }
+}
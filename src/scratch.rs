@@ -0,0 +1,288 @@
+//! Scratch allocators.
+//!
+//! A scratch allocator has a private bookkeeper, whose memory is BRK'd separately from the global
+//! allocator. When the scratch allocator is dropped, all of its memory is released at once,
+//! regardless of whether the individual buffers were freed.
+//!
+//! Every BRK of a scratch allocator forms a "segment", whose end holds a header linking it to the
+//! previous segment. This way, the segments can be found again when releasing, without any
+//! bookkeeping beside the segments themselves.
+
+use prelude::*;
+
+use core::{mem, ops, ptr};
+
+use bookkeeper::{self, Allocator, Bookkeeper};
+use {allocator, brk, fail};
+
+/// The header at the end of every segment.
+#[derive(Clone, Copy)]
+struct Header {
+    /// The address of the header of the previous segment, or zero if this is the first segment.
+    prev: usize,
+    /// The address of the start of this segment.
+    start: usize,
+    /// The size of this segment, including the header.
+    size: usize,
+}
+
+/// A scratch allocator.
+///
+/// This is created by `with_scratch`. Buffers allocated from it must be freed (or reallocated)
+/// through it, and never through the global functions, such as `ralloc::free`.
+pub struct ScratchAllocator {
+    /// The inner bookkeeper.
+    inner: Bookkeeper,
+    /// The address of the header of the last segment.
+    last: usize,
+}
+
+impl ScratchAllocator {
+    /// Create a new scratch allocator.
+    ///
+    /// The OOM handler is called if the initial segment cannot be acquired.
+    fn new() -> ScratchAllocator {
+        // Logging...
+        log!(NOTE, "Initializing a scratch allocator.");
+
+        let (last, initial_segment, aligner, excessive) = acquire(
+            0,
+            8 * bookkeeper::EXTRA_ELEMENTS * mem::size_of::<Block>(),
+            mem::align_of::<Block>(),
+        ).unwrap_or_else(|| fail::oom());
+
+        let mut res = ScratchAllocator {
+            inner: Bookkeeper::new(
+                unsafe {
+                    // The segment was just acquired, so we own it.
+                    Vec::from_raw_parts(initial_segment, 0)
+                },
+                "scratch",
+                ::shim::syscalls::thread_id(),
+            ),
+            last: last,
+        };
+
+        // Free the secondary space.
+        res.push(aligner);
+        res.push(excessive);
+
+        res
+    }
+
+    /// Allocate a buffer.
+    ///
+    /// If `size` is zero, a dangling (but non-null and aligned) pointer is returned, without
+    /// allocating.
+    ///
+    /// # Errors
+    ///
+    /// The OOM handler handles out-of-memory conditions.
+    pub fn alloc(&mut self, size: usize, align: usize) -> *mut u8 {
+        log!(CALL, "Allocating scratch buffer of size {} (align {}).", size, align);
+
+        if size == 0 {
+            return allocator::zero_sized(align);
+        }
+
+        Pointer::from(Allocator::alloc(self, size, align)).get()
+    }
+
+    /// Free a buffer.
+    ///
+    /// Freeing is optional, since all the memory is released along with the scratch allocator, but
+    /// it allows the memory to be reused.
+    ///
+    /// # Safety
+    ///
+    /// The buffer must have been allocated by this scratch allocator with size `size`.
+    pub unsafe fn free(&mut self, ptr: *mut u8, size: usize) {
+        log!(CALL, "Freeing scratch buffer of size {}.", size);
+
+        if size == 0 {
+            return;
+        }
+
+        debug_assert!(self.owns(ptr), "Freeing a buffer, which is not scratch memory.");
+
+        Allocator::free(self, Block::from_raw_parts(Pointer::new(ptr), size));
+    }
+
+    /// Reallocate a buffer.
+    ///
+    /// The buffer is resized to `size` bytes aligned to `align` (moving it, if necessary), and the
+    /// new pointer is returned.
+    ///
+    /// # Safety
+    ///
+    /// The buffer must have been allocated by this scratch allocator with size `old_size`.
+    pub unsafe fn realloc(
+        &mut self,
+        ptr: *mut u8,
+        old_size: usize,
+        size: usize,
+        align: usize,
+    ) -> *mut u8 {
+        log!(CALL, "Reallocating scratch buffer of size {} to new size {}.", old_size, size);
+
+        if old_size == 0 {
+            return self.alloc(size, align);
+        } else if size == 0 {
+            self.free(ptr, old_size);
+
+            return allocator::zero_sized(align);
+        }
+
+        debug_assert!(self.owns(ptr), "Reallocating a buffer, which is not scratch memory.");
+
+        Pointer::from(Allocator::realloc(
+            self,
+            Block::from_raw_parts(Pointer::new(ptr), old_size),
+            size,
+            align,
+        )).get()
+    }
+
+    /// Does some pointer point into the memory of this scratch allocator?
+    ///
+    /// This tells scratch buffers apart from buffers of the global allocator.
+    pub fn owns(&self, ptr: *const u8) -> bool {
+        let addr = ptr as usize;
+        let mut header = self.last;
+
+        while header != 0 {
+            let Header { prev, start, size } = unsafe {
+                // The header was written when the segment was acquired.
+                ptr::read_unaligned(header as *const Header)
+            };
+
+            if addr >= start && addr - start < size {
+                return true;
+            }
+
+            header = prev;
+        }
+
+        false
+    }
+}
+
+impl ops::Deref for ScratchAllocator {
+    type Target = Bookkeeper;
+
+    fn deref(&self) -> &Bookkeeper {
+        &self.inner
+    }
+}
+
+impl ops::DerefMut for ScratchAllocator {
+    fn deref_mut(&mut self) -> &mut Bookkeeper {
+        &mut self.inner
+    }
+}
+
+impl Allocator for ScratchAllocator {
+    #[inline]
+    fn try_alloc_fresh(&mut self, size: usize, align: usize) -> Option<Block> {
+        let (last, res, aligner, excessive) = acquire(self.last, size, align)?;
+        // Pushing might acquire a segment too, so the list must be updated first.
+        self.last = last;
+
+        self.push(aligner);
+        self.push(excessive);
+
+        Some(res)
+    }
+}
+
+/// Release all the segments.
+///
+/// This runs even if the scratch closure panics, since the scratch allocator is dropped while
+/// unwinding.
+impl Drop for ScratchAllocator {
+    fn drop(&mut self) {
+        // Logging...
+        log!(NOTE, "Releasing the scratch allocator.");
+
+        // The bookkeeper lives in the segments, so it is left as is, and never touched again.
+        let mut header = self.last;
+        while header != 0 {
+            let Header { prev, start, size } = unsafe {
+                // The header was written when the segment was acquired.
+                ptr::read_unaligned(header as *const Header)
+            };
+            let segment = unsafe {
+                // Every buffer of the segment is dead now, so we own the segment.
+                Block::from_raw_parts(Pointer::new(start as *mut u8), size)
+            };
+
+            // The segments are released from the top. If the global allocator has BRK'd since,
+            // the segment is not next to the program break, and goes to the global allocator
+            // instead. Note that the BRK lock must be released before that.
+            let res = brk::lock().release(segment);
+            if let Err(segment) = res {
+                allocator::free_to_global(segment);
+            }
+
+            header = prev;
+        }
+    }
+}
+
+/// BRK a new segment holding a block of `size` bytes aligned to `align`.
+///
+/// The header of the segment is written (linking to the segment with the header at `prev`), and
+/// its address is returned along with the block, and the aligner and excessive blocks, which are
+/// free. `None` is returned if out of memory.
+fn acquire(prev: usize, size: usize, align: usize) -> Option<(usize, Block, Block, Block)> {
+    let header_size = mem::size_of::<Header>();
+    let (aligner, res, excessive) = {
+        let mut brk = brk::lock();
+        let total = size.checked_add(header_size)?;
+
+        brk.try_canonical_brk(total, align)
+            .or_else(|| brk.try_exact_brk(total, align))?
+    };
+    let (res, header) = res.split(size);
+
+    let start = Pointer::from(aligner.empty_left()).get() as usize;
+    let addr = Pointer::from(header.empty_left()).get() as usize;
+    unsafe {
+        // The header block was just acquired. Blocks have no alignment, hence the unaligned write.
+        ptr::write_unaligned(
+            addr as *mut Header,
+            Header {
+                prev: prev,
+                start: start,
+                size: aligner.size() + res.size() + header_size + excessive.size(),
+            },
+        );
+    }
+
+    Some((addr, res, aligner, excessive))
+}
+
+/// Run a closure with a scratch allocator.
+///
+/// The scratch allocator is private to the closure, and its memory is kept apart from the global
+/// allocator. When the closure returns (or panics), all of its memory is released at once, so the
+/// buffers need not be freed individually (but they must not be used afterwards).
+///
+/// # Example
+///
+/// ```
+/// let sum = ralloc::with_scratch(|scratch| {
+///     let buf = scratch.alloc(100, 8);
+///     unsafe {
+///         *buf = 42;
+///         *buf as usize
+///     }
+/// });
+/// assert_eq!(sum, 42);
+/// ```
+pub fn with_scratch<R, F: FnOnce(&mut ScratchAllocator) -> R>(f: F) -> R {
+    // Logging...
+    log!(CALL, "Running with a scratch allocator.");
+
+    f(&mut ScratchAllocator::new())
+}
@@ -0,0 +1,104 @@
+//! Allocation statistics.
+//!
+//! This keeps counters of the allocations and frees of each size class, which can be used for
+//! tuning (e.g. to find out which size classes dominate).
+//!
+//! Size class `n` covers the sizes `2^(n - 1) + 1` through `2^n`, that is, the sizes are rounded up
+//! to the nearest power of two. The last class (see `config::SIZE_CLASSES`) covers every larger
+//! size as well.
+
+use core::mem;
+
+use shim::config;
+
+use sync;
+
+/// The global per-size-class statistics.
+static CLASS_STATS: sync::Mutex<[ClassStats; config::SIZE_CLASSES]> =
+    sync::Mutex::new([ClassStats::new(); config::SIZE_CLASSES]);
+
+/// The statistics of a size class.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ClassStats {
+    /// The number of allocations.
+    pub allocs: usize,
+    /// The number of frees.
+    pub frees: usize,
+    /// The number of currently live allocations.
+    pub live: usize,
+    /// The highest number of live allocations at any point.
+    pub peak: usize,
+}
+
+impl ClassStats {
+    /// Create new, zeroed statistics.
+    const fn new() -> ClassStats {
+        ClassStats {
+            allocs: 0,
+            frees: 0,
+            live: 0,
+            peak: 0,
+        }
+    }
+}
+
+/// Get the size class of some size.
+#[inline]
+pub fn size_class(size: usize) -> usize {
+    if size <= 1 {
+        0
+    } else {
+        let class = mem::size_of::<usize>() * 8 - (size - 1).leading_zeros() as usize;
+
+        if class < config::SIZE_CLASSES {
+            class
+        } else {
+            config::SIZE_CLASSES - 1
+        }
+    }
+}
+
+/// Record an allocation of some size.
+pub fn record_alloc(size: usize) {
+    let mut stats = CLASS_STATS.lock();
+    let class = &mut stats[size_class(size)];
+
+    class.allocs += 1;
+    class.live += 1;
+    if class.live > class.peak {
+        class.peak = class.live;
+    }
+}
+
+/// Record a (possibly partial) free of some size.
+pub fn record_free(size: usize) {
+    let mut stats = CLASS_STATS.lock();
+    let class = &mut stats[size_class(size)];
+
+    class.frees += 1;
+    // Partial frees might not match any allocation of this class.
+    class.live = class.live.saturating_sub(1);
+}
+
+/// Get a snapshot of the statistics of every size class.
+pub fn class_stats() -> [ClassStats; config::SIZE_CLASSES] {
+    *CLASS_STATS.lock()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_size_class() {
+        assert_eq!(size_class(0), 0);
+        assert_eq!(size_class(1), 0);
+        assert_eq!(size_class(2), 1);
+        assert_eq!(size_class(3), 2);
+        assert_eq!(size_class(4), 2);
+        assert_eq!(size_class(5), 3);
+        assert_eq!(size_class(8), 3);
+        assert_eq!(size_class(9), 4);
+        assert_eq!(size_class(!0), config::SIZE_CLASSES - 1);
+    }
+}
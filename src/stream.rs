@@ -0,0 +1,105 @@
+//! Allocation event streams.
+//!
+//! This writes a compact binary trace of every allocation, deallocation, and reallocation to a
+//! file descriptor, for offline analysis (e.g. replay or visualization).
+//!
+//! # Format
+//!
+//! The stream consists of fixed-size records of four native-endian `u64`s: The operation (`0` for
+//! allocations, `1` for frees, and `2` for reallocations), the pointer, the size, and a monotonic
+//! timestamp in nanoseconds. A reallocation is recorded as a free of the old buffer followed by a
+//! reallocation record of the new buffer.
+
+use core::sync::atomic::{self, AtomicUsize};
+use core::{mem, slice};
+
+use shim::syscalls;
+
+use hook::AllocEvent;
+
+/// The operation of an allocation record.
+const OP_ALLOC: u64 = 0;
+/// The operation of a free record.
+const OP_FREE: u64 = 1;
+/// The operation of a reallocation record.
+const OP_REALLOC: u64 = 2;
+
+/// The file descriptor of the event stream, or `!0` if disabled.
+static STREAM_FD: AtomicUsize = AtomicUsize::new(!0);
+
+/// A record of the event stream.
+#[repr(C)]
+struct Record {
+    /// The operation.
+    op: u64,
+    /// The pointer to the buffer.
+    ptr: u64,
+    /// The size of the buffer.
+    size: u64,
+    /// The time of the event.
+    time: u64,
+}
+
+impl Record {
+    /// Write the record to the stream.
+    ///
+    /// If the write would block, the record is dropped.
+    fn write(&self, fd: usize) {
+        let bytes = unsafe {
+            slice::from_raw_parts(self as *const Record as *const u8, mem::size_of::<Record>())
+        };
+
+        syscalls::write(fd, bytes);
+    }
+}
+
+/// Write an allocation event to the stream, if enabled.
+#[inline]
+pub fn emit(event: &AllocEvent) {
+    let fd = STREAM_FD.load(atomic::Ordering::Relaxed);
+    if fd == !0 {
+        return;
+    }
+
+    let time = syscalls::monotonic_time();
+    let record = |op, ptr: *mut u8, size: usize| Record {
+        op: op,
+        ptr: ptr as u64,
+        size: size as u64,
+        time: time,
+    };
+
+    match *event {
+        AllocEvent::Alloc { ptr, size, .. } => record(OP_ALLOC, ptr, size).write(fd),
+        AllocEvent::Free { ptr, size } => record(OP_FREE, ptr, size).write(fd),
+        AllocEvent::Realloc {
+            old_ptr,
+            old_size,
+            ptr,
+            size,
+            ..
+        } => {
+            record(OP_FREE, old_ptr, old_size).write(fd);
+            record(OP_REALLOC, ptr, size).write(fd);
+        }
+    }
+}
+
+/// Set the file descriptor the allocation event stream is written to.
+///
+/// The file descriptor must be dedicated to the stream, and opened non-blocking (e.g. a pipe
+/// created with `O_NONBLOCK`), so a full pipe drops records instead of stalling the allocator. Its
+/// flags are left as is, as they are shared with every other user of the file, so if it is not
+/// non-blocking (or invalid), the stream is not enabled and `Err(())` is returned.
+pub fn set_event_stream(fd: usize) -> Result<(), ()> {
+    // Logging...
+    log!(NOTE, "Setting the event stream to fd {}.", fd);
+
+    if !syscalls::is_nonblocking(fd) {
+        return Err(());
+    }
+
+    STREAM_FD.store(fd, atomic::Ordering::SeqCst);
+
+    Ok(())
+}
@@ -0,0 +1,198 @@
+//! C ABI symbols.
+//!
+//! This exposes the allocator through the C interface, for interop with C code.
+//!
+//! The whole `malloc` family is exported, since these symbols override the ones of the libc, and
+//! a buffer must never be freed by another allocator than the one which allocated it. C frees
+//! without a size, so every buffer is prefixed with a header holding the size of the underlying
+//! allocation. Hence, buffers from these symbols are not interchangeable with the ones from the
+//! Rust API (e.g. `ralloc::free`).
+
+use core::{cmp, mem, ptr};
+
+use allocator;
+
+/// The minimal alignment of buffers, as of `max_align_t` on common platforms.
+const MIN_ALIGN: usize = 2 * mem::size_of::<usize>();
+/// The error code of an invalid argument.
+const EINVAL: i32 = 22;
+/// The error code of running out of memory.
+const ENOMEM: i32 = 12;
+
+/// The header stored right before every buffer.
+#[derive(Clone, Copy)]
+struct Header {
+    /// The number of bytes from the start of the allocation to the buffer.
+    ///
+    /// This is the alignment of the buffer, which leaves room for the header, since it is no
+    /// bigger than `MIN_ALIGN`.
+    prefix: usize,
+    /// The size of the allocation, including the prefix.
+    size: usize,
+}
+
+impl Header {
+    /// Read the header of a buffer.
+    unsafe fn of(ptr: *mut u8) -> Header {
+        ptr::read((ptr as *const Header).offset(-1))
+    }
+
+    /// Get the start of the allocation of a buffer.
+    unsafe fn base(&self, ptr: *mut u8) -> *mut u8 {
+        ptr.offset(-(self.prefix as isize))
+    }
+}
+
+/// Allocate a buffer of `size` bytes aligned to `align`, which must be a power of two.
+///
+/// Null is returned if the size overflows.
+unsafe fn alloc(size: usize, align: usize, zeroed: bool) -> *mut u8 {
+    let prefix = cmp::max(align, MIN_ALIGN);
+    let size = match size.checked_add(prefix) {
+        Some(size) => size,
+        None => return ptr::null_mut(),
+    };
+
+    let base = if zeroed {
+        allocator::alloc_secure(size, prefix)
+    } else {
+        allocator::alloc(size, prefix)
+    };
+    let ptr = base.offset(prefix as isize);
+    ptr::write(
+        (ptr as *mut Header).offset(-1),
+        Header {
+            prefix: prefix,
+            size: size,
+        },
+    );
+
+    ptr
+}
+
+/// Allocate a buffer of `size` bytes.
+///
+/// The buffer is aligned to `2 * size_of::<usize>()`. The OOM handler handles out-of-memory
+/// conditions.
+#[no_mangle]
+pub unsafe extern fn malloc(size: usize) -> *mut u8 {
+    log!(CALL, "Malloc'ing {} bytes.", size);
+
+    alloc(size, MIN_ALIGN, false)
+}
+
+/// Allocate a zeroed array of `nmemb` elements of size `size`.
+///
+/// If `nmemb * size` overflows, null is returned, rather than a buffer smaller than requested.
+#[no_mangle]
+pub unsafe extern fn calloc(nmemb: usize, size: usize) -> *mut u8 {
+    log!(CALL, "Calloc'ing {} elements of size {}.", nmemb, size);
+
+    if let Some(size) = nmemb.checked_mul(size) {
+        alloc(size, MIN_ALIGN, true)
+    } else {
+        // Logging...
+        log!(WARNING, "Calloc size overflowed.");
+
+        ptr::null_mut()
+    }
+}
+
+/// Allocate a buffer of `size` bytes aligned to `align`.
+///
+/// Null is returned if `align` is not a power of two.
+#[no_mangle]
+pub unsafe extern fn aligned_alloc(align: usize, size: usize) -> *mut u8 {
+    log!(CALL, "Allocating {} bytes aligned to {}.", size, align);
+
+    if align.is_power_of_two() {
+        alloc(size, align, false)
+    } else {
+        ptr::null_mut()
+    }
+}
+
+/// Allocate a buffer of `size` bytes aligned to `align`.
+///
+/// This is the obsolete equivalent of `aligned_alloc`.
+#[no_mangle]
+pub unsafe extern fn memalign(align: usize, size: usize) -> *mut u8 {
+    aligned_alloc(align, size)
+}
+
+/// Allocate a buffer of `size` bytes aligned to `align`, and store it in `memptr`.
+///
+/// `EINVAL` is returned if `align` is not a power of two multiple of the pointer size, `ENOMEM` if
+/// the size overflows, and zero otherwise.
+#[no_mangle]
+pub unsafe extern fn posix_memalign(memptr: *mut *mut u8, align: usize, size: usize) -> i32 {
+    if !align.is_power_of_two() || align % mem::size_of::<usize>() != 0 {
+        return EINVAL;
+    }
+
+    let ptr = aligned_alloc(align, size);
+    if ptr.is_null() {
+        return ENOMEM;
+    }
+
+    *memptr = ptr;
+
+    0
+}
+
+/// Reallocate a buffer to `size` bytes.
+///
+/// Null reallocates nothing, i.e. allocates, and a size of zero frees the buffer, returning null.
+#[no_mangle]
+pub unsafe extern fn realloc(ptr: *mut u8, size: usize) -> *mut u8 {
+    log!(CALL, "Reallocating buffer to {} bytes.", size);
+
+    if ptr.is_null() {
+        return malloc(size);
+    } else if size == 0 {
+        free(ptr);
+        return ptr::null_mut();
+    }
+
+    let header = Header::of(ptr);
+    let new_size = match size.checked_add(header.prefix) {
+        Some(new_size) => new_size,
+        None => return ptr::null_mut(),
+    };
+
+    let base = allocator::realloc(header.base(ptr), header.size, new_size, header.prefix);
+    let ptr = base.offset(header.prefix as isize);
+    ptr::write(
+        (ptr as *mut Header).offset(-1),
+        Header {
+            prefix: header.prefix,
+            size: new_size,
+        },
+    );
+
+    ptr
+}
+
+/// Free a buffer.
+///
+/// Freeing null does nothing.
+#[no_mangle]
+pub unsafe extern fn free(ptr: *mut u8) {
+    log!(CALL, "Freeing buffer.");
+
+    if !ptr.is_null() {
+        let header = Header::of(ptr);
+        allocator::free(header.base(ptr), header.size);
+    }
+}
+
+/// Get the number of bytes usable in a buffer.
+#[no_mangle]
+pub unsafe extern fn malloc_usable_size(ptr: *mut u8) -> usize {
+    if ptr.is_null() {
+        0
+    } else {
+        let header = Header::of(ptr);
+        header.size - header.prefix
+    }
+}
@@ -1,8 +1,12 @@
 //! Synchronization primitives.
 
 use core::cell::UnsafeCell;
+#[cfg(debug_assertions)]
+use core::intrinsics;
 use core::ops;
 use core::sync::atomic::{self, AtomicBool};
+#[cfg(debug_assertions)]
+use core::sync::atomic::AtomicUsize;
 
 use shim;
 
@@ -18,8 +22,20 @@ pub struct Mutex<T> {
     ///
     /// This is true, if and only if the lock is currently held.
     locked: AtomicBool,
+    /// The ID of the thread holding the lock, or zero if it is not held.
+    ///
+    /// This is only tracked in debug builds, to catch a thread locking a lock it already holds,
+    /// which would otherwise spin forever.
+    #[cfg(debug_assertions)]
+    owner: AtomicUsize,
 }
 
+/// Has a re-entrant lock been detected?
+///
+/// Reporting it logs, which takes the log lock, which might be the very lock re-entered.
+#[cfg(debug_assertions)]
+static REENTERED: AtomicBool = AtomicBool::new(false);
+
 impl<T> Mutex<T> {
     /// Create a new mutex with some inner value.
     #[inline]
@@ -27,6 +43,8 @@ impl<T> Mutex<T> {
         Mutex {
             inner: UnsafeCell::new(inner),
             locked: AtomicBool::new(false),
+            #[cfg(debug_assertions)]
+            owner: AtomicUsize::new(0),
         }
     }
 
@@ -35,6 +53,23 @@ impl<T> Mutex<T> {
     /// If another lock is held, this will block the thread until it is released.
     #[inline]
     pub fn lock(&self) -> MutexGuard<T> {
+        // Locking a lock held by this very thread (e.g. an allocating log handler) would spin
+        // forever, so in debug builds, we abort instead.
+        #[cfg(debug_assertions)]
+        let thread = shim::syscalls::thread_id();
+        #[cfg(debug_assertions)]
+        {
+            if self.owner.load(atomic::Ordering::Relaxed) == thread {
+                if !REENTERED.swap(true, atomic::Ordering::SeqCst) {
+                    log!(ERROR, "Re-entrant allocator lock: The thread already holds the lock.");
+                }
+
+                unsafe {
+                    intrinsics::abort();
+                }
+            }
+        }
+
         // Lock the mutex.
         #[cfg(not(feature = "unsafe_no_mutex_lock"))]
         while self
@@ -45,11 +80,39 @@ impl<T> Mutex<T> {
             // {O,o}
             // |)``)
             // SRSLY?
-            shim::syscalls::sched_yield();
+            //
+            // Yielding is only a hint, so a failed yield (e.g. under seccomp) is ignored. It just
+            // means busier spinning.
+            let _ = shim::syscalls::sched_yield();
         }
 
+        #[cfg(debug_assertions)]
+        self.owner.store(thread, atomic::Ordering::Relaxed);
+
         MutexGuard { mutex: self }
     }
+
+    /// Try to lock this mutex.
+    ///
+    /// Unlike `lock`, this never blocks: If another lock is held, `None` is returned.
+    #[inline]
+    pub fn try_lock(&self) -> Option<MutexGuard<T>> {
+        #[cfg(not(feature = "unsafe_no_mutex_lock"))]
+        {
+            if self
+                .locked
+                .compare_and_swap(false, true, atomic::Ordering::SeqCst)
+            {
+                return None;
+            }
+        }
+
+        #[cfg(debug_assertions)]
+        self.owner
+            .store(shim::syscalls::thread_id(), atomic::Ordering::Relaxed);
+
+        Some(MutexGuard { mutex: self })
+    }
 }
 
 /// A mutex guard.
@@ -65,6 +128,9 @@ pub struct MutexGuard<'a, T: 'a> {
 impl<'a, T> Drop for MutexGuard<'a, T> {
     #[inline]
     fn drop(&mut self) {
+        #[cfg(debug_assertions)]
+        self.mutex.owner.store(0, atomic::Ordering::Relaxed);
+
         self.mutex.locked.store(false, atomic::Ordering::SeqCst);
     }
 }
@@ -112,4 +178,41 @@ mod test {
         *mutex.lock() = 0xFF;
         assert_eq!(*mutex.lock(), 0xFF);
     }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn test_owner() {
+        let mutex = Mutex::new(3);
+
+        {
+            let _guard = mutex.lock();
+            assert_eq!(
+                mutex.owner.load(atomic::Ordering::Relaxed),
+                shim::syscalls::thread_id()
+            );
+        }
+        assert_eq!(mutex.owner.load(atomic::Ordering::Relaxed), 0);
+
+        {
+            let _guard = mutex.try_lock().unwrap();
+            assert_eq!(
+                mutex.owner.load(atomic::Ordering::Relaxed),
+                shim::syscalls::thread_id()
+            );
+        }
+        assert_eq!(mutex.owner.load(atomic::Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_try_lock() {
+        let mutex = Mutex::new(3);
+
+        {
+            let _guard = mutex.lock();
+            assert!(mutex.try_lock().is_none());
+        }
+
+        *mutex.try_lock().unwrap() = 4;
+        assert_eq!(*mutex.lock(), 4);
+    }
 }
@@ -0,0 +1,227 @@
+//! Falling back to the system allocator.
+//!
+//! If the very first BRK fails (e.g. in a sandbox disallowing it), `ralloc` cannot even initialize
+//! itself. With the `libc_fallback` feature, it then routes every allocation to the allocator of
+//! libc (`malloc` and `free`, which are linked weakly) instead of aborting.
+//!
+//! The fallback is sticky: Once fallen back, every later allocation and free goes to libc, so
+//! buffers of the two allocators are never mixed up. Unlike `ralloc`, libc cannot free a part of a
+//! buffer, so such frees are ignored, leaking the buffer.
+
+use core::sync::atomic::{self, AtomicUsize};
+use core::{cmp, mem, ptr};
+
+extern {
+    #[linkage = "extern_weak"]
+    #[link_name = "malloc"]
+    static libc_malloc: *const u8;
+    #[linkage = "extern_weak"]
+    #[link_name = "free"]
+    static libc_free: *const u8;
+}
+
+/// It is not yet known if BRK works.
+const UNKNOWN: usize = 0;
+/// The first BRK succeeded, so `ralloc` is used.
+const BRK: usize = 1;
+/// The first BRK failed, so libc is used.
+const FALLEN_BACK: usize = 2;
+
+/// The state of the fallback.
+static STATE: AtomicUsize = AtomicUsize::new(UNKNOWN);
+
+/// Is it known yet, whether to fall back or not?
+#[inline]
+pub fn is_determined() -> bool {
+    STATE.load(atomic::Ordering::Acquire) != UNKNOWN
+}
+
+/// Have we fallen back to libc?
+#[inline]
+pub fn is_active() -> bool {
+    STATE.load(atomic::Ordering::Acquire) == FALLEN_BACK
+}
+
+/// Record that the first BRK succeeded.
+///
+/// From now on, we never fall back.
+pub fn mark_brk() {
+    let _ = STATE.compare_exchange(
+        UNKNOWN,
+        BRK,
+        atomic::Ordering::AcqRel,
+        atomic::Ordering::Relaxed,
+    );
+}
+
+/// Fall back to libc, because the first BRK failed.
+///
+/// This fails if BRK already worked before (in which case the failure is an ordinary OOM), or if
+/// libc's allocator is not linked in.
+pub fn fall_back() -> Result<(), ()> {
+    if !is_available() {
+        return Err(());
+    }
+
+    match STATE.compare_exchange(
+        UNKNOWN,
+        FALLEN_BACK,
+        atomic::Ordering::AcqRel,
+        atomic::Ordering::Acquire,
+    ) {
+        Ok(_) | Err(FALLEN_BACK) => {
+            // Logging...
+            log!(WARNING, "BRK failed during initialization, falling back to libc.");
+
+            Ok(())
+        }
+        Err(_) => Err(()),
+    }
+}
+
+/// Is libc's allocator linked in?
+#[inline]
+fn is_available() -> bool {
+    unsafe { !libc_malloc.is_null() && !libc_free.is_null() }
+}
+
+/// The header stored right before every buffer allocated through libc.
+#[derive(Clone, Copy)]
+struct Header {
+    /// The pointer returned by `malloc`.
+    raw: usize,
+    /// The size of the buffer.
+    size: usize,
+    /// A checksum of the header and the address of the buffer.
+    ///
+    /// This tells a header written by `alloc` apart from arbitrary data before a pointer into the
+    /// middle of a buffer.
+    check: usize,
+}
+
+impl Header {
+    /// Create the header of a buffer.
+    fn new(raw: usize, size: usize, ptr: *mut u8) -> Header {
+        Header {
+            raw: raw,
+            size: size,
+            check: Header::checksum(raw, size, ptr),
+        }
+    }
+
+    /// Calculate the checksum of a header of a buffer.
+    fn checksum(raw: usize, size: usize, ptr: *mut u8) -> usize {
+        !(raw ^ size.rotate_left(16) ^ ptr as usize)
+    }
+}
+
+/// Allocate a buffer through libc.
+///
+/// `malloc` knows nothing about alignment (and `ralloc` allows alignments, which are not powers of
+/// two), so the buffer is aligned by hand, and a header holding the pointer returned by `malloc`
+/// and the size of the buffer is stored right before it. Null is returned on failure.
+pub unsafe fn alloc(size: usize, align: usize) -> *mut u8 {
+    /// `malloc`.
+    type Malloc = unsafe extern "C" fn(usize) -> *mut u8;
+
+    let header = mem::size_of::<Header>();
+    let raw_size = match size.checked_add(header).and_then(|x| x.checked_add(align)) {
+        Some(x) => x,
+        None => return ptr::null_mut(),
+    };
+
+    if !is_available() {
+        return ptr::null_mut();
+    }
+
+    let raw = mem::transmute::<*const u8, Malloc>(libc_malloc)(raw_size);
+    if raw.is_null() {
+        return ptr::null_mut();
+    }
+
+    // Leave space for the header, and align. `align` is non-zero, hence never more than
+    // `align - 1` bytes are skipped.
+    let start = raw as usize + header;
+    let res = (start + (align - start % align) % align) as *mut u8;
+    ptr::write_unaligned(
+        (res as *mut Header).offset(-1),
+        Header::new(raw as usize, size, res),
+    );
+
+    res
+}
+
+/// Free a buffer allocated through `alloc`.
+///
+/// libc can only free whole buffers, so unless `ptr` and `size` cover exactly the buffer, the
+/// free is ignored (and the memory leaked). `ptr` must point into a buffer allocated through
+/// `alloc`, at least `size_of::<Header>()` bytes past the pointer returned by `malloc`, which
+/// holds for every pointer into the buffer.
+pub unsafe fn free(ptr: *mut u8, size: usize) {
+    /// `free`.
+    type Free = unsafe extern "C" fn(*mut u8);
+
+    if !is_available() {
+        return;
+    }
+
+    let header = ptr::read_unaligned((ptr as *const Header).offset(-1));
+    if header.check != Header::checksum(header.raw, header.size, ptr) || header.size != size {
+        // Logging...
+        log!(WARNING, "Ignoring partial free of a buffer of libc, leaking {} bytes.", size);
+
+        return;
+    }
+
+    mem::transmute::<*const u8, Free>(libc_free)(header.raw as *mut u8);
+}
+
+/// Reallocate a buffer allocated through `alloc`.
+///
+/// On failure, null is returned and the old buffer is left intact.
+pub unsafe fn realloc(ptr: *mut u8, old_size: usize, size: usize, align: usize) -> *mut u8 {
+    let new = alloc(size, align);
+
+    if !new.is_null() {
+        ptr::copy_nonoverlapping(ptr, new, cmp::min(old_size, size));
+        free(ptr, old_size);
+    }
+
+    new
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_alloc_free() {
+        // The test binary links libc.
+        assert!(is_available());
+
+        unsafe {
+            for &align in &[1, 3, 8, 64, 4096] {
+                let ptr = alloc(100, align);
+                assert!(!ptr.is_null());
+                assert_eq!(ptr as usize % align, 0);
+
+                *ptr = 42;
+                *ptr.offset(99) = 7;
+
+                let new = realloc(ptr, 100, 200, align);
+                assert_eq!(*new, 42);
+                assert_eq!(*new.offset(99), 7);
+
+                free(new, 200);
+            }
+
+            assert!(alloc(!0, 1).is_null());
+
+            // Partial frees are ignored, and the whole buffer can still be freed afterwards.
+            let ptr = alloc(100, 8);
+            free(ptr, 50);
+            free(ptr.offset(50), 50);
+            free(ptr, 100);
+        }
+    }
+}
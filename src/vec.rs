@@ -83,6 +83,34 @@ impl<T: Leak> Vec<T> {
         self.cap
     }
 
+    /// Shrink the buffer to some capacity, and return the cut off end of it.
+    ///
+    /// The elements stay in place, so this is O(1).
+    ///
+    /// # Panics
+    ///
+    /// This panics if the vector is longer than the new capacity, or if the new capacity exceeds
+    /// the old one.
+    pub fn shrink(&mut self, cap: usize) -> Block {
+        log!(INTERNAL, "Shrinking vector...");
+
+        // Make some assertions.
+        assert!(self.len <= cap, "Shrinking below the length of the vector.");
+        assert!(cap <= self.cap, "Shrinking to a bigger capacity.");
+
+        let res = unsafe {
+            // The end of the buffer is owned by the vector, and lies within the buffer, since the
+            // capacity is bounded by it.
+            Block::from_raw_parts(
+                Pointer::new(self.ptr.get().offset(cap as isize)).cast(),
+                (self.cap - cap) * mem::size_of::<T>(),
+            )
+        };
+        self.cap = cap;
+
+        res
+    }
+
     /// Push an element to the end of this vector.
     ///
     /// On success, return `Ok(())`. On failure (not enough capacity), return `Err(())`.
@@ -106,6 +134,53 @@ impl<T: Leak> Vec<T> {
         }
     }
 
+    /// Push the elements of an iterator to the end of this vector.
+    ///
+    /// This never reallocates: If the capacity runs out, `Err(())` is returned, leaving the
+    /// elements pushed so far in the vector. The element, which did not fit, is lost, but the rest
+    /// of the iterator is left unconsumed (pass the iterator by reference to retrieve it).
+    #[cfg(test)]
+    #[inline]
+    pub fn extend<I: Iterator<Item = T>>(&mut self, iter: I) -> Result<(), ()> {
+        for elem in iter {
+            self.push(elem)?;
+        }
+
+        Ok(())
+    }
+
+    /// Insert an element at some index, shifting all the elements after it to the right.
+    ///
+    /// On success, return `Ok(())`. On failure (not enough capacity), return `Err(())`, leaving
+    /// the vector untouched.
+    ///
+    /// # Panics
+    ///
+    /// Panics on out-of-bound.
+    #[inline]
+    pub fn insert(&mut self, ind: usize, elem: T) -> Result<(), ()> {
+        // Bound check.
+        assert!(ind <= self.len, "Out of bound.");
+
+        if self.len == self.cap {
+            Err(())
+        } else {
+            unsafe {
+                // Memmove the elements to make a gap to the new element. The capacity check above
+                // makes sure there is room for the last element.
+                let ptr = self.ptr.get().offset(ind as isize);
+                ptr::copy(ptr, ptr.offset(1), self.len - ind);
+
+                // Place the element in the gap.
+                ptr::write(ptr, elem);
+            }
+
+            // Increment the length.
+            self.len += 1;
+            Ok(())
+        }
+    }
+
     /// Pop an element from the vector.
     ///
     /// If the vector is empty, `None` is returned.
@@ -121,8 +196,9 @@ impl<T: Leak> Vec<T> {
                 self.len -= 1;
 
                 // We use `ptr::read` since the element is unaccessible due to the decrease in the
-                // length.
-                Some(ptr::read(self.get_unchecked(self.len)))
+                // length. Note that indexing the slice would be out of bounds, so we offset the
+                // pointer instead.
+                Some(ptr::read(self.ptr.get().offset(self.len as isize)))
             }
         }
     }
@@ -264,4 +340,70 @@ mod test {
         assert!(vec.pop().is_none());
         assert!(vec.pop().is_none());
     }
+
+    #[test]
+    fn test_shrink() {
+        let mut buffer = [b'a'; 32];
+        let ptr = &mut buffer[0] as *mut u8;
+        let mut vec = unsafe { Vec::from_raw_parts(Block::from_raw_parts(Pointer::new(ptr), 32), 4) };
+
+        // The end of the buffer is cut off.
+        let tail = vec.shrink(8);
+        assert_eq!(tail.size(), 24);
+        assert_eq!(Pointer::from(tail).get(), unsafe { ptr.offset(8) });
+        assert_eq!(vec.capacity(), 8);
+        assert_eq!(&*vec, b"aaaa");
+
+        for _ in 0..4 {
+            vec.push(b'b').unwrap();
+        }
+        vec.push(b'c').unwrap_err();
+        assert_eq!(&*vec, b"aaaabbbb");
+
+        assert_eq!(Block::from(vec).size(), 8);
+    }
+
+    #[test]
+    fn test_extend() {
+        let mut buffer = [b'a'; 8];
+        let mut vec = unsafe {
+            Vec::from_raw_parts(
+                Block::from_raw_parts(Pointer::new(&mut buffer[0] as *mut u8), 8),
+                2,
+            )
+        };
+
+        vec.extend(b"bc".iter().cloned()).unwrap();
+        vec.extend(None.into_iter()).unwrap();
+        assert_eq!(&*vec, b"aabc");
+
+        // Out of capacity in the middle.
+        let mut iter = b"defghi".iter().cloned();
+        vec.extend(&mut iter).unwrap_err();
+        assert_eq!(&*vec, b"aabcdefg");
+        assert_eq!(iter.next(), Some(b'i'));
+    }
+
+    #[test]
+    fn test_insert() {
+        let mut buffer = [b'a'; 8];
+        let mut vec = unsafe {
+            Vec::from_raw_parts(
+                Block::from_raw_parts(Pointer::new(&mut buffer[0] as *mut u8), 8),
+                4,
+            )
+        };
+
+        vec.insert(0, b'b').unwrap();
+        vec.insert(5, b'c').unwrap();
+        vec.insert(2, b'd').unwrap();
+        assert_eq!(&*vec, b"badaaac");
+
+        vec.insert(1, b'e').unwrap();
+        assert_eq!(&*vec, b"beadaaac");
+
+        // Out of capacity.
+        vec.insert(3, b'h').unwrap_err();
+        assert_eq!(&*vec, b"beadaaac");
+    }
 }
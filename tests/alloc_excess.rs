@@ -0,0 +1,40 @@
+extern crate ralloc;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+mod util;
+
+use std::ptr;
+
+#[test]
+fn alloc_excess() {
+    util::multiply(|| {
+        let (buf, size) = ralloc::alloc_excess(200, 8);
+        assert!(size >= 200);
+        assert_eq!(buf as usize % 8, 0);
+
+        unsafe {
+            // The whole buffer is usable.
+            util::acid(|| {
+                ptr::write_bytes(buf, 0xAB, size);
+            });
+
+            let buf = ralloc::realloc(buf, size, 300, 8);
+            assert_eq!(*buf.offset(199), 0xAB);
+
+            ralloc::free(buf, 300);
+        }
+    });
+}
+
+#[test]
+fn alloc_excess_zero() {
+    let (buf, size) = ralloc::alloc_excess(0, 16);
+    assert!(!buf.is_null());
+    assert_eq!(size, 0);
+
+    unsafe {
+        ralloc::free(buf, 0);
+    }
+}
@@ -0,0 +1,51 @@
+extern crate ralloc;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+mod util;
+
+#[test]
+fn alloc_rt() {
+    util::multiply(|| {
+        // Make sure the pool has some memory.
+        unsafe {
+            ralloc::free(ralloc::alloc(1024, 8), 1024);
+        }
+
+        if let Some(ptr) = ralloc::alloc_rt(16, 8) {
+            assert!(ptr as usize % 8 == 0);
+
+            unsafe {
+                *ptr = 42;
+                ralloc::free(ptr, 16);
+            }
+        }
+    });
+}
+
+#[test]
+fn alloc_rt_never_brks() {
+    util::multiply(|| {
+        // Nothing can hold this, so it must fail without BRK'ing or calling the OOM handler.
+        assert!(ralloc::alloc_rt(1 << 40, 1).is_none());
+    });
+}
+
+#[cfg(feature = "size_class_cache")]
+#[test]
+fn alloc_rt_cache_hit() {
+    util::multiply(|| {
+        // The freed block goes to the size class cache, and is the first of its class.
+        let ptr = ralloc::alloc(16, 8);
+        unsafe {
+            ralloc::free(ptr, 16);
+        }
+
+        assert_eq!(ralloc::alloc_rt(16, 8), Some(ptr));
+
+        unsafe {
+            ralloc::free(ptr, 16);
+        }
+    });
+}
@@ -0,0 +1,35 @@
+//! `alloc_rt` must split cached blocks exactly, even if the remainder is too small to be cached.
+//!
+//! The shard statistics only cover the global allocator, which `alloc_rt` only uses without TLS.
+
+#![cfg(all(feature = "size_class_cache", feature = "introspection", not(feature = "tls")))]
+
+extern crate ralloc;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+/// Get the number of free bytes of the global allocator.
+fn free_bytes() -> usize {
+    ralloc::shard_stats().iter().map(|&(bytes, _)| bytes).sum()
+}
+
+#[test]
+fn alloc_rt_small_remainder() {
+    let before = free_bytes();
+
+    for _ in 0..256 {
+        unsafe {
+            // Cache a block, which leaves a remainder too small to be cached when taking 16 bytes
+            // from it.
+            ralloc::free(ralloc::alloc(24, 8), 24);
+
+            if let Some(ptr) = ralloc::alloc_rt(16, 8) {
+                ralloc::free(ptr, 16);
+            }
+        }
+    }
+
+    // No remainder was lost.
+    assert!(free_bytes() >= before);
+}
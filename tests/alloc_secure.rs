@@ -0,0 +1,70 @@
+extern crate ralloc;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+mod util;
+
+use std::ptr;
+
+#[test]
+fn alloc_secure() {
+    util::multiply(|| {
+        for size in 1..64 {
+            unsafe {
+                // Leave some garbage in the pool.
+                let buf = ralloc::alloc(size * 8, 8);
+                ptr::write_bytes(buf, 0xFF, size * 8);
+                ralloc::free(buf, size * 8);
+
+                let buf = ralloc::alloc_secure(size * 8, 8);
+                for i in 0..size * 8 {
+                    assert_eq!(*buf.offset(i as isize), 0);
+                }
+
+                util::acid(|| {
+                    ralloc::free(buf, size * 8);
+                });
+            }
+        }
+    });
+}
+
+#[test]
+fn alloc_secure_fresh() {
+    util::multiply(|| {
+        for &size in &[1 << 12, 1 << 16, 1 << 20] {
+            unsafe {
+                // The buffer is likely to be fresh memory. Compacting hands it to the global
+                // allocator, which might release it to the OS, so the next one is BRK'd again.
+                let buf = ralloc::alloc_secure(size, 8);
+                ptr::write_bytes(buf, 0xFF, size);
+                ralloc::free(buf, size);
+                ralloc::compact();
+
+                let buf = ralloc::alloc_secure(size, 8);
+                for i in 0..size {
+                    assert_eq!(*buf.offset(i as isize), 0);
+                }
+
+                util::acid(|| {
+                    ralloc::free(buf, size);
+                });
+            }
+        }
+    });
+}
+
+#[test]
+fn alloc_zeroed() {
+    util::multiply(|| {
+        for size in 1..2000 {
+            let mut vec = vec![0u8; size * 37];
+            assert!(vec.iter().all(|&x| x == 0));
+
+            for x in &mut vec {
+                *x = 0xFF;
+            }
+        }
+    });
+}
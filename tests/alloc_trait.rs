@@ -0,0 +1,37 @@
+#![feature(allocator_api)]
+
+extern crate ralloc;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+mod util;
+
+use std::alloc::{Alloc, Layout};
+use std::ptr;
+
+#[test]
+fn alloc_trait_realloc_align() {
+    util::multiply(|| unsafe {
+        for align in [16, 64, 256, 4096].iter() {
+            let layout = Layout::from_size_align(100, *align).unwrap();
+            let buf = (&ALLOCATOR).alloc(layout).unwrap();
+            assert_eq!(buf.as_ptr() as usize % align, 0);
+            util::acid(|| {
+                ptr::write_bytes(buf.as_ptr(), 0xAB, 100);
+            });
+
+            // Grow, and shrink back. The buffer stays aligned either way.
+            let buf = (&ALLOCATOR).realloc(buf, layout, 300).unwrap();
+            assert_eq!(buf.as_ptr() as usize % align, 0);
+            assert_eq!(*buf.as_ptr().offset(99), 0xAB);
+
+            let big = Layout::from_size_align(300, *align).unwrap();
+            let buf = (&ALLOCATOR).realloc(buf, big, 50).unwrap();
+            assert_eq!(buf.as_ptr() as usize % align, 0);
+            assert_eq!(*buf.as_ptr().offset(49), 0xAB);
+
+            (&ALLOCATOR).dealloc(buf, Layout::from_size_align(50, *align).unwrap());
+        }
+    });
+}
@@ -0,0 +1,82 @@
+#![cfg(feature = "c_symbols")]
+
+extern crate ralloc;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+use std::{mem, ptr};
+
+extern {
+    fn malloc(size: usize) -> *mut u8;
+    fn calloc(nmemb: usize, size: usize) -> *mut u8;
+    fn realloc(ptr: *mut u8, size: usize) -> *mut u8;
+    fn free(ptr: *mut u8);
+    fn posix_memalign(memptr: *mut *mut u8, align: usize, size: usize) -> i32;
+    fn malloc_usable_size(ptr: *mut u8) -> usize;
+}
+
+#[test]
+fn calloc_zeroed() {
+    unsafe {
+        let ptr = calloc(10, 8);
+        assert!(!ptr.is_null());
+        assert_eq!(ptr as usize % mem::size_of::<usize>(), 0);
+        for i in 0..80 {
+            assert_eq!(*ptr.offset(i), 0);
+        }
+
+        free(ptr);
+    }
+}
+
+#[test]
+fn calloc_overflow() {
+    unsafe {
+        // Call through a volatile pointer, since LLVM knows `calloc`, and would elide the unused
+        // allocation.
+        let calloc: unsafe extern fn(usize, usize) -> *mut u8 =
+            ptr::read_volatile(&(calloc as unsafe extern fn(usize, usize) -> *mut u8));
+
+        assert!(calloc(usize::max_value(), 2).is_null());
+        assert!(calloc(2, usize::max_value()).is_null());
+    }
+}
+
+#[test]
+fn malloc_realloc_free() {
+    unsafe {
+        let ptr = malloc(16);
+        assert!(!ptr.is_null());
+        assert!(malloc_usable_size(ptr) >= 16);
+        for i in 0..16 {
+            *ptr.offset(i) = i as u8;
+        }
+
+        let ptr = realloc(ptr, 4096);
+        assert!(malloc_usable_size(ptr) >= 4096);
+        for i in 0..16 {
+            assert_eq!(*ptr.offset(i), i as u8);
+        }
+
+        free(ptr);
+        free(0 as *mut u8);
+    }
+}
+
+#[test]
+fn posix_memalign_aligned() {
+    unsafe {
+        let mut ptr = 0 as *mut u8;
+        assert_eq!(posix_memalign(&mut ptr, 4096, 100), 0);
+        assert_eq!(ptr as usize % 4096, 0);
+
+        let ptr = realloc(ptr, 10000);
+        assert_eq!(ptr as usize % 4096, 0);
+        free(ptr);
+
+        let mut ptr = 0 as *mut u8;
+        assert!(posix_memalign(&mut ptr, 3, 100) != 0);
+        assert!(ptr.is_null());
+    }
+}
@@ -0,0 +1,51 @@
+#![cfg(feature = "canary")]
+
+extern crate ralloc;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+mod util;
+
+use std::env;
+
+#[test]
+fn canary() {
+    util::multiply(|| unsafe {
+        let ptr = ralloc::alloc(30, 8);
+        *ptr.offset(29) = 1;
+
+        // Grow, shrink, and grow inplace, moving the canary around.
+        let ptr = ralloc::realloc(ptr, 30, 300, 8);
+        *ptr.offset(299) = 1;
+        let ptr = ralloc::realloc(ptr, 300, 20, 8);
+        *ptr.offset(19) = 1;
+        if ralloc::realloc_inplace(ptr, 20, 40).is_ok() {
+            *ptr.offset(39) = 1;
+            ralloc::free(ptr, 40);
+        } else {
+            ralloc::free(ptr, 20);
+        }
+    });
+}
+
+#[test]
+fn canary_overflow() {
+    if env::var_os("RALLOC_CANARY_OVERFLOW").is_some() {
+        unsafe {
+            let ptr = ralloc::alloc(16, 8);
+            // Overflow the buffer by one byte.
+            *ptr.offset(16) = 0;
+            ralloc::free(ptr, 16);
+        }
+
+        return;
+    }
+
+    // The overflow aborts the process, so it is run in a child process.
+    let stderr = util::run_child("canary_overflow", "RALLOC_CANARY_OVERFLOW");
+    // The error is only printed with `log`.
+    if cfg!(feature = "log") {
+        assert!(stderr.contains("Heap overflow"));
+    }
+}
@@ -0,0 +1,38 @@
+#![cfg(feature = "introspection")]
+
+extern crate ralloc;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+#[test]
+fn class_stats() {
+    let before = ralloc::class_stats();
+
+    // Allocate across a few size classes.
+    let bufs: Vec<_> = (0..10)
+        .map(|i| (ralloc::alloc(3 << i, 1), 3 << i))
+        .collect();
+
+    let during = ralloc::class_stats();
+    for i in 0..10 {
+        // `3 << i` lies in class `i + 2`.
+        let class = i + 2;
+        assert!(during[class].allocs > before[class].allocs);
+        assert!(during[class].live > 0);
+        assert!(during[class].peak >= during[class].live);
+    }
+
+    for (ptr, size) in bufs {
+        unsafe {
+            ralloc::free(ptr, size);
+        }
+    }
+
+    let after = ralloc::class_stats();
+    for i in 0..10 {
+        let class = i + 2;
+        assert!(after[class].frees > during[class].frees);
+        assert!(after[class].peak >= during[class].peak);
+    }
+}
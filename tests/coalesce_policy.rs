@@ -0,0 +1,42 @@
+#![cfg(feature = "deferred_coalescing")]
+
+extern crate ralloc;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+mod util;
+
+use ralloc::CoalescePolicy;
+
+#[test]
+fn deferred() {
+    ralloc::set_coalesce_policy(CoalescePolicy::Deferred);
+
+    util::multiply(|| {
+        let mut vec = Vec::new();
+
+        // Churn through allocations of various sizes.
+        for i in 0..0x400 {
+            util::acid(|| {
+                vec.push(vec![i as u8; i % 97]);
+            });
+
+            if i % 3 == 0 {
+                vec.swap_remove(i % vec.len());
+            }
+        }
+
+        for x in &vec {
+            assert!(x.iter().all(|&y| y == x.first().cloned().unwrap_or(0)));
+        }
+
+        // Allocate a block bigger than any of the freed ones, missing the deferred blocks.
+        let buf = ralloc::alloc(1 << 16, 8);
+        unsafe {
+            ralloc::free(buf, 1 << 16);
+        }
+    });
+
+    ralloc::set_coalesce_policy(CoalescePolicy::Eager);
+}
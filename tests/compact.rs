@@ -0,0 +1,33 @@
+extern crate ralloc;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+mod util;
+
+#[test]
+fn compact() {
+    util::multiply(|| {
+        let mut bufs: Vec<_> = (0..256).map(|i| Box::new([i as u8; 24])).collect();
+
+        // Leave a lot of small non-adjacent blocks behind.
+        for i in (0..256).rev().step_by(2) {
+            bufs.remove(i);
+        }
+
+        util::acid(|| {
+            ralloc::compact();
+        });
+
+        for (i, buf) in bufs.iter().enumerate() {
+            assert_eq!(buf[0], (2 * i) as u8);
+        }
+
+        drop(bufs);
+        ralloc::compact();
+
+        // Make sure the allocator is still usable.
+        let buf = Box::new([0u8; 4096]);
+        assert_eq!(buf[4095], 0);
+    });
+}
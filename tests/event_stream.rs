@@ -0,0 +1,70 @@
+#![cfg(feature = "event_stream")]
+
+extern crate ralloc;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+use std::io::Read;
+use std::mem;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+
+/// A size nothing else allocates.
+const SIZE: usize = 12345;
+
+/// Decode a record of the stream.
+fn decode(record: &[u8]) -> [u64; 4] {
+    let mut res = [0; 4];
+    for (i, x) in res.iter_mut().enumerate() {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&record[8 * i..8 * i + 8]);
+        *x = unsafe { mem::transmute(bytes) };
+    }
+
+    res
+}
+
+#[test]
+fn event_stream() {
+    let (mut rx, tx) = UnixStream::pair().unwrap();
+    // Allocate the buffer beforehand, so reading doesn't allocate.
+    let mut buf = vec![0; 1 << 16];
+    rx.set_nonblocking(true).unwrap();
+
+    // The stream must be non-blocking, and is left as is.
+    assert!(ralloc::set_event_stream(tx.as_raw_fd() as usize).is_err());
+    tx.set_nonblocking(true).unwrap();
+    ralloc::set_event_stream(tx.as_raw_fd() as usize).unwrap();
+
+    let ptr = unsafe {
+        let ptr = ralloc::alloc(SIZE, 8);
+        let ptr = ralloc::realloc(ptr, SIZE, 2 * SIZE, 8);
+        ralloc::free(ptr, 2 * SIZE);
+
+        ptr
+    };
+
+    let len = rx.read(&mut buf).unwrap();
+    let records: Vec<_> = buf[..len].chunks(32).map(decode).collect();
+
+    // Find our events.
+    let alloc = records
+        .iter()
+        .position(|r| r[0] == 0 && r[2] == SIZE as u64)
+        .unwrap();
+    let realloc = records
+        .iter()
+        .position(|r| r[0] == 2 && r[1] == ptr as u64 && r[2] == 2 * SIZE as u64)
+        .unwrap();
+    let free = records
+        .iter()
+        .position(|r| r[0] == 1 && r[1] == ptr as u64 && r[2] == 2 * SIZE as u64)
+        .unwrap();
+
+    // The reallocation frees the old buffer first.
+    assert_eq!(records[realloc - 1][0], 1);
+    assert_eq!(records[realloc - 1][1], records[alloc][1]);
+    assert!(alloc < realloc && realloc < free);
+    assert!(records[alloc][3] <= records[free][3]);
+}
@@ -0,0 +1,22 @@
+extern crate ralloc;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+/// The size of the allocation.
+const SIZE: usize = 1 << 20;
+
+#[test]
+fn exact_brk() {
+    // The extra space of every BRK overflows, so only exact BRKs succeed.
+    ralloc::set_brk_extra_multiplier(!0);
+
+    unsafe {
+        let ptr = ralloc::alloc(SIZE, 8);
+        *ptr = 1;
+        *ptr.offset(SIZE as isize - 1) = 1;
+        ralloc::free(ptr, SIZE);
+    }
+
+    ralloc::set_brk_extra_multiplier(1);
+}
@@ -0,0 +1,33 @@
+#![cfg(feature = "testing")]
+
+extern crate ralloc;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+use std::alloc::{GlobalAlloc, Layout};
+use std::ptr;
+
+/// The number of allocations to succeed.
+const N: usize = 8;
+
+#[test]
+fn fail_after() {
+    let layout = Layout::from_size_align(16, 8).unwrap();
+    let mut bufs = [ptr::null_mut(); N];
+
+    unsafe {
+        ralloc::fail_after(N);
+        for buf in bufs.iter_mut() {
+            *buf = ALLOCATOR.alloc(layout);
+        }
+        let failed = ALLOCATOR.alloc(layout);
+        ralloc::fail_after(!0);
+
+        assert!(failed.is_null());
+        for &buf in bufs.iter() {
+            assert!(!buf.is_null());
+            ALLOCATOR.dealloc(buf, layout);
+        }
+    }
+}
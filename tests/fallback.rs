@@ -0,0 +1,64 @@
+extern crate ralloc;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// The size of the emergency pool.
+const POOL_SIZE: usize = 1 << 21;
+
+/// The emergency pool.
+static mut POOL: [u8; POOL_SIZE] = [0; POOL_SIZE];
+/// Has the emergency pool been handed out?
+static USED: AtomicBool = AtomicBool::new(false);
+
+fn emergency(size: usize, align: usize) -> *mut u8 {
+    unsafe {
+        let ptr = POOL.as_mut_ptr();
+        let aligner = (align - ptr as usize % align) % align;
+
+        if aligner + size > POOL_SIZE || USED.swap(true, Ordering::SeqCst) {
+            std::ptr::null_mut()
+        } else {
+            ptr.offset(aligner as isize)
+        }
+    }
+}
+
+#[repr(C)]
+struct Rlimit {
+    cur: u64,
+    max: u64,
+}
+
+extern "C" {
+    fn getrlimit(resource: i32, rlim: *mut Rlimit) -> i32;
+    fn setrlimit(resource: i32, rlim: *const Rlimit) -> i32;
+}
+
+/// `RLIMIT_DATA`, which limits the program break.
+const RLIMIT_DATA: i32 = 2;
+
+#[test]
+fn fallback() {
+    ralloc::set_fallback(emergency);
+
+    unsafe {
+        let mut old = Rlimit { cur: 0, max: 0 };
+        assert_eq!(getrlimit(RLIMIT_DATA, &mut old), 0);
+
+        // Make BRK fail.
+        assert_eq!(setrlimit(RLIMIT_DATA, &Rlimit { cur: 0, max: old.max }), 0);
+        let ptr = ralloc::try_alloc(1 << 20, 64);
+        assert_eq!(setrlimit(RLIMIT_DATA, &old), 0);
+
+        let ptr = ptr.unwrap();
+        assert!(USED.load(Ordering::SeqCst));
+        assert!(ptr >= POOL.as_mut_ptr() && ptr < POOL.as_mut_ptr().offset(POOL_SIZE as isize));
+        assert_eq!(ptr as usize % 64, 0);
+
+        *ptr = 42;
+        ralloc::free(ptr, 1 << 20);
+    }
+}
@@ -0,0 +1,34 @@
+// This must be compiled with `-C force-frame-pointers=yes`, which is signaled through
+// `--cfg frame_pointers`. Backtraces are only captured on Linux.
+#![cfg(all(feature = "fp_backtrace", frame_pointers, target_os = "linux"))]
+
+extern crate ralloc;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A size nothing else allocates.
+const SIZE: usize = 12345;
+
+static FRAMES: AtomicUsize = AtomicUsize::new(0);
+
+fn walk(_: *mut u8, size: usize, backtrace: &[usize]) {
+    if size == SIZE {
+        FRAMES.store(backtrace.len(), Ordering::SeqCst);
+    }
+}
+
+#[test]
+fn walk_live() {
+    // An outstanding allocation.
+    let ptr = ralloc::alloc(SIZE, 8);
+
+    ralloc::walk_live(walk);
+    assert!(FRAMES.load(Ordering::SeqCst) > 0);
+
+    unsafe {
+        ralloc::free(ptr, SIZE);
+    }
+}
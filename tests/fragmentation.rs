@@ -0,0 +1,27 @@
+extern crate ralloc;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+#[test]
+fn fragmentation() {
+    let frag = ralloc::fragmentation();
+    assert!(frag >= 0.0 && frag < 1.0);
+
+    unsafe {
+        let bufs: Vec<_> = (0..100).map(|_| ralloc::alloc(1000, 8)).collect();
+
+        // Free every other buffer, leaving holes between the remaining ones.
+        for buf in bufs.iter().step_by(2) {
+            ralloc::free(*buf, 1000);
+        }
+
+        // There are many free blocks now, so none of them holds all the free memory.
+        let frag = ralloc::fragmentation();
+        assert!(frag > 0.0 && frag < 1.0);
+
+        for buf in bufs.iter().skip(1).step_by(2) {
+            ralloc::free(*buf, 1000);
+        }
+    }
+}
@@ -0,0 +1,42 @@
+extern crate ralloc;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use ralloc::AllocEvent;
+
+/// The number of reallocations.
+static REALLOCS: AtomicUsize = AtomicUsize::new(0);
+/// The number of reallocations, which didn't move the buffer.
+static INPLACE: AtomicUsize = AtomicUsize::new(0);
+
+fn hook(event: AllocEvent) {
+    if let AllocEvent::Realloc { old_ptr, ptr, .. } = event {
+        REALLOCS.fetch_add(1, Ordering::SeqCst);
+        if old_ptr == ptr {
+            INPLACE.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+#[test]
+fn global_realloc() {
+    ralloc::set_alloc_hook(hook);
+
+    // Leave a free block, which the vector can grow into.
+    drop(Vec::<u8>::with_capacity(1 << 16));
+
+    // Growing goes through `GlobalAlloc::realloc`, rather than alloc-copy-dealloc.
+    let mut vec = Vec::new();
+    for i in 0..100000 {
+        vec.push(i as u8);
+    }
+
+    assert!(REALLOCS.load(Ordering::SeqCst) > 0);
+    assert!(INPLACE.load(Ordering::SeqCst) > 0);
+    for (i, &x) in vec.iter().enumerate() {
+        assert_eq!(x, i as u8);
+    }
+}
@@ -0,0 +1,45 @@
+extern crate ralloc;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+use std::thread;
+
+/// The number of threads allocating at once.
+const THREADS: usize = 8;
+/// The number of buffers every thread holds.
+const BUFFERS: usize = 4;
+/// The size of the buffers, which is too big to be served without fresh memory.
+const SIZE: usize = 1 << 20;
+
+/// No grow is lost, whatever the ordering of the counters (see `precise_stats`).
+#[test]
+fn grow_count_exact() {
+    let before = ralloc::grow_count() + ralloc::avoided_grow_count();
+
+    let join: Vec<_> = (0..THREADS)
+        .map(|_| {
+            thread::spawn(move || {
+                let bufs: Vec<_> = (0..BUFFERS)
+                    .map(|_| ralloc::alloc(SIZE, 8) as usize)
+                    .collect();
+
+                // Every buffer needed fresh memory, which is counted as either a grow or an
+                // avoided grow, so none of them must be lost.
+                assert!(ralloc::grow_count() + ralloc::avoided_grow_count() >= before + BUFFERS);
+
+                bufs
+            })
+        })
+        .collect();
+
+    // Joining the threads makes all their grows visible.
+    let bufs: Vec<_> = join.into_iter().flat_map(|i| i.join().unwrap()).collect();
+    assert!(ralloc::grow_count() + ralloc::avoided_grow_count() >= before + THREADS * BUFFERS);
+
+    for buf in bufs {
+        unsafe {
+            ralloc::free(buf as *mut u8, SIZE);
+        }
+    }
+}
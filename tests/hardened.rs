@@ -0,0 +1,84 @@
+#![cfg(feature = "hardened")]
+
+extern crate ralloc;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+mod util;
+
+use std::{env, ptr};
+
+#[test]
+fn zero_on_free() {
+    unsafe {
+        let ptr = ralloc::alloc(64, 8);
+        ptr::write_bytes(ptr, 0xAA, 64);
+        ralloc::free(ptr, 64);
+
+        // The buffer is too small to be protected, but zeroed.
+        for i in 0..64 {
+            assert_eq!(ptr::read_volatile(ptr.offset(i)), 0);
+        }
+        // The freed marker replacing the canary is kept, so double frees are still caught.
+        assert!(ptr::read_unaligned(ptr.offset(64) as *const u32) != 0);
+    }
+}
+
+#[test]
+fn double_free() {
+    if env::var_os("RALLOC_DOUBLE_FREE").is_some() {
+        unsafe {
+            let ptr = ralloc::alloc(16, 8);
+            ralloc::free(ptr, 16);
+            ralloc::free(ptr, 16);
+        }
+
+        return;
+    }
+
+    let stderr = util::run_child("double_free", "RALLOC_DOUBLE_FREE");
+    // The error is only printed with `log`.
+    if cfg!(feature = "log") {
+        assert!(stderr.contains("Double free"));
+    }
+}
+
+#[test]
+fn double_free_realloc() {
+    if env::var_os("RALLOC_DOUBLE_FREE_REALLOC").is_some() {
+        unsafe {
+            let ptr = ralloc::alloc(16, 8);
+            // Block the buffer from growing inplace, so it moves.
+            let _block = ralloc::alloc(16, 8);
+            ralloc::realloc(ptr, 16, 4096, 8);
+            ralloc::free(ptr, 16);
+        }
+
+        return;
+    }
+
+    let stderr = util::run_child("double_free_realloc", "RALLOC_DOUBLE_FREE_REALLOC");
+    if cfg!(feature = "log") {
+        assert!(stderr.contains("Double free"));
+    }
+}
+
+#[test]
+fn overflow() {
+    if env::var_os("RALLOC_OVERFLOW").is_some() {
+        unsafe {
+            let ptr = ralloc::alloc(16, 8);
+            // Overflow the buffer by one byte.
+            *ptr.offset(16) = 0;
+            ralloc::free(ptr, 16);
+        }
+
+        return;
+    }
+
+    let stderr = util::run_child("overflow", "RALLOC_OVERFLOW");
+    if cfg!(feature = "log") {
+        assert!(stderr.contains("Heap overflow"));
+    }
+}
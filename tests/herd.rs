@@ -0,0 +1,38 @@
+extern crate ralloc;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+use std::sync::{Arc, Barrier};
+use std::thread;
+
+/// The number of threads allocating at once.
+const THREADS: usize = 32;
+
+#[test]
+fn herd() {
+    let barrier = Arc::new(Barrier::new(THREADS));
+    let before = ralloc::grow_count();
+
+    // Start a bunch of threads from cold, all allocating at once.
+    let join: Vec<_> = (0..THREADS)
+        .map(|_| {
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                barrier.wait();
+
+                let buf = ralloc::alloc(64, 8);
+                unsafe {
+                    ralloc::free(buf, 64);
+                }
+            })
+        })
+        .collect();
+
+    for i in join {
+        i.join().unwrap();
+    }
+
+    let grows = ralloc::grow_count() - before;
+    assert!(grows < THREADS / 4, "{} grows for {} threads", grows, THREADS);
+}
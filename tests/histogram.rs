@@ -0,0 +1,28 @@
+#![cfg(feature = "histogram")]
+
+extern crate ralloc;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+#[test]
+fn size_histogram() {
+    let before = ralloc::size_histogram();
+
+    // Allocate across a few buckets.
+    let bufs: Vec<_> = (0..10)
+        .map(|i| (ralloc::alloc(3 << i, 1), 3 << i))
+        .collect();
+
+    let after = ralloc::size_histogram();
+    for i in 0..10 {
+        // `3 << i` lies in bucket `i + 2`.
+        assert!(after[i + 2] > before[i + 2]);
+    }
+
+    for (ptr, size) in bufs {
+        unsafe {
+            ralloc::free(ptr, size);
+        }
+    }
+}
@@ -0,0 +1,47 @@
+extern crate ralloc;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use ralloc::AllocEvent;
+
+/// A size nothing else allocates.
+const SIZE: usize = 12345;
+
+static ALLOCATED: AtomicBool = AtomicBool::new(false);
+static REALLOCATED: AtomicBool = AtomicBool::new(false);
+static FREED: AtomicBool = AtomicBool::new(false);
+
+fn hook(event: AllocEvent) {
+    match event {
+        AllocEvent::Alloc { size: SIZE, align: 8, .. } => ALLOCATED.store(true, Ordering::SeqCst),
+        AllocEvent::Realloc {
+            old_size: SIZE,
+            size,
+            ..
+        } if size == 2 * SIZE => REALLOCATED.store(true, Ordering::SeqCst),
+        AllocEvent::Free { size, .. } if size == 2 * SIZE => FREED.store(true, Ordering::SeqCst),
+        _ => (),
+    }
+
+    // Allocations from within the hook are not traced.
+    drop(Box::new(0u64));
+}
+
+#[test]
+fn alloc_hook() {
+    ralloc::set_alloc_hook(hook);
+
+    unsafe {
+        let ptr = ralloc::alloc(SIZE, 8);
+        assert!(ALLOCATED.load(Ordering::SeqCst));
+
+        let ptr = ralloc::realloc(ptr, SIZE, 2 * SIZE, 8);
+        assert!(REALLOCATED.load(Ordering::SeqCst));
+
+        ralloc::free(ptr, 2 * SIZE);
+        assert!(FREED.load(Ordering::SeqCst));
+    }
+}
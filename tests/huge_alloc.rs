@@ -0,0 +1,35 @@
+extern crate ralloc;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+mod util;
+
+use std::env;
+use std::usize;
+
+#[test]
+fn try_alloc_huge() {
+    // The size doesn't fit in an `isize`, so it can never be BRK'd.
+    assert!(ralloc::try_alloc(usize::MAX - 16, 1).is_none());
+
+    // The allocator is still usable.
+    let buf = ralloc::alloc(30, 3);
+    unsafe {
+        ralloc::free(buf, 30);
+    }
+}
+
+#[test]
+fn alloc_huge() {
+    if env::var_os("RALLOC_ALLOC_HUGE").is_some() {
+        ralloc::alloc(usize::MAX - 16, 1);
+
+        return;
+    }
+
+    // The default OOM handler aborts the process, so it is run in a child process.
+    let stderr = util::run_child("alloc_huge", "RALLOC_ALLOC_HUGE");
+    // The OOM handler was called, rather than something panicking.
+    assert!(stderr.contains("ran out of memory"));
+}
@@ -0,0 +1,25 @@
+extern crate ralloc;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+#[test]
+fn idle_trim() {
+    ralloc::set_idle_trim(true, 0);
+
+    let buf = ralloc::alloc(1 << 20, 1);
+    unsafe {
+        ralloc::free(buf, 1 << 20);
+    }
+
+    let brk = unsafe { ralloc::sbrk(0) };
+
+    // Stay idle for a while.
+    for _ in 0..64 {
+        ralloc::tick();
+    }
+
+    assert!(unsafe { ralloc::sbrk(0) } < brk);
+
+    ralloc::set_idle_trim(false, 0);
+}
@@ -0,0 +1,15 @@
+extern crate ralloc;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+#[test]
+fn largest_free_block() {
+    let ptr = ralloc::alloc(1 << 20, 8);
+    unsafe {
+        ralloc::free(ptr, 1 << 20);
+    }
+
+    // The freed block is in either the local or the global pool (possibly merged).
+    assert!(ralloc::largest_free_block() >= 1 << 20);
+}
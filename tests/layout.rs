@@ -0,0 +1,75 @@
+extern crate ralloc;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+mod util;
+
+use std::alloc::{GlobalAlloc, Layout};
+use std::ptr;
+
+#[test]
+fn layout() {
+    util::multiply(|| {
+        for align in [1, 2, 8, 16, 64, 256].iter() {
+            let layout = Layout::from_size_align(100, *align).unwrap();
+
+            let ptr = ralloc::alloc_layout(layout);
+            assert_eq!(ptr as usize % align, 0);
+
+            unsafe {
+                ptr::write_bytes(ptr, 0xAB, 100);
+                assert_eq!(*ptr.offset(99), 0xAB);
+
+                util::acid(|| {
+                    ralloc::free_layout(ptr, layout);
+                });
+            }
+        }
+    });
+}
+
+#[test]
+fn realloc_layout() {
+    util::multiply(|| unsafe {
+        let small = Layout::from_size_align(100, 8).unwrap();
+
+        for align in [16, 64, 256].iter() {
+            let ptr = ralloc::alloc_layout(small);
+            ptr::write_bytes(ptr, 0xAB, 100);
+
+            // Grow, strengthening the alignment.
+            let big = Layout::from_size_align(200, *align).unwrap();
+            let ptr = ralloc::realloc_layout(ptr, small, big);
+            assert_eq!(ptr as usize % align, 0);
+            assert_eq!(*ptr.offset(99), 0xAB);
+
+            // Shrink back.
+            let ptr = ralloc::realloc_layout(ptr, big, small);
+            assert_eq!(*ptr.offset(99), 0xAB);
+
+            ralloc::free_layout(ptr, small);
+        }
+    });
+}
+
+#[test]
+fn layout_overflow() {
+    unsafe {
+        // Such a layout cannot be built safely, but nothing stops unsafe code from doing so.
+        let huge = Layout::from_size_align_unchecked(!0 - 8, 16);
+
+        assert!(ALLOCATOR.alloc(huge).is_null());
+        assert!(ALLOCATOR.alloc_zeroed(huge).is_null());
+
+        let layout = Layout::from_size_align(100, 16).unwrap();
+        let ptr = ALLOCATOR.alloc(layout);
+        *ptr = 42;
+
+        // The old buffer is left intact.
+        assert!(ALLOCATOR.realloc(ptr, layout, !0 - 8).is_null());
+        assert_eq!(*ptr, 42);
+
+        ALLOCATOR.dealloc(ptr, layout);
+    }
+}
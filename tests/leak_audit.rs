@@ -0,0 +1,64 @@
+#![cfg(feature = "leak_audit")]
+
+extern crate ralloc;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "log")]
+use std::sync::atomic::AtomicBool;
+#[cfg(feature = "log")]
+use std::thread;
+
+/// A size nothing else allocates.
+const SIZE: usize = 12345;
+
+static LEAKS: AtomicUsize = AtomicUsize::new(0);
+
+fn report(_: *mut u8, size: usize) {
+    if size == SIZE {
+        LEAKS.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[test]
+fn report_leaks() {
+    let ptr = ralloc::alloc(SIZE, 8);
+
+    ralloc::report_leaks(report);
+    assert_eq!(LEAKS.load(Ordering::SeqCst), 1);
+
+    unsafe {
+        ralloc::free(ptr, SIZE);
+    }
+
+    ralloc::report_leaks(report);
+    assert_eq!(LEAKS.load(Ordering::SeqCst), 1);
+}
+
+#[cfg(feature = "log")]
+static WARNED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(feature = "log")]
+fn log_handler(s: &str) -> usize {
+    if s.contains("bytes outstanding") {
+        WARNED.store(true, Ordering::SeqCst);
+    }
+
+    s.len()
+}
+
+#[test]
+#[cfg(feature = "log")]
+fn thread_exit_warning() {
+    ralloc::set_log_handler(log_handler);
+
+    thread::spawn(|| {
+        // Leak a buffer.
+        ralloc::alloc(SIZE, 8);
+    }).join()
+        .unwrap();
+
+    assert!(WARNED.load(Ordering::SeqCst));
+}
@@ -0,0 +1,138 @@
+#![cfg(feature = "track_live")]
+
+extern crate ralloc;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+mod util;
+
+use std::sync::{Arc, Barrier};
+use std::thread;
+
+#[test]
+fn is_live() {
+    util::multiply(|| {
+        let ptr = ralloc::alloc(30, 3);
+        assert!(ralloc::is_live(ptr));
+
+        unsafe {
+            ralloc::free(ptr, 30);
+        }
+        assert!(!ralloc::is_live(ptr));
+    });
+}
+
+#[test]
+// Canaries are only placed after whole allocations.
+#[cfg(not(feature = "canary"))]
+fn is_live_partial_free() {
+    util::multiply(|| {
+        let ptr = ralloc::alloc(200, 4);
+
+        unsafe {
+            // Free the head, making the tail the live allocation.
+            ralloc::free(ptr, 100);
+            assert!(!ralloc::is_live(ptr));
+            assert!(ralloc::is_live(ptr.offset(100)));
+
+            ralloc::free(ptr.offset(100), 100);
+            assert!(!ralloc::is_live(ptr.offset(100)));
+        }
+    });
+}
+
+/// The size of the canary following every allocation.
+const CANARY_SIZE: usize = if cfg!(feature = "canary") { 4 } else { 0 };
+
+#[test]
+fn allocation_size() {
+    util::multiply(|| {
+        let ptr = ralloc::alloc(50, 8);
+        assert_eq!(ralloc::allocation_size(ptr), Some(50 + CANARY_SIZE));
+
+        unsafe {
+            // Canaries are only placed after whole allocations.
+            if cfg!(feature = "canary") {
+                ralloc::free(ptr, 50);
+            } else {
+                // Free the tail.
+                ralloc::free(ptr.offset(20), 30);
+                assert_eq!(ralloc::allocation_size(ptr), Some(20));
+
+                ralloc::free(ptr, 20);
+            }
+        }
+        assert_eq!(ralloc::allocation_size(ptr), None);
+    });
+}
+
+#[test]
+// The canary rules out excess.
+#[cfg(not(feature = "canary"))]
+fn allocation_size_excess() {
+    // Other tests might take the hole, so it is tried a few times.
+    let excess = (0..100).any(|_| unsafe {
+        // Leave a hole, which is too small to split after allocating 80 bytes.
+        let hole = ralloc::alloc(96, 8);
+        let guard = ralloc::alloc(8, 8);
+        ralloc::free(hole, 96);
+
+        let (ptr, size) = ralloc::alloc_excess(80, 8);
+        let res = ralloc::allocation_size(ptr);
+
+        ralloc::free(ptr, size);
+        ralloc::free(guard, 8);
+
+        // The size of the block includes the excess.
+        assert_eq!(res, Some(size));
+        size > 80
+    });
+
+    assert!(excess);
+}
+
+#[test]
+fn free_interior() {
+    util::multiply(|| {
+        let ptr = ralloc::alloc(64, 8);
+
+        unsafe {
+            assert!(ralloc::free_interior(ptr.offset(40)));
+        }
+
+        // The whole allocation was freed, not just the part after the pointer.
+        assert!(!ralloc::is_live(ptr));
+        assert_eq!(ralloc::allocation_size(ptr), None);
+        unsafe {
+            assert!(!ralloc::free_interior(ptr));
+            assert!(!ralloc::free_interior(ptr.offset(63)));
+        }
+    });
+}
+
+#[test]
+fn free_interior_race() {
+    for _ in 0..100 {
+        let ptr = ralloc::alloc(64, 8) as usize;
+        let barrier = Arc::new(Barrier::new(2));
+
+        let threads: Vec<_> = (0..2)
+            .map(|i| {
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    barrier.wait();
+                    unsafe { ralloc::free_interior((ptr + 8 * i) as *mut u8) }
+                })
+            })
+            .collect();
+
+        // Exactly one of the threads frees the allocation.
+        let freed = threads
+            .into_iter()
+            .map(|x| x.join().unwrap())
+            .filter(|&x| x)
+            .count();
+        assert_eq!(freed, 1);
+    }
+}
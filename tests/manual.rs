@@ -5,10 +5,18 @@ static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
 
 mod util;
 
-use std::ptr;
+use std::{env, ptr};
 
 #[test]
 fn manual() {
+    // This frees a part of an allocation, and canaries are only placed after whole allocations,
+    // so it aborts.
+    if cfg!(feature = "canary") && env::var_os("RALLOC_MANUAL").is_none() {
+        util::run_child("manual", "RALLOC_MANUAL");
+
+        return;
+    }
+
     util::multiply(|| {
         let ptr1 = ralloc::alloc(30, 3);
         let ptr2 = ralloc::alloc(500, 20);
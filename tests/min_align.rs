@@ -0,0 +1,31 @@
+extern crate ralloc;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+mod util;
+
+/// The default minimum alignment (`config::MIN_ALIGN`).
+const MIN_ALIGN: usize = 16;
+
+#[test]
+fn min_align() {
+    util::multiply(|| {
+        for &align in &[1, 2, 3, 8, 24] {
+            let bufs: Vec<_> = (1..20)
+                .map(|size| (ralloc::alloc(size, align), size))
+                .chain((1..20).map(|size| (ralloc::try_alloc(size, align).unwrap(), size)))
+                .collect();
+
+            for (ptr, size) in bufs {
+                // The alignment is raised to a multiple of both.
+                assert_eq!(ptr as usize % MIN_ALIGN, 0);
+                assert_eq!(ptr as usize % align, 0);
+
+                unsafe {
+                    ralloc::free(ptr, size);
+                }
+            }
+        }
+    });
+}
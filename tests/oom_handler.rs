@@ -0,0 +1,58 @@
+extern crate ralloc;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+mod util;
+
+use std::env;
+use std::io::{self, Write};
+use std::process;
+use std::usize;
+
+#[test]
+fn oom_handler_with() {
+    if env::var_os("RALLOC_OOM_HANDLER_WITH").is_some() {
+        // The state is allocated up front.
+        let msg = String::from("closure handler called");
+        let handler = Box::leak(Box::new(move || {
+            let _ = io::stderr().write_all(msg.as_bytes());
+        }));
+        ralloc::set_oom_handler_with(handler);
+
+        ralloc::alloc(usize::MAX - 16, 1);
+
+        return;
+    }
+
+    // The OOM handlers end up aborting the process, so they are run in a child process.
+    let stderr = util::run_child("oom_handler_with", "RALLOC_OOM_HANDLER_WITH");
+    assert!(stderr.contains("closure handler called"));
+    // The handler returned, so the default handler was called after it.
+    assert!(stderr.contains("ran out of memory"));
+}
+
+#[test]
+fn oom_handler_replaced() {
+    if env::var_os("RALLOC_OOM_HANDLER_REPLACED").is_some() {
+        fn handler() -> ! {
+            let _ = io::stderr().write_all(b"fn handler called");
+            process::abort();
+        }
+
+        let handler_with = Box::leak(Box::new(|| {
+            let _ = io::stderr().write_all(b"closure handler called");
+        }));
+        ralloc::set_oom_handler_with(handler_with);
+        // The last handler set wins.
+        ralloc::set_oom_handler(handler);
+
+        ralloc::alloc(usize::MAX - 16, 1);
+
+        return;
+    }
+
+    let stderr = util::run_child("oom_handler_replaced", "RALLOC_OOM_HANDLER_REPLACED");
+    assert!(stderr.contains("fn handler called"));
+    assert!(!stderr.contains("closure handler called"));
+}
@@ -5,10 +5,17 @@ static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
 
 mod util;
 
-use std::ptr;
+use std::{env, ptr};
 
 #[test]
 fn partial_free() {
+    // Canaries are only placed after whole allocations, so the partial frees abort.
+    if cfg!(feature = "canary") && env::var_os("RALLOC_PARTIAL_FREE").is_none() {
+        util::run_child("partial_free", "RALLOC_PARTIAL_FREE");
+
+        return;
+    }
+
     util::multiply(|| {
         let buf = ralloc::alloc(63, 3);
 
@@ -35,6 +42,13 @@ fn partial_free() {
 
 #[test]
 fn partial_free_double() {
+    // See `partial_free`.
+    if cfg!(feature = "canary") && env::var_os("RALLOC_PARTIAL_FREE_DOUBLE").is_none() {
+        util::run_child("partial_free_double", "RALLOC_PARTIAL_FREE_DOUBLE");
+
+        return;
+    }
+
     util::multiply(|| {
         let buf = ralloc::alloc(64, 4);
 
@@ -5,10 +5,17 @@ static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
 
 mod util;
 
-use std::ptr;
+use std::{env, ptr};
 
 #[test]
 fn partial_realloc() {
+    // Canaries are only placed after whole allocations, so the partial reallocation aborts.
+    if cfg!(feature = "canary") && env::var_os("RALLOC_PARTIAL_REALLOC").is_none() {
+        util::run_child("partial_realloc", "RALLOC_PARTIAL_REALLOC");
+
+        return;
+    }
+
     util::multiply(|| {
         let buf = ralloc::alloc(63, 3);
 
@@ -0,0 +1,31 @@
+#![cfg(feature = "introspection")]
+
+extern crate ralloc;
+extern crate ralloc_shim;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+use ralloc_shim::config;
+
+/// The size of the blocks, which is too small to be worthy of memtrimming on its own.
+const SIZE: usize = 2048;
+/// The number of blocks, holding more than the default maximum pool size in total.
+const BLOCKS: usize = 40000;
+
+#[test]
+fn pool_cap() {
+    let bufs: Vec<_> = (0..BLOCKS).map(|_| ralloc::alloc(SIZE, 8)).collect();
+
+    for buf in bufs {
+        unsafe {
+            ralloc::free(buf, SIZE);
+        }
+    }
+
+    // Way less than the total memory trimming limit was freed, but no shard holds more than the
+    // cap.
+    for &(bytes, _) in ralloc::shard_stats().iter() {
+        assert!(bytes <= config::MAX_POOL_FREE_BYTES);
+    }
+}
@@ -0,0 +1,48 @@
+#![cfg(feature = "protect_freed")]
+
+extern crate ralloc;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+mod util;
+
+use std::{env, ptr};
+
+/// The size of the buffers, which is big enough to be protected when freed.
+const SIZE: usize = 1 << 16;
+
+#[test]
+fn protect_freed() {
+    util::multiply(|| unsafe {
+        // Free more buffers than the quarantine holds, so they are evicted and reused.
+        for _ in 0..64 {
+            let ptr = ralloc::alloc(SIZE, 8);
+            *ptr = 1;
+            *ptr.offset(SIZE as isize - 1) = 1;
+            ralloc::free(ptr, SIZE);
+        }
+
+        // Unaligned buffers are only partially protected, leaving the partial pages usable.
+        let ptr = ralloc::alloc(SIZE + 3, 1).offset(1);
+        *ptr = 1;
+        ralloc::free(ptr.offset(-1), SIZE + 3);
+    });
+}
+
+#[test]
+fn use_after_free() {
+    if env::var_os("RALLOC_USE_AFTER_FREE").is_some() {
+        unsafe {
+            let ptr = ralloc::alloc(SIZE, 8);
+            ralloc::free(ptr, SIZE);
+            // The middle of the buffer lies on a whole page, which is protected.
+            ptr::read_volatile(ptr.offset(SIZE as isize / 2));
+        }
+
+        return;
+    }
+
+    // The use-after-free faults, so it is run in a child process.
+    util::run_child("use_after_free", "RALLOC_USE_AFTER_FREE");
+}
@@ -5,6 +5,8 @@ static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
 
 mod util;
 
+use std::ptr;
+
 #[test]
 fn realloc_vec() {
     util::multiply(|| {
@@ -41,3 +43,23 @@ fn realloc_vec_2() {
         }
     });
 }
+
+#[test]
+fn realloc_stronger_align() {
+    util::multiply(|| {
+        let buf = ralloc::alloc(30, 3);
+
+        unsafe {
+            util::acid(|| {
+                ptr::write_bytes(buf, 0x2A, 30);
+            });
+
+            let buf = ralloc::realloc(buf, 30, 60, 64);
+            assert_eq!(buf as usize % 64, 0);
+            assert_eq!(*buf, 0x2A);
+            assert_eq!(*buf.offset(29), 0x2A);
+
+            ralloc::free(buf, 60);
+        }
+    });
+}
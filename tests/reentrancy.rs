@@ -0,0 +1,30 @@
+#![cfg(all(feature = "log", debug_assertions))]
+
+extern crate ralloc;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+mod util;
+
+use std::env;
+
+/// A log handler, which allocates (and thus logs) while the log lock is held.
+fn allocating_handler(s: &str) -> usize {
+    let _buf = s.to_owned();
+
+    s.len()
+}
+
+#[test]
+fn allocating_log_handler() {
+    if env::var_os("RALLOC_REENTRANCY").is_some() {
+        ralloc::set_log_handler(allocating_handler);
+        let _ = Box::new(42);
+
+        return;
+    }
+
+    // The child must abort rather than spin forever.
+    util::run_child("allocating_log_handler", "RALLOC_REENTRANCY");
+}
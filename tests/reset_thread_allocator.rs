@@ -0,0 +1,51 @@
+#![cfg(feature = "tls")]
+
+extern crate ralloc;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+mod util;
+
+use std::thread;
+
+#[test]
+fn reset_thread_allocator() {
+    util::multiply(|| {
+        let mut bufs: Vec<_> = (0..64).map(|i| Box::new([i as u8; 24])).collect();
+
+        // Leave some free blocks behind.
+        for i in (0..64).rev().step_by(2) {
+            bufs.remove(i);
+        }
+
+        ralloc::reset_thread_allocator();
+
+        // The live allocations are untouched.
+        for (i, buf) in bufs.iter().enumerate() {
+            assert_eq!(buf[0], (2 * i) as u8);
+        }
+
+        // They can be freed to the fresh allocator.
+        drop(bufs);
+
+        // Make sure the allocator is still usable.
+        let buf = Box::new([0u8; 4096]);
+        assert_eq!(buf[4095], 0);
+        drop(buf);
+
+        ralloc::reset_thread_allocator();
+    });
+}
+
+#[test]
+fn reset_uninitialized() {
+    // Resetting a thread, which never allocated, does nothing.
+    thread::spawn(|| {
+        ralloc::reset_thread_allocator();
+
+        let buf = Box::new([1u8; 64]);
+        assert_eq!(buf[63], 1);
+    }).join()
+        .unwrap();
+}
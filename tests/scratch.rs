@@ -0,0 +1,75 @@
+extern crate ralloc;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+mod util;
+
+use std::{panic, ptr};
+
+#[test]
+fn scratch() {
+    util::multiply(|| {
+        ralloc::with_scratch(|scratch| unsafe {
+            let mut bufs = Vec::new();
+
+            for i in 1..200 {
+                let buf = scratch.alloc(i * 13, 8);
+                assert_eq!(buf as usize % 8, 0);
+                assert!(scratch.owns(buf));
+                ptr::write_bytes(buf, i as u8, i * 13);
+
+                bufs.push((buf, i));
+            }
+
+            // Free every other buffer, and grow the rest. The remaining buffers are released
+            // along with the scratch allocator.
+            for (n, &mut (ref mut buf, i)) in bufs.iter_mut().enumerate() {
+                if n % 2 == 0 {
+                    scratch.free(*buf, i * 13);
+                } else {
+                    *buf = scratch.realloc(*buf, i * 13, i * 26, 8);
+                    assert_eq!(*buf.offset(i as isize * 13 - 1), i as u8);
+                }
+            }
+
+            // Zero-sized buffers are never null.
+            assert!(!scratch.alloc(0, 0).is_null());
+            let buf = scratch.alloc(8, 8);
+            assert!(!scratch.realloc(buf, 8, 0, 0).is_null());
+
+            // Buffers of the global allocator are told apart.
+            let global = ralloc::alloc(16, 8);
+            assert!(!scratch.owns(global));
+            ralloc::free(global, 16);
+        });
+    });
+}
+
+#[test]
+fn scratch_big() {
+    ralloc::with_scratch(|scratch| unsafe {
+        // Many BRKs, and thus segments.
+        for i in 1..20 {
+            let buf = scratch.alloc(i << 16, 4096);
+            assert_eq!(buf as usize % 4096, 0);
+            ptr::write_bytes(buf, 0xFF, i << 16);
+        }
+    });
+}
+
+#[test]
+fn scratch_panic() {
+    let res = panic::catch_unwind(|| {
+        ralloc::with_scratch(|scratch| {
+            scratch.alloc(1000, 8);
+
+            panic!("Scratch closure panicked.");
+        });
+    });
+    assert!(res.is_err());
+
+    // The allocator is still usable.
+    let vec = vec![0u8; 1000];
+    assert_eq!(vec.len(), 1000);
+}
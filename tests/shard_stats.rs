@@ -0,0 +1,36 @@
+#![cfg(feature = "introspection")]
+
+extern crate ralloc;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+use std::thread;
+
+/// The size of the buffer freed to a single shard.
+const SIZE: usize = 1 << 20;
+
+#[test]
+fn shard_stats() {
+    let before = ralloc::shard_stats();
+
+    // A thread sticks to one shard, which its local allocator gives the memory back to.
+    thread::spawn(|| unsafe {
+        let buf = ralloc::alloc(SIZE, 8);
+        ralloc::free(buf, SIZE);
+    }).join()
+        .unwrap();
+
+    let after = ralloc::shard_stats();
+
+    // The memory landed on a single shard.
+    assert!(
+        before
+            .iter()
+            .zip(after.iter())
+            .any(|(&(old, _), &(new, len))| new >= old + SIZE && len > 0),
+        "{:?} -> {:?}",
+        before,
+        after
+    );
+}
@@ -0,0 +1,49 @@
+extern crate ralloc;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+mod util;
+
+use std::{ptr, thread};
+
+#[test]
+fn cross_shard_free() {
+    util::multiply(|| {
+        // Allocate on a bunch of threads (and thus shards).
+        let bufs: Vec<usize> = (0..8)
+            .map(|_| {
+                thread::spawn(|| {
+                    let buf = ralloc::alloc(1000, 8);
+                    unsafe {
+                        ptr::write_bytes(buf, 0x2A, 1000);
+                    }
+
+                    buf as usize
+                }).join()
+                    .unwrap()
+            })
+            .collect();
+
+        // Free everything on other threads.
+        let join: Vec<_> = bufs
+            .into_iter()
+            .map(|buf| {
+                thread::spawn(move || unsafe {
+                    let buf = buf as *mut u8;
+
+                    util::acid(|| {
+                        assert_eq!(*buf, 0x2A);
+                        assert_eq!(*buf.offset(999), 0x2A);
+                    });
+
+                    ralloc::free(buf, 1000);
+                })
+            })
+            .collect();
+
+        for i in join {
+            i.join().unwrap();
+        }
+    });
+}
@@ -0,0 +1,39 @@
+extern crate ralloc;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+mod util;
+
+use std::ptr;
+
+#[test]
+fn try_alloc() {
+    util::multiply(|| {
+        let buf = ralloc::try_alloc(30, 3).unwrap();
+        assert_eq!(buf as usize % 3, 0);
+
+        unsafe {
+            util::acid(|| {
+                ptr::write_bytes(buf, 0x2A, 30);
+            });
+            assert_eq!(*buf.offset(29), 0x2A);
+
+            ralloc::free(buf, 30);
+        }
+    });
+}
+
+#[test]
+fn try_alloc_oom() {
+    util::multiply(|| {
+        // This is far beyond what the program break can be extended by.
+        assert!(ralloc::try_alloc(1 << 46, 8).is_none());
+
+        // The allocator is still usable.
+        let buf = ralloc::try_alloc(30, 3).unwrap();
+        unsafe {
+            ralloc::free(buf, 30);
+        }
+    });
+}
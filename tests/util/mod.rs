@@ -1,6 +1,7 @@
 //! Test automation.
 
-use std::{mem, thread};
+use std::process::Command;
+use std::{env, mem, thread};
 
 /// Magic trait for boxed `FnOnce`s.
 ///
@@ -92,3 +93,21 @@ pub fn acid<F: FnOnce()>(func: F) {
     assert_eq!(*bx, 55);
     assert_eq!(*abc, "abc");
 }
+
+/// Run a test in a child process, and get its stderr.
+///
+/// This is for tests that abort the process (e.g. on heap corruption or OOM). The child is the
+/// test binary itself, running only `test` with the environment variable `var` set, which the
+/// test checks to run the aborting code rather than spawning the child. The child must fail.
+#[allow(dead_code)]
+pub fn run_child(test: &str, var: &str) -> String {
+    let output = Command::new(env::current_exe().unwrap())
+        .args(&["--exact", test])
+        .env(var, "1")
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
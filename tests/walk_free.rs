@@ -0,0 +1,42 @@
+#![cfg(feature = "introspection")]
+
+extern crate ralloc;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+/// The size of the freed buffer.
+const SIZE: usize = 1 << 20;
+
+/// The address of the freed buffer.
+static BUF: AtomicUsize = AtomicUsize::new(0);
+/// Was a free block covering the freed buffer found?
+static FOUND: AtomicUsize = AtomicUsize::new(0);
+
+fn visit(ptr: usize, size: usize) {
+    assert!(size > 0);
+
+    let buf = BUF.load(Ordering::SeqCst);
+    if ptr <= buf && buf + SIZE <= ptr + size {
+        FOUND.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[test]
+fn walk_free() {
+    // The local allocator of the thread gives the memory back to the global allocator when the
+    // thread exits.
+    thread::spawn(|| unsafe {
+        let buf = ralloc::alloc(SIZE, 8);
+        BUF.store(buf as usize, Ordering::SeqCst);
+        ralloc::free(buf, SIZE);
+    }).join()
+        .unwrap();
+
+    ralloc::walk_free(visit);
+
+    assert_eq!(FOUND.load(Ordering::SeqCst), 1);
+}
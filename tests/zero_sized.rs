@@ -0,0 +1,57 @@
+extern crate ralloc;
+
+#[global_allocator]
+static ALLOCATOR: ralloc::Allocator = ralloc::Allocator;
+
+mod util;
+
+use std::alloc::Layout;
+
+#[test]
+fn zero_sized() {
+    util::multiply(|| unsafe {
+        let layout = Layout::from_size_align(0, 8).unwrap();
+
+        let a = ralloc::alloc_layout(layout);
+        let b = ralloc::alloc_layout(layout);
+        assert!(!a.is_null());
+        assert_eq!(a as usize % 8, 0);
+        assert_eq!(b as usize % 8, 0);
+
+        ralloc::free_layout(a, layout);
+        ralloc::free_layout(b, layout);
+        ralloc::free_layout(a, layout);
+
+        assert_eq!(ralloc::try_alloc(0, 16).unwrap() as usize % 16, 0);
+        // An alignment of zero still gives a non-null pointer.
+        assert!(!ralloc::alloc(0, 0).is_null());
+        assert!(!ralloc::realloc(ralloc::alloc(8, 8), 8, 0, 0).is_null());
+
+        // The pool is intact.
+        let buf = ralloc::alloc(64, 8);
+        *buf.offset(63) = 1;
+        ralloc::free(buf, 64);
+    });
+}
+
+#[test]
+fn zero_sized_realloc() {
+    util::multiply(|| unsafe {
+        let ptr = ralloc::alloc(0, 8);
+
+        // Grow from nothing.
+        let ptr = ralloc::realloc(ptr, 0, 100, 8);
+        *ptr.offset(99) = 7;
+        assert!(ralloc::realloc_inplace(ptr, 100, 50).is_ok());
+
+        // Shrink to nothing.
+        let ptr = ralloc::realloc(ptr, 50, 0, 8);
+        assert_eq!(ptr as usize % 8, 0);
+        assert!(ralloc::realloc_inplace(ptr, 0, 1).is_err());
+        ralloc::free(ptr, 0);
+
+        let ptr = ralloc::alloc(10, 8);
+        assert!(ralloc::realloc_inplace(ptr, 10, 0).is_ok());
+        ralloc::free(ptr, 0);
+    });
+}